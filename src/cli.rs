@@ -1,9 +1,15 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser};
 use solana_client::client_error::reqwest::Url;
 use solana_sdk::pubkey::Pubkey;
 
+use crate::{
+    journal::Operation,
+    raydium::range::{LadderRung, RangeInput, RangeWidth},
+    save::park::WorkingReserve,
+};
+
 #[derive(Debug, Parser)]
 pub struct Cli {
     #[clap(subcommand)]
@@ -35,23 +41,318 @@ pub struct Cli {
 
     #[arg(long, env)]
     pub save_program: Pubkey,
+
+    /// Save lending market whose reserves and obligations are used, required by the Save
+    /// commands, `--park`, `--from-save`, allocate and the leverage commands
+    #[arg(long, env)]
+    pub lending_market: Option<Pubkey>,
+
+    /// Highest loan to value, in percent, that a borrow may leave a Save obligation at; it must
+    /// stay below the obligation's liquidation threshold
+    #[arg(long, env, default_value_t = 60.0)]
+    pub max_ltv: f64,
+
+    /// Read and print prices as token_0 per token_1 instead of token_1 per token_0
+    #[arg(long, env, conflicts_with = "quote_mint")]
+    pub invert_price: bool,
+
+    /// Mint that prices are quoted in, prices are inverted when it is the pool's token_0
+    #[arg(long, env)]
+    pub quote_mint: Option<Pubkey>,
+
+    /// Directory for local state such as the journal of sent operations
+    #[arg(long, env, default_value = "~/.config/solana-lq-manager")]
+    pub data_dir: PathBuf,
+}
+impl Cli {
+    /// The configured lending market, for the commands that cannot run without one.
+    pub fn lending_market(&self) -> Pubkey {
+        self.lending_market
+            .expect("--lending-market is required for this command")
+    }
 }
+
 #[derive(Debug, Parser)]
 pub enum CommandsName {
     OpenPosition {
-        tick_lower_price: f64,
-        tick_upper_price: f64,
+        #[arg(
+            required_unless_present_any = ["range_pct", "range_bps", "range_ticks", "ladder"],
+            conflicts_with_all = ["range_pct", "range_bps", "range_ticks", "ladder"]
+        )]
+        tick_lower_price: Option<f64>,
+        #[arg(required_unless_present_any = ["range_pct", "range_bps", "range_ticks", "ladder"])]
+        tick_upper_price: Option<f64>,
         #[arg(short, long)]
         is_base_0: bool,
-        input_amount: u64,
+        /// Amount of the base token, token_1 unless `--is-base-0` is given
+        #[arg(long, required_unless_present_all = ["amount_0", "amount_1"])]
+        amount: Option<u64>,
+        /// Desired amount of token_0, used together with `--amount-1` instead of `--amount`
+        #[arg(long, requires = "amount_1", conflicts_with_all = ["amount", "is_base_0"])]
+        amount_0: Option<u64>,
+        /// Desired amount of token_1, used together with `--amount-0` instead of `--amount`
+        #[arg(long, requires = "amount_0", conflicts_with_all = ["amount", "is_base_0"])]
+        amount_1: Option<u64>,
+        #[command(flatten)]
+        range: RangeArgs,
+        /// Open one position per rung, `LOWER..UPPER:WEIGHT` or `PCT%:WEIGHT`, splitting the
+        /// amounts by weight
+        #[arg(long, conflicts_with_all = ["range_pct", "range_bps", "range_ticks"])]
+        ladder: Vec<LadderRung>,
+        /// Print the resolved ticks and token ratio without sending the transaction
+        #[arg(long)]
+        preview: bool,
+        /// Create metadata for the position NFT so it shows up properly in wallets
+        #[arg(long)]
+        with_metadata: bool,
+        /// Mint a legacy SPL Token NFT with Metaplex metadata instead of a Token-2022 NFT
+        #[arg(long)]
+        legacy_nft: bool,
+        /// Withdraw from Save whatever the wallet lacks of the amounts before opening
+        #[arg(long, conflicts_with = "preview")]
+        from_save: bool,
+    },
+    /// List the payer's positions in the pool with their amounts, pending fees and rewards
+    ListPositions,
+    /// Report impermanent loss and PnL of positions opened by this tool
+    Pnl,
+    /// Rebuild the liquidity and fee collection timeline of a position from its transactions
+    PositionTimeline { position_nft: Pubkey },
+    /// Print journaled operations, oldest first
+    History {
+        #[arg(long)]
+        operation: Option<Operation>,
+        #[arg(long)]
+        pool: Option<Pubkey>,
+        /// Position NFT mint
+        #[arg(long)]
+        position: Option<Pubkey>,
+        /// Only print the latest entries
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Replay a historical price or swap series against several range widths
+    Backtest {
+        /// CSV of `timestamp,price[,amount_0_in,amount_1_in]` rows, prices in the configured
+        /// orientation
+        input: PathBuf,
+        /// Range widths to compare, in percent of the price on each side
+        #[arg(long, value_delimiter = ',', required_unless_present = "widths_ticks")]
+        widths_pct: Vec<f64>,
+        /// Range widths to compare, in multiples of tick_spacing on each side
+        #[arg(long, value_delimiter = ',')]
+        widths_ticks: Vec<u32>,
+        /// Starting capital in raw units of the quote token
+        #[arg(long)]
+        capital: u64,
+        /// Re-center the range once the price has been out of it this many seconds
+        #[arg(long)]
+        rebalance_after: Option<u64>,
+        /// Network fee paid per transaction
+        #[arg(long, default_value_t = 5000)]
+        tx_fee_lamports: u64,
+        /// Liquidity of the rest of the pool, the pool's current liquidity when omitted
+        #[arg(long)]
+        pool_liquidity: Option<u128>,
+    },
+    /// Write every journaled token movement as CSV rows
+    Export {
+        /// CSV file to write, stdout when omitted
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Send a position NFT to another wallet or vault
+    TransferPosition {
+        position_nft: Pubkey,
+        /// Owner of the destination token account
+        destination: Pubkey,
+    },
+    /// Close empty positions and token accounts to reclaim rent, keeping the accounts of the
+    /// configured pool, of pools with open positions and of the Save reserves
+    Cleanup {
+        /// List the accounts that would be closed without sending anything
+        #[arg(long)]
+        dry_run: bool,
     },
     ClosePosition {
-        tick_lower_index: f64,
-        tick_upper_index: f64,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        tick_lower_index: Option<f64>,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        tick_upper_index: Option<f64>,
+        /// Close every position of the payer, in all pools unless `--pool` is given
+        #[arg(long)]
+        all: bool,
+        /// Only close positions in this pool
+        #[arg(long, requires = "all")]
+        pool: Option<Pubkey>,
+        /// Deposit the received tokens into their Save reserves afterwards
+        #[arg(long)]
+        park: bool,
+        /// With `--park`, deposit the whole balance of MINT above AMOUNT instead of only what
+        /// was received, as `MINT:AMOUNT`
+        #[arg(long, requires = "park")]
+        working_reserve: Vec<WorkingReserve>,
     },
 
+    /// Print the Save lending market's reserves with their rates, utilization and config
+    Reserves,
+    /// Print the payer's Save obligations with their collateral, borrows and health
+    Obligations,
+    /// Borrow from a Save reserve against the payer's obligation
+    Borrow {
+        /// Mint of the liquidity to borrow
+        mint: Pubkey,
+        amount: u64,
+    },
+    /// Pay back liquidity borrowed from a Save reserve
+    Repay {
+        /// Mint of the borrowed liquidity
+        mint: Pubkey,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        amount: Option<u64>,
+        /// Repay the whole debt, or as much of it as the wallet holds
+        #[arg(long)]
+        all: bool,
+    },
+    /// Supply liquidity to a Save reserve, keeping the cTokens or depositing them as collateral
     Deposit {
+        /// Mint of the liquidity to supply
+        mint: Pubkey,
         input_amount: u64,
+        /// Deposit the minted cTokens into the payer's obligation, created when missing
+        #[arg(long)]
+        collateral: bool,
+    },
+    /// Move cTokens from the wallet into the payer's Save obligation
+    DepositCollateral {
+        /// Mint of the reserve's liquidity
+        mint: Pubkey,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        collateral_amount: Option<u64>,
+        /// Move every cToken of the reserve the wallet holds
+        #[arg(long)]
+        all: bool,
+    },
+    /// Move cTokens out of the payer's Save obligation into the wallet
+    WithdrawCollateral {
+        /// Mint of the reserve's liquidity
+        mint: Pubkey,
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        collateral_amount: Option<u64>,
+        /// Move every cToken of the reserve the obligation holds
+        #[arg(long)]
+        all: bool,
+    },
+    /// Redeem cTokens of a Save reserve for liquidity, from the wallet or the obligation
+    Withdraw {
+        /// Mint of the liquidity to withdraw
+        mint: Pubkey,
+        /// Underlying amount to receive, converted to cTokens at the reserve's exchange rate
+        #[arg(
+            required_unless_present_any = ["ctokens", "all"],
+            conflicts_with_all = ["ctokens", "all"]
+        )]
+        amount: Option<u64>,
+        /// Exact amount of cTokens to redeem
+        #[arg(long, conflicts_with = "all")]
+        ctokens: Option<u64>,
+        /// Redeem every cToken held
+        #[arg(long)]
+        all: bool,
+        /// Take the cTokens out of the payer's obligation instead of the wallet
+        #[arg(long)]
+        collateral: bool,
+    },
+    /// Check the payer's Save obligation every few slots and repay it before liquidation
+    WatchObligation {
+        /// Slots between checks
+        #[arg(long, default_value_t = 150)]
+        interval_slots: u64,
+        /// LTV, in percent, at which to warn and repay, above --max-ltv
+        #[arg(long, default_value_t = 70.0)]
+        warn_ltv: f64,
+        /// Repay borrows from the wallet down to --max-ltv once the LTV crosses --warn-ltv
+        #[arg(long)]
+        auto_repay: bool,
+        /// Close the positions in pools holding a borrowed mint when the wallet lacks it
+        #[arg(long, requires = "auto_repay")]
+        withdraw_lp: bool,
+    },
+    /// Deposit one pool token as Save collateral, borrow the other and open a position with both
+    LeverageOpen {
+        /// Pool mint deposited as collateral, the other one is borrowed
+        collateral_mint: Pubkey,
+        collateral_amount: u64,
+        borrow_amount: u64,
+        /// Amount of the collateral mint from the wallet paired with the borrowed tokens in the
+        /// position
+        #[arg(long)]
+        pair_amount: u64,
+        #[command(flatten)]
+        range: RangeArgs,
     },
-    Withdraw,
+    /// Close a leveraged position, repay the borrow and withdraw the collateral
+    LeverageClose {
+        /// Pool mint deposited as collateral when the position was opened
+        collateral_mint: Pubkey,
+        tick_lower_price: f64,
+        tick_upper_price: f64,
+    },
+    /// Move the pool's capital between a position and Save deposits, whichever yields more
+    Allocate {
+        /// Distance of each bound of the position from the current price, in percent
+        #[arg(long)]
+        range_pct: f64,
+        /// Yield spread, in percentage points, needed before capital moves
+        #[arg(long, default_value_t = 1.0)]
+        threshold_pct: f64,
+        /// Seconds capital stays where it was last moved before it may move again
+        #[arg(long, default_value_t = 86400)]
+        min_hold_secs: u64,
+        /// Least number of seconds of fee history the fee APR is estimated from
+        #[arg(long, default_value_t = 3600)]
+        fee_window_secs: u64,
+        /// Print the decision without moving anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Range around the current price, used instead of absolute lower and upper prices.
+#[derive(Debug, Args)]
+pub struct RangeArgs {
+    /// Distance of each bound from the current price, in percent
+    #[arg(long, group = "relative_range")]
+    pub range_pct: Option<f64>,
+    /// Distance of each bound from the current price, in basis points
+    #[arg(long, group = "relative_range")]
+    pub range_bps: Option<f64>,
+    /// Distance of each bound from the current tick, in multiples of tick_spacing
+    #[arg(long, group = "relative_range")]
+    pub range_ticks: Option<u32>,
+    /// Shift of the range from -1 (entirely below the price) to 1 (entirely above)
+    #[arg(
+        long,
+        default_value_t = 0.0,
+        allow_negative_numbers = true,
+        requires = "relative_range"
+    )]
+    pub skew: f64,
+}
+
+impl RangeArgs {
+    /// The range the flags describe, with the skew flipped when prices are inverted, or `None`
+    /// when no width is given.
+    pub fn around_current(&self, invert_price: bool) -> Option<RangeInput> {
+        let width = match (self.range_pct, self.range_bps, self.range_ticks) {
+            (Some(percent), _, _) => RangeWidth::Percent(percent),
+            (_, Some(bps), _) => RangeWidth::Percent(bps / 100.0),
+            (_, _, Some(ticks)) => RangeWidth::Ticks(ticks),
+            _ => return None,
+        };
+        Some(RangeInput::AroundCurrent {
+            width,
+            skew: if invert_price { -self.skew } else { self.skew },
+        })
+    }
 }
@@ -0,0 +1,402 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use raydium_amm_v3::states::PoolState;
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+use crate::raydium::utils::{sqrt_price_x64_to_price, try_get_chain_timestamp};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+/// Entry amounts of opened positions, written by the pnl report before the journal existed.
+const LEGACY_POSITION_ENTRIES_FILE: &str = "positions.jsonl";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    OpenPosition,
+    ClosePosition,
+    /// Part of a position's liquidity withdrawn, the position staying open.
+    DecreaseLiquidity,
+    TransferPosition,
+    Borrow,
+    Repay,
+    Deposit,
+    Withdraw,
+    DepositCollateral,
+    WithdrawCollateral,
+}
+
+/// Direction and purpose of a token movement, seen from the wallet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MovementKind {
+    /// Tokens leaving the wallet into a pool or reserve.
+    Deposit,
+    /// Principal returned to the wallet from a pool or reserve.
+    Withdraw,
+    /// Trading fees collected into the wallet.
+    Fee,
+    /// Rewards collected into the wallet.
+    Reward,
+    /// Token-2022 transfer fees withheld from the other movements of the entry, which are
+    /// journaled gross, so the movements of an entry add up to the wallet's balance change.
+    TransferFee,
+    /// Liquidity borrowed into the wallet.
+    Borrow,
+    /// Borrowed liquidity paid back from the wallet.
+    Repay,
+}
+
+impl MovementKind {
+    /// Whether the tokens left the wallet.
+    pub fn is_outflow(&self) -> bool {
+        matches!(
+            self,
+            MovementKind::Deposit | MovementKind::TransferFee | MovementKind::Repay
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenMovement {
+    pub mint: String,
+    pub kind: MovementKind,
+    pub amount: u64,
+    pub decimals: u8,
+}
+
+/// One confirmed transaction sent by the tool.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: u64,
+    pub signature: String,
+    pub slot: u64,
+    pub operation: Operation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position_nft: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick_lower_index: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick_upper_index: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<u128>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sqrt_price_x64: Option<u128>,
+    /// Owner a position NFT was sent to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    /// Save reserve the tokens were lent to or borrowed from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reserve: Option<String>,
+    /// Save obligation holding the collateral or debt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obligation: Option<String>,
+    /// cTokens minted, redeemed, or moved in or out of the obligation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collateral_amount: Option<u64>,
+    /// Pool price as token_1 per token_0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    /// Why the action failed, on entries journaled without a confirmed transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub movements: Vec<TokenMovement>,
+}
+
+/// Seconds since the Unix epoch by the local clock.
+fn local_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl JournalEntry {
+    /// Starts an entry for a confirmed transaction, reading its slot and the cluster time. The
+    /// transaction is journaled even when those reads fail, with no slot and the local time.
+    pub fn new(rpc_client: &RpcClient, signature: &Signature, operation: Operation) -> Self {
+        let slot = rpc_client
+            .get_signature_statuses(&[*signature])
+            .ok()
+            .and_then(|statuses| statuses.value[0].as_ref().map(|status| status.slot))
+            .unwrap_or_default();
+        let timestamp = try_get_chain_timestamp(rpc_client).unwrap_or_else(|_| local_timestamp());
+        Self::empty(timestamp, signature.to_string(), slot, operation)
+    }
+
+    /// Starts an entry for an action that failed before any transaction confirmed, at `slot` and
+    /// the local time.
+    pub fn failed(slot: u64, operation: Operation, error: String) -> Self {
+        let mut entry = Self::empty(local_timestamp(), String::new(), slot, operation);
+        entry.error = Some(error);
+        entry
+    }
+
+    fn empty(timestamp: u64, signature: String, slot: u64, operation: Operation) -> Self {
+        JournalEntry {
+            timestamp,
+            signature,
+            slot,
+            operation,
+            pool: None,
+            position_nft: None,
+            tick_lower_index: None,
+            tick_upper_index: None,
+            liquidity: None,
+            sqrt_price_x64: None,
+            destination: None,
+            reserve: None,
+            obligation: None,
+            collateral_amount: None,
+            price: None,
+            error: None,
+            movements: Vec::new(),
+        }
+    }
+
+    pub fn push_movement(&mut self, mint: &Pubkey, kind: MovementKind, amount: u64, decimals: u8) {
+        if amount > 0 {
+            self.movements.push(TokenMovement {
+                mint: mint.to_string(),
+                kind,
+                amount,
+                decimals,
+            });
+        }
+    }
+
+    /// Total amount of `mint` moved with the given kind.
+    pub fn movement_amount(&self, mint: &Pubkey, kind: MovementKind) -> u64 {
+        let mint = mint.to_string();
+        self.movements
+            .iter()
+            .filter(|movement| movement.mint == mint && movement.kind == kind)
+            .map(|movement| movement.amount)
+            .sum()
+    }
+}
+
+pub fn append_journal_entry(data_dir: &Path, entry: &JournalEntry) {
+    std::fs::create_dir_all(data_dir).unwrap();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir.join(JOURNAL_FILE))
+        .unwrap();
+    writeln!(file, "{}", serde_json::to_string(entry).unwrap()).unwrap();
+}
+
+pub fn load_journal(data_dir: &Path) -> Vec<JournalEntry> {
+    match File::open(data_dir.join(JOURNAL_FILE)) {
+        Err(_) => Vec::new(),
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect(),
+    }
+}
+
+#[derive(Deserialize)]
+struct LegacyPositionEntry {
+    nft_mint: String,
+    pool_id: String,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    liquidity: u128,
+    amount_0: u64,
+    amount_1: u64,
+    sqrt_price_x64: u128,
+    timestamp: u64,
+    signature: String,
+}
+
+/// Appends the legacy position entries of `pool_id` that are missing from the journal as open
+/// position entries, so positions opened before the journal keep their entry amounts. They
+/// carry no slot and no transfer fee. Returns how many were imported.
+pub fn import_legacy_position_entries(
+    data_dir: &Path,
+    pool_id: &Pubkey,
+    pool: &PoolState,
+) -> usize {
+    let Ok(file) = File::open(data_dir.join(LEGACY_POSITION_ENTRIES_FILE)) else {
+        return 0;
+    };
+    let pool_id = pool_id.to_string();
+    let journal = load_journal(data_dir);
+    let mut imported = 0;
+    for line in BufReader::new(file).lines() {
+        let legacy: LegacyPositionEntry = serde_json::from_str(&line.unwrap()).unwrap();
+        if legacy.pool_id != pool_id
+            || journal.iter().any(|entry| {
+                entry.operation == Operation::OpenPosition
+                    && entry.position_nft.as_ref() == Some(&legacy.nft_mint)
+            })
+        {
+            continue;
+        }
+        let mut entry = JournalEntry {
+            timestamp: legacy.timestamp,
+            signature: legacy.signature,
+            slot: 0,
+            operation: Operation::OpenPosition,
+            pool: Some(legacy.pool_id),
+            position_nft: Some(legacy.nft_mint),
+            tick_lower_index: Some(legacy.tick_lower_index),
+            tick_upper_index: Some(legacy.tick_upper_index),
+            liquidity: Some(legacy.liquidity),
+            sqrt_price_x64: Some(legacy.sqrt_price_x64),
+            destination: None,
+            reserve: None,
+            obligation: None,
+            collateral_amount: None,
+            price: Some(sqrt_price_x64_to_price(
+                legacy.sqrt_price_x64,
+                pool.mint_decimals_0,
+                pool.mint_decimals_1,
+            )),
+            error: None,
+            movements: Vec::new(),
+        };
+        entry.push_movement(
+            &pool.token_mint_0,
+            MovementKind::Deposit,
+            legacy.amount_0,
+            pool.mint_decimals_0,
+        );
+        entry.push_movement(
+            &pool.token_mint_1,
+            MovementKind::Deposit,
+            legacy.amount_1,
+            pool.mint_decimals_1,
+        );
+        append_journal_entry(data_dir, &entry);
+        imported += 1;
+    }
+    imported
+}
+
+/// Prints journal entries, oldest first, matching every given filter.
+pub fn print_history(
+    data_dir: &Path,
+    operation: Option<Operation>,
+    pool: Option<Pubkey>,
+    position_nft: Option<Pubkey>,
+    limit: Option<usize>,
+) {
+    let pool = pool.map(|pool| pool.to_string());
+    let position_nft = position_nft.map(|nft| nft.to_string());
+    let entries: Vec<JournalEntry> = load_journal(data_dir)
+        .into_iter()
+        .filter(|entry| operation.is_none_or(|operation| entry.operation == operation))
+        .filter(|entry| pool.is_none() || entry.pool == pool)
+        .filter(|entry| position_nft.is_none() || entry.position_nft == position_nft)
+        .collect();
+    let skip = limit.map_or(0, |limit| entries.len().saturating_sub(limit));
+    for entry in entries.iter().skip(skip) {
+        println!(
+            "{} slot:{} {:?} signature:{}",
+            entry.timestamp, entry.slot, entry.operation, entry.signature
+        );
+        if let Some(pool) = &entry.pool {
+            println!("    pool:{}", pool);
+        }
+        if let Some(position_nft) = &entry.position_nft {
+            println!("    position nft:{}", position_nft);
+        }
+        if let (Some(tick_lower_index), Some(tick_upper_index)) =
+            (entry.tick_lower_index, entry.tick_upper_index)
+        {
+            println!(
+                "    tick_lower_index:{}, tick_upper_index:{}",
+                tick_lower_index, tick_upper_index
+            );
+        }
+        if let Some(destination) = &entry.destination {
+            println!("    destination:{}", destination);
+        }
+        if let Some(reserve) = &entry.reserve {
+            println!("    reserve:{}", reserve);
+        }
+        if let Some(obligation) = &entry.obligation {
+            println!("    obligation:{}", obligation);
+        }
+        if let Some(collateral_amount) = entry.collateral_amount {
+            println!("    ctokens:{}", collateral_amount);
+        }
+        if let Some(liquidity) = entry.liquidity {
+            println!("    liquidity:{}", liquidity);
+        }
+        if let Some(price) = entry.price {
+            println!("    price:{}", price);
+        }
+        if let Some(error) = &entry.error {
+            println!("    failed: {}", error);
+        }
+        for movement in &entry.movements {
+            println!(
+                "    {:?} mint:{}, amount:{}, decimals:{}",
+                movement.kind, movement.mint, movement.amount, movement.decimals
+            );
+        }
+    }
+}
+
+/// Formats a raw token amount with its decimal point, without going through floats.
+fn ui_amount(amount: u64, decimals: u8) -> String {
+    if decimals == 0 {
+        return amount.to_string();
+    }
+    let digits = format!("{:0>width$}", amount, width = decimals as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals as usize);
+    format!("{}.{}", whole, fraction)
+}
+
+/// Writes one CSV row per journaled token movement to `output`, or stdout when it is `None`.
+pub fn export_csv(data_dir: &Path, output: Option<&Path>) {
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).unwrap()),
+        None => Box::new(io::stdout().lock()),
+    };
+    writeln!(
+        writer,
+        "timestamp,slot,signature,operation,pool,position_nft,kind,direction,mint,amount,decimals,ui_amount"
+    )
+    .unwrap();
+    for entry in load_journal(data_dir) {
+        let operation = entry.operation.to_possible_value().unwrap();
+        for movement in &entry.movements {
+            let kind = serde_json::to_value(movement.kind).unwrap();
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{}",
+                entry.timestamp,
+                entry.slot,
+                entry.signature,
+                operation.get_name(),
+                entry.pool.as_deref().unwrap_or_default(),
+                entry.position_nft.as_deref().unwrap_or_default(),
+                kind.as_str().unwrap(),
+                if movement.kind.is_outflow() {
+                    "out"
+                } else {
+                    "in"
+                },
+                movement.mint,
+                movement.amount,
+                movement.decimals,
+                ui_amount(movement.amount, movement.decimals)
+            )
+            .unwrap();
+        }
+    }
+}
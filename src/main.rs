@@ -6,7 +6,9 @@ use std::{
 
 use anchor_client::{Client, Cluster};
 use clap::Parser;
-use solana_client::{rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_client::{
+    client_error::ClientError, rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig,
+};
 use solana_sdk::{
     commitment_config::CommitmentConfig,
     signature::{Keypair, Signature},
@@ -15,6 +17,7 @@ use solana_sdk::{
 
 pub mod cli;
 pub mod error;
+pub mod journal;
 pub mod raydium;
 pub mod save;
 
@@ -30,12 +33,45 @@ fn main() {
     let anchor_client = Client::new(url, Rc::new(read_keypair_file(&args.payer_path)));
 
     match args.command {
-        cli::CommandsName::OpenPosition { .. } | cli::CommandsName::ClosePosition { .. } => {
+        cli::CommandsName::OpenPosition { .. }
+        | cli::CommandsName::ListPositions
+        | cli::CommandsName::Pnl
+        | cli::CommandsName::PositionTimeline { .. }
+        | cli::CommandsName::Backtest { .. }
+        | cli::CommandsName::TransferPosition { .. }
+        | cli::CommandsName::Cleanup { .. }
+        | cli::CommandsName::ClosePosition { .. }
+        | cli::CommandsName::Allocate { .. }
+        | cli::CommandsName::LeverageOpen { .. }
+        | cli::CommandsName::LeverageClose { .. } => {
             raydium::run(&args, rpc_client, anchor_client, payer)
         }
-        cli::CommandsName::Deposit { .. } | cli::CommandsName::Withdraw => {
+        cli::CommandsName::Reserves
+        | cli::CommandsName::Obligations
+        | cli::CommandsName::Borrow { .. }
+        | cli::CommandsName::Repay { .. }
+        | cli::CommandsName::Deposit { .. }
+        | cli::CommandsName::DepositCollateral { .. }
+        | cli::CommandsName::WithdrawCollateral { .. }
+        | cli::CommandsName::Withdraw { .. }
+        | cli::CommandsName::WatchObligation { .. } => {
             save::run(&args, rpc_client, anchor_client, payer);
         }
+        cli::CommandsName::Export { ref output } => {
+            journal::export_csv(&expand_home_dir(&args.data_dir), output.as_deref())
+        }
+        cli::CommandsName::History {
+            operation,
+            pool,
+            position,
+            limit,
+        } => journal::print_history(
+            &expand_home_dir(&args.data_dir),
+            operation,
+            pool,
+            position,
+            limit,
+        ),
     }
 }
 
@@ -57,18 +93,25 @@ fn read_keypair_file(path: &Path) -> Keypair {
 }
 
 pub fn send_txn(client: &RpcClient, txn: &Transaction, wait_confirm: bool) -> Signature {
-    client
-        .send_and_confirm_transaction_with_spinner_and_config(
-            txn,
-            if wait_confirm {
-                CommitmentConfig::confirmed()
-            } else {
-                CommitmentConfig::processed()
-            },
-            RpcSendTransactionConfig {
-                skip_preflight: true,
-                ..RpcSendTransactionConfig::default()
-            },
-        )
-        .unwrap()
+    try_send_txn(client, txn, wait_confirm).unwrap()
+}
+
+/// Like `send_txn`, but hands a failed send or transaction back to the caller.
+pub fn try_send_txn(
+    client: &RpcClient,
+    txn: &Transaction,
+    wait_confirm: bool,
+) -> Result<Signature, ClientError> {
+    client.send_and_confirm_transaction_with_spinner_and_config(
+        txn,
+        if wait_confirm {
+            CommitmentConfig::confirmed()
+        } else {
+            CommitmentConfig::processed()
+        },
+        RpcSendTransactionConfig {
+            skip_preflight: true,
+            ..RpcSendTransactionConfig::default()
+        },
+    )
 }
@@ -0,0 +1,491 @@
+//! Moves the pool's capital between the CLMM position and Save lending, whichever currently
+//! yields more.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use anchor_client::{Client, Program};
+use raydium_amm_v3::{libraries::liquidity_math, states::PoolState};
+use serde::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::{
+    journal::{JournalEntry, Operation, load_journal},
+    save::{
+        oracle::refresh_market_prices,
+        park::{ParkConfig, park_idle_tokens},
+        state::{Reserve, get_reserves, rate_to_apy},
+        withdraw::{WithdrawAmount, withdraw},
+    },
+};
+
+use super::{
+    close_all::close_all,
+    open::{LiquidityInput, open},
+    pnl::quote_value,
+    position::get_all_personal_positions_by_owner,
+    range::{RangeInput, RangeWidth},
+    utils::{get_chain_timestamp, get_token_account_balances, sqrt_price_x64_to_price},
+};
+
+const SNAPSHOT_FILE: &str = "pool_snapshots.jsonl";
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0;
+/// Liquidity of the hypothetical position the fee APR is measured on, large enough for the
+/// amounts to keep their precision.
+const REFERENCE_LIQUIDITY: u128 = 1_000_000_000_000;
+
+/// Fee growth and tick of a pool at one point in time, recorded on every run to measure recent
+/// fees.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct PoolSnapshot {
+    timestamp: u64,
+    pool: String,
+    fee_growth_global_0_x64: u128,
+    fee_growth_global_1_x64: u128,
+    /// Absent from snapshots recorded before the tick was
+    #[serde(default)]
+    tick_current: Option<i32>,
+}
+
+/// When capital moves between the pool and Save.
+pub struct AllocateConfig {
+    /// Width of the target range around the current price, in percent
+    pub range_pct: f64,
+    /// Yield spread, in percentage points, needed to move
+    pub threshold_pct: f64,
+    /// Seconds capital stays where it was last moved before it may move again
+    pub min_hold_secs: u64,
+    /// Least age of the fee growth snapshot the fee APR is measured from
+    pub fee_window_secs: u64,
+    /// Report the decision without moving anything
+    pub dry_run: bool,
+}
+
+fn append_snapshot(data_dir: &Path, snapshot: &PoolSnapshot) {
+    std::fs::create_dir_all(data_dir).unwrap();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(data_dir.join(SNAPSHOT_FILE))
+        .unwrap();
+    writeln!(file, "{}", serde_json::to_string(snapshot).unwrap()).unwrap();
+}
+
+fn load_snapshots(data_dir: &Path) -> Vec<PoolSnapshot> {
+    match File::open(data_dir.join(SNAPSHOT_FILE)) {
+        Err(_) => Vec::new(),
+        Ok(file) => BufReader::new(file)
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect(),
+    }
+}
+
+/// Token amounts of a position of `REFERENCE_LIQUIDITY` in the range at the current price.
+fn reference_amounts(pool: &PoolState, tick_lower_index: i32, tick_upper_index: i32) -> (u64, u64) {
+    liquidity_math::get_delta_amounts_signed(
+        pool.tick_current,
+        pool.sqrt_price_x64,
+        tick_lower_index,
+        tick_upper_index,
+        -(REFERENCE_LIQUIDITY as i128),
+    )
+    .unwrap()
+}
+
+/// Share of the time since `base` the pool's tick sat in `tick_lower..tick_upper`, each interval
+/// between two snapshots counted by the tick at its start. `None` when no snapshot of the window
+/// recorded its tick.
+fn in_range_share(
+    snapshots: &[PoolSnapshot],
+    base: &PoolSnapshot,
+    now: u64,
+    tick_current: i32,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+) -> Option<f64> {
+    let mut samples: Vec<(u64, Option<i32>)> = snapshots
+        .iter()
+        .filter(|snapshot| snapshot.timestamp >= base.timestamp && snapshot.timestamp < now)
+        .map(|snapshot| (snapshot.timestamp, snapshot.tick_current))
+        .collect();
+    samples.push((now, Some(tick_current)));
+    let (mut in_range, mut sampled) = (0, 0);
+    for pair in samples.windows(2) {
+        let (start, tick) = pair[0];
+        let Some(tick) = tick else {
+            continue;
+        };
+        let duration = pair[1].0 - start;
+        sampled += duration;
+        if (tick_lower_index..tick_upper_index).contains(&tick) {
+            in_range += duration;
+        }
+    }
+    (sampled > 0).then(|| in_range as f64 / sampled as f64)
+}
+
+/// cTokens of `reserve_key` the wallet deposited for `pool_key` and has not withdrawn for it since.
+/// Deposits as collateral stay with the obligation and are not counted.
+fn attributed_collateral(journal: &[JournalEntry], pool_key: &str, reserve_key: &str) -> u64 {
+    journal
+        .iter()
+        .filter(|entry| {
+            entry.pool.as_deref() == Some(pool_key)
+                && entry.reserve.as_deref() == Some(reserve_key)
+                && entry.obligation.is_none()
+        })
+        .fold(0u64, |attributed, entry| {
+            let collateral_amount = entry.collateral_amount.unwrap_or_default();
+            match entry.operation {
+                Operation::Deposit => attributed + collateral_amount,
+                Operation::Withdraw => attributed.saturating_sub(collateral_amount),
+                _ => attributed,
+            }
+        })
+}
+
+/// Yearly fee rate of a position in `tick_lower..tick_upper`, from the pool's fee growth since
+/// `snapshot`. The position earns the global growth while in range, so the rate is scaled by
+/// `in_range_share`, itself sampled only at the snapshots.
+fn estimate_fee_apr(
+    pool: &PoolState,
+    snapshot: &PoolSnapshot,
+    now: u64,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    in_range_share: f64,
+    invert_price: bool,
+) -> f64 {
+    let price = sqrt_price_x64_to_price(
+        pool.sqrt_price_x64,
+        pool.mint_decimals_0,
+        pool.mint_decimals_1,
+    );
+    let (amount_0, amount_1) = reference_amounts(pool, tick_lower_index, tick_upper_index);
+    let growth_to_fee =
+        |growth_x64: u128| (growth_x64 as f64 / 2f64.powi(64) * REFERENCE_LIQUIDITY as f64) as u64;
+    let fee_0 = growth_to_fee(
+        pool.fee_growth_global_0_x64
+            .wrapping_sub(snapshot.fee_growth_global_0_x64),
+    );
+    let fee_1 = growth_to_fee(
+        pool.fee_growth_global_1_x64
+            .wrapping_sub(snapshot.fee_growth_global_1_x64),
+    );
+    let value = |amount_0: u64, amount_1: u64| {
+        quote_value(
+            amount_0,
+            amount_1,
+            price,
+            pool.mint_decimals_0,
+            pool.mint_decimals_1,
+            invert_price,
+        )
+    };
+    let position_value = value(amount_0, amount_1);
+    if position_value == 0.0 || now <= snapshot.timestamp {
+        return 0.0;
+    }
+    value(fee_0, fee_1) / position_value * in_range_share * SECONDS_PER_YEAR
+        / (now - snapshot.timestamp) as f64
+}
+
+/// Compares the Save supply APY of the pool's mints with the estimated fee APR of the target
+/// range, and moves capital to the better one when the spread exceeds the threshold and the
+/// capital has stayed put long enough: closing the positions and depositing the proceeds, or
+/// withdrawing the cTokens those deposits minted, as much as the range takes, and opening a
+/// position with them. Nothing moves while an oracle of the two reserves is stale.
+pub fn allocate(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    pool_id_account: Pubkey,
+    tickarray_bitmap_extension: Pubkey,
+    save_program: Pubkey,
+    lending_market: Pubkey,
+    config: &AllocateConfig,
+    slippage: f64,
+    invert_price: bool,
+    data_dir: &Path,
+) {
+    let pool: PoolState = program.account(pool_id_account).unwrap();
+    let now = get_chain_timestamp(&rpc_client);
+    let pool_key = pool_id_account.to_string();
+    let snapshots: Vec<PoolSnapshot> = load_snapshots(data_dir)
+        .into_iter()
+        .filter(|snapshot| snapshot.pool == pool_key)
+        .collect();
+    let base_snapshot = snapshots
+        .iter()
+        .rev()
+        .find(|snapshot| now.saturating_sub(snapshot.timestamp) >= config.fee_window_secs)
+        .cloned();
+    append_snapshot(
+        data_dir,
+        &PoolSnapshot {
+            timestamp: now,
+            pool: pool_key.clone(),
+            fee_growth_global_0_x64: pool.fee_growth_global_0_x64,
+            fee_growth_global_1_x64: pool.fee_growth_global_1_x64,
+            tick_current: Some(pool.tick_current),
+        },
+    );
+    let Some(base_snapshot) = base_snapshot else {
+        println!(
+            "no fee growth snapshot of pool:{} older than {}s yet, recorded one, run again later",
+            pool_id_account, config.fee_window_secs
+        );
+        return;
+    };
+
+    let range_input = RangeInput::AroundCurrent {
+        width: RangeWidth::Percent(config.range_pct),
+        skew: 0.0,
+    };
+    let (tick_lower_index, tick_upper_index) = range_input.tick_indexes(&pool);
+    let in_range_share = in_range_share(
+        &snapshots,
+        &base_snapshot,
+        now,
+        pool.tick_current,
+        tick_lower_index,
+        tick_upper_index,
+    );
+    let lp_apr = estimate_fee_apr(
+        &pool,
+        &base_snapshot,
+        now,
+        tick_lower_index,
+        tick_upper_index,
+        in_range_share.unwrap_or(1.0),
+        invert_price,
+    );
+
+    let mut reserves = get_reserves(&rpc_client, &save_program, &lending_market);
+    let find_reserve = |reserves: &[(Pubkey, Reserve)], mint: &Pubkey| {
+        *reserves
+            .iter()
+            .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+            .expect("no reserve for the pool mint in the lending market")
+    };
+    let reserve_key_0 = find_reserve(&reserves, &pool.token_mint_0).0;
+    let reserve_key_1 = find_reserve(&reserves, &pool.token_mint_1).0;
+    // moving capital refreshes both reserves, which fails on a stale oracle anyway
+    if let Err(err) =
+        refresh_market_prices(&rpc_client, &mut reserves, &[reserve_key_0, reserve_key_1])
+    {
+        println!("no move: {}", err);
+        return;
+    }
+    let (_, reserve_0) = find_reserve(&reserves, &pool.token_mint_0);
+    let (_, reserve_1) = find_reserve(&reserves, &pool.token_mint_1);
+    let supply_apy_0 = rate_to_apy(reserve_0.supply_rate());
+    let supply_apy_1 = rate_to_apy(reserve_1.supply_rate());
+    // the capital would be split between the tokens as the target range holds them, valued at
+    // the oracle prices
+    let (amount_0, amount_1) = reference_amounts(&pool, tick_lower_index, tick_upper_index);
+    let value_0 =
+        amount_0 as f64 / 10f64.powi(pool.mint_decimals_0 as i32) * reserve_0.market_price();
+    let value_1 =
+        amount_1 as f64 / 10f64.powi(pool.mint_decimals_1 as i32) * reserve_1.market_price();
+    let share_0 = value_0 / (value_0 + value_1);
+    let save_apy = share_0 * supply_apy_0 + (1.0 - share_0) * supply_apy_1;
+    println!(
+        "range ticks {}..{}, estimated fee apr:{:.4}% over the last {}s",
+        tick_lower_index,
+        tick_upper_index,
+        lp_apr * 100.0,
+        now - base_snapshot.timestamp
+    );
+    match in_range_share {
+        Some(in_range_share) => println!(
+            "range in range for {:.2}% of the window at the recorded ticks, the price may have left it between them",
+            in_range_share * 100.0
+        ),
+        None => println!(
+            "no ticks recorded over the window, the fee apr assumes the range stayed in range and may be overestimated"
+        ),
+    }
+    println!(
+        "save supply apy mint_0:{:.4}%, mint_1:{:.4}%, blended:{:.4}%",
+        supply_apy_0 * 100.0,
+        supply_apy_1 * 100.0,
+        save_apy * 100.0
+    );
+
+    let has_positions =
+        get_all_personal_positions_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program)
+            .iter()
+            .any(|(_, position)| position.pool_id == pool_id_account && position.liquidity > 0);
+    let journal = load_journal(data_dir);
+    let reserve_keys = [reserve_key_0.to_string(), reserve_key_1.to_string()];
+    // only the cTokens parked out of this pool move back into it, and no more than the wallet holds
+    let ctoken_accounts = [
+        get_associated_token_address(&payer.pubkey(), &reserve_0.collateral_mint),
+        get_associated_token_address(&payer.pubkey(), &reserve_1.collateral_mint),
+    ];
+    let ctoken_balances = get_token_account_balances(&rpc_client, &ctoken_accounts);
+    let attributed = [0, 1].map(|index| {
+        attributed_collateral(&journal, &pool_key, &reserve_keys[index]).min(ctoken_balances[index])
+    });
+    // the range takes the tokens in the ratio of its reference amounts, so the scarcer side of
+    // the deposits sizes the position
+    let available = [
+        reserve_0.collateral_to_liquidity(attributed[0]),
+        reserve_1.collateral_to_liquidity(attributed[1]),
+    ];
+    let scale = [(amount_0, available[0]), (amount_1, available[1])]
+        .iter()
+        .filter(|(reference_amount, _)| *reference_amount > 0)
+        .map(|(reference_amount, available)| *available as f64 / *reference_amount as f64)
+        .fold(f64::INFINITY, f64::min);
+    let needed = [
+        (amount_0 as f64 * scale) as u64,
+        (amount_1 as f64 * scale) as u64,
+    ];
+    let has_deposits = needed.iter().any(|amount| *amount > 0);
+
+    let last_open = journal
+        .iter()
+        .rev()
+        .find(|entry| {
+            entry.operation == Operation::OpenPosition && entry.pool.as_ref() == Some(&pool_key)
+        })
+        .map_or(0, |entry| entry.timestamp);
+    let last_deposit = journal
+        .iter()
+        .rev()
+        .find(|entry| {
+            entry.operation == Operation::Deposit
+                && entry.pool.as_ref() == Some(&pool_key)
+                && entry
+                    .reserve
+                    .as_ref()
+                    .is_some_and(|reserve| reserve_keys.contains(reserve))
+        })
+        .map_or(0, |entry| entry.timestamp);
+
+    let threshold = config.threshold_pct / 100.0;
+    let to_save = has_positions && save_apy - lp_apr > threshold;
+    let to_pool = !to_save && has_deposits && lp_apr - save_apy > threshold;
+    if !to_save && !to_pool {
+        println!(
+            "no move: spread {:.4}% within the {}% threshold or nothing to move",
+            (save_apy - lp_apr) * 100.0,
+            config.threshold_pct
+        );
+        return;
+    }
+    let held_since = if to_save { last_open } else { last_deposit };
+    if now.saturating_sub(held_since) < config.min_hold_secs {
+        println!(
+            "no move: capital moved {}s ago, minimum hold is {}s",
+            now.saturating_sub(held_since),
+            config.min_hold_secs
+        );
+        return;
+    }
+    if config.dry_run {
+        if to_save {
+            println!("would move capital from the pool to save");
+        } else {
+            println!(
+                "would move capital from save to the pool, withdrawing amount_0:{}, amount_1:{}",
+                needed[0], needed[1]
+            );
+        }
+        return;
+    }
+    let park = ParkConfig {
+        save_program,
+        lending_market,
+        working_reserves: Vec::new(),
+        pool: Some(pool_id_account),
+    };
+
+    if to_save {
+        println!("moving capital from the pool to save");
+        close_all(
+            client,
+            &rpc_client,
+            &payer,
+            raydium_v3_program,
+            program,
+            Some(pool_id_account),
+            slippage,
+            Some(&park),
+            data_dir,
+        );
+    } else {
+        println!("moving capital from save to the pool");
+        let mints = [pool.token_mint_0, pool.token_mint_1];
+        let pool_reserves = [reserve_0, reserve_1];
+        // what the open leaves of the withdrawn tokens is parked again
+        let token_accounts = mints.map(|mint| get_associated_token_address(&payer.pubkey(), &mint));
+        let balances_before = get_token_account_balances(&rpc_client, &token_accounts);
+        let withdrawn = [0, 1].map(|index| {
+            if needed[index] == 0 {
+                return 0;
+            }
+            // cTokens in the wallet are not collateral, so there is no LTV to respect
+            withdraw(
+                &rpc_client,
+                &payer,
+                &save_program,
+                &lending_market,
+                &mints[index],
+                WithdrawAmount::Collateral(
+                    pool_reserves[index]
+                        .liquidity_to_collateral_ceil(needed[index])
+                        .min(attributed[index]),
+                ),
+                false,
+                0.0,
+                Some(&pool_id_account),
+                data_dir,
+            )
+        });
+        open(
+            client,
+            &rpc_client,
+            &payer,
+            raydium_v3_program,
+            program,
+            pool_id_account,
+            tickarray_bitmap_extension,
+            pool.token_mint_0,
+            pool.token_mint_1,
+            &[(
+                range_input,
+                LiquidityInput::BothAmounts {
+                    amount_0: withdrawn[0],
+                    amount_1: withdrawn[1],
+                },
+            )],
+            slippage,
+            invert_price,
+            false,
+            false,
+            false,
+            None,
+            data_dir,
+        );
+        park_idle_tokens(
+            &rpc_client,
+            &payer,
+            &park,
+            &mints,
+            &balances_before,
+            data_dir,
+        );
+    }
+}
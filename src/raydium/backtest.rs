@@ -0,0 +1,370 @@
+//! Replays a historical price or swap series against simulated positions of different range
+//! widths, to compare fees, impermanent loss and costs before deploying a strategy.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    rc::Rc,
+};
+
+use anchor_client::Program;
+use raydium_amm_v3::{
+    libraries::{liquidity_math, tick_math},
+    states::{AmmConfig, PoolState, TickState},
+};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+
+use super::{
+    pnl::quote_value,
+    range::{RangeWidth, ticks_around_current},
+    utils::{
+        SwapState, lp_fee_amount, multipler, orient_price, price_to_sqrt_price_x64,
+        sqrt_price_x64_to_price, swap_steps,
+    },
+};
+
+/// Liquidity used to find the token split of a range before scaling it to the capital.
+const UNIT_LIQUIDITY: u128 = 1_000_000_000_000;
+/// Transactions paid for each rebalance: close, swap to the new ratio and open.
+const REBALANCE_TRANSACTIONS: u64 = 3;
+
+/// One row of the replayed series. Swap amounts are the raw input amounts traded since the
+/// previous row, zero for a price-only series.
+#[derive(Clone, Copy, Debug)]
+pub struct MarketRow {
+    pub timestamp: u64,
+    pub sqrt_price_x64: u128,
+    pub amount_0_in: u64,
+    pub amount_1_in: u64,
+}
+
+/// Parameters shared by every simulated range width.
+#[derive(Clone, Copy, Debug)]
+pub struct BacktestConfig {
+    /// Starting capital in raw units of the quote token.
+    pub capital: u64,
+    /// Seconds the price may stay out of range before the position is re-centered, `None`
+    /// never rebalances.
+    pub rebalance_after: Option<u64>,
+    pub tx_fee_lamports: u64,
+    /// Liquidity of everyone else in the pool, sharing the fees with the simulated position.
+    pub pool_liquidity: u128,
+    pub invert_price: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BacktestResult {
+    pub fees_0: u64,
+    pub fees_1: u64,
+    pub fee_value: f64,
+    pub hodl_value: f64,
+    pub lp_value: f64,
+    pub swap_cost_value: f64,
+    pub gas_lamports: u64,
+    pub rebalances: u32,
+    pub seconds_in_range: u64,
+    pub seconds_total: u64,
+}
+
+/// Reads `timestamp,price[,amount_0_in,amount_1_in]` rows sorted by timestamp. Prices follow the
+/// configured orientation. Blank lines and a header on the first line are skipped, any other row
+/// that does not parse is refused with its line number.
+pub fn load_market_rows(
+    path: &Path,
+    pool: &PoolState,
+    invert_price: bool,
+) -> Result<Vec<MarketRow>, String> {
+    let file =
+        File::open(path).map_err(|err| format!("cannot open {}: {}", path.display(), err))?;
+    let mut rows = Vec::new();
+    let mut first_line = true;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let malformed = |reason: String| format!("{}:{}: {}", path.display(), index + 1, reason);
+        let line = line.map_err(|err| malformed(err.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let is_first_line = std::mem::replace(&mut first_line, false);
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let Ok(timestamp) = fields[0].parse::<u64>() else {
+            if is_first_line {
+                continue;
+            }
+            return Err(malformed(format!("invalid timestamp: {}", fields[0])));
+        };
+        if !(2..=4).contains(&fields.len()) {
+            return Err(malformed(
+                "expected timestamp,price[,amount_0_in,amount_1_in]".to_string(),
+            ));
+        }
+        let price = fields[1]
+            .parse::<f64>()
+            .ok()
+            .filter(|price| price.is_finite() && *price > 0.0)
+            .ok_or_else(|| malformed(format!("invalid price: {}", fields[1])))?;
+        let price = orient_price(price, invert_price);
+        let amount = |index: usize| -> Result<u64, String> {
+            match fields.get(index).filter(|field| !field.is_empty()) {
+                None => Ok(0),
+                Some(field) => field
+                    .parse::<u64>()
+                    .map_err(|_| malformed(format!("invalid amount: {}", field))),
+            }
+        };
+        rows.push(MarketRow {
+            timestamp,
+            sqrt_price_x64: price_to_sqrt_price_x64(
+                price,
+                pool.mint_decimals_0,
+                pool.mint_decimals_1,
+            ),
+            amount_0_in: amount(2)?,
+            amount_1_in: amount(3)?,
+        });
+    }
+    rows.sort_by_key(|row| row.timestamp);
+    Ok(rows)
+}
+
+/// Token amounts of `liquidity` between two ticks at the given price.
+fn position_amounts(
+    sqrt_price_x64: u128,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    liquidity: u128,
+) -> (u64, u64) {
+    liquidity_math::get_delta_amounts_signed(
+        tick_math::get_tick_at_sqrt_price(sqrt_price_x64).unwrap(),
+        sqrt_price_x64,
+        tick_lower_index,
+        tick_upper_index,
+        -(liquidity as i128),
+    )
+    .unwrap()
+}
+
+/// Simulates one range width over the whole series.
+pub fn run_backtest(
+    pool: &PoolState,
+    amm_config: &AmmConfig,
+    rows: &[MarketRow],
+    width: RangeWidth,
+    config: &BacktestConfig,
+) -> BacktestResult {
+    let (decimals_0, decimals_1) = (pool.mint_decimals_0, pool.mint_decimals_1);
+    let value = |amount_0: u64, amount_1: u64, sqrt_price_x64: u128| {
+        quote_value(
+            amount_0,
+            amount_1,
+            sqrt_price_x64_to_price(sqrt_price_x64, decimals_0, decimals_1),
+            decimals_0,
+            decimals_1,
+            config.invert_price,
+        )
+    };
+    // the simulated pool only moves its price, ranges are placed the way open-position does
+    let mut sim_pool = *pool;
+    let mut place_range = |sqrt_price_x64: u128, capital_value: f64| {
+        sim_pool.sqrt_price_x64 = sqrt_price_x64;
+        sim_pool.tick_current = tick_math::get_tick_at_sqrt_price(sqrt_price_x64).unwrap();
+        let (tick_lower_index, tick_upper_index) = ticks_around_current(&sim_pool, width, 0.0);
+        let (unit_0, unit_1) = position_amounts(
+            sqrt_price_x64,
+            tick_lower_index,
+            tick_upper_index,
+            UNIT_LIQUIDITY,
+        );
+        let unit_value = value(unit_0, unit_1, sqrt_price_x64);
+        let liquidity = (UNIT_LIQUIDITY as f64 * capital_value / unit_value) as u128;
+        (tick_lower_index, tick_upper_index, liquidity)
+    };
+
+    let mut result = BacktestResult::default();
+    let first = rows[0];
+    let quote_decimals = if config.invert_price {
+        decimals_0
+    } else {
+        decimals_1
+    };
+    let capital_value = config.capital as f64 / multipler(quote_decimals);
+    let (mut tick_lower_index, mut tick_upper_index, mut liquidity) =
+        place_range(first.sqrt_price_x64, capital_value);
+    let (entry_0, entry_1) = position_amounts(
+        first.sqrt_price_x64,
+        tick_lower_index,
+        tick_upper_index,
+        liquidity,
+    );
+    result.gas_lamports += config.tx_fee_lamports;
+
+    let mut sqrt_price_x64 = first.sqrt_price_x64;
+    let mut previous_timestamp = first.timestamp;
+    let mut out_of_range_since = None;
+    for row in &rows[1..] {
+        let tick_current = tick_math::get_tick_at_sqrt_price(sqrt_price_x64).unwrap();
+        let in_range = tick_current >= tick_lower_index && tick_current < tick_upper_index;
+        let elapsed = row.timestamp.saturating_sub(previous_timestamp);
+        result.seconds_total += elapsed;
+        if in_range {
+            result.seconds_in_range += elapsed;
+            // the part of each swap filled before the price leaves our range pays us the liquidity
+            // providers' fee in proportion to our share of the active liquidity
+            for (amount_in, zero_for_one) in [(row.amount_0_in, true), (row.amount_1_in, false)] {
+                if amount_in == 0 {
+                    continue;
+                }
+                let mut bound = TickState::default();
+                bound.tick = if zero_for_one {
+                    tick_lower_index
+                } else {
+                    tick_upper_index
+                };
+                bound.liquidity_gross = liquidity;
+                bound.liquidity_net = liquidity as i128;
+                let mut state = SwapState {
+                    amount_specified_remaining: amount_in,
+                    amount_calculated: 0,
+                    sqrt_price_x64,
+                    tick: tick_current,
+                    liquidity: config.pool_liquidity + liquidity,
+                };
+                let mut fee = 0;
+                swap_steps(
+                    &mut state,
+                    zero_for_one,
+                    true,
+                    amm_config.trade_fee_rate,
+                    tick_math::get_sqrt_price_at_tick(bound.tick).unwrap(),
+                    |_| Ok(bound),
+                    |swap_step, step_liquidity| {
+                        if step_liquidity > 0 {
+                            fee += (lp_fee_amount(swap_step.fee_amount, amm_config) as u128
+                                * liquidity
+                                / step_liquidity) as u64;
+                        }
+                    },
+                )
+                .unwrap();
+                if zero_for_one {
+                    result.fees_0 += fee;
+                } else {
+                    result.fees_1 += fee;
+                }
+            }
+        }
+        sqrt_price_x64 = row.sqrt_price_x64;
+        previous_timestamp = row.timestamp;
+
+        let tick_current = tick_math::get_tick_at_sqrt_price(sqrt_price_x64).unwrap();
+        if tick_current >= tick_lower_index && tick_current < tick_upper_index {
+            out_of_range_since = None;
+            continue;
+        }
+        let out_since = *out_of_range_since.get_or_insert(row.timestamp);
+        let Some(rebalance_after) = config.rebalance_after else {
+            continue;
+        };
+        if row.timestamp.saturating_sub(out_since) < rebalance_after {
+            continue;
+        }
+        // close, swap the single-sided amounts to the new range's ratio and reopen
+        let (amount_0, amount_1) = position_amounts(
+            sqrt_price_x64,
+            tick_lower_index,
+            tick_upper_index,
+            liquidity,
+        );
+        let position_value = value(amount_0, amount_1, sqrt_price_x64);
+        let (new_lower, new_upper, new_liquidity) = place_range(sqrt_price_x64, position_value);
+        let (new_amount_0, _) =
+            position_amounts(sqrt_price_x64, new_lower, new_upper, new_liquidity);
+        let swapped_value = value(amount_0.abs_diff(new_amount_0), 0, sqrt_price_x64);
+        let swap_cost = swapped_value * amm_config.trade_fee_rate as f64 / 1_000_000.0;
+        let (new_lower, new_upper, new_liquidity) =
+            place_range(sqrt_price_x64, position_value - swap_cost);
+        tick_lower_index = new_lower;
+        tick_upper_index = new_upper;
+        liquidity = new_liquidity;
+        result.swap_cost_value += swap_cost;
+        result.gas_lamports += REBALANCE_TRANSACTIONS * config.tx_fee_lamports;
+        result.rebalances += 1;
+        out_of_range_since = None;
+    }
+
+    let (amount_0, amount_1) = position_amounts(
+        sqrt_price_x64,
+        tick_lower_index,
+        tick_upper_index,
+        liquidity,
+    );
+    result.lp_value = value(amount_0, amount_1, sqrt_price_x64);
+    result.hodl_value = value(entry_0, entry_1, sqrt_price_x64);
+    result.fee_value = value(result.fees_0, result.fees_1, sqrt_price_x64);
+    result
+}
+
+/// Backtests every width against the series in `input` and prints one report per width.
+pub fn backtest(
+    program: Program<Rc<Keypair>>,
+    pool_id_account: Pubkey,
+    input: &Path,
+    widths: &[RangeWidth],
+    capital: u64,
+    rebalance_after: Option<u64>,
+    tx_fee_lamports: u64,
+    pool_liquidity: Option<u128>,
+    invert_price: bool,
+) {
+    let pool: PoolState = program.account(pool_id_account).unwrap();
+    let amm_config: AmmConfig = program.account(pool.amm_config).unwrap();
+    let rows = match load_market_rows(input, &pool, invert_price) {
+        Ok(rows) => rows,
+        Err(err) => {
+            println!("cannot read the series: {}", err);
+            return;
+        }
+    };
+    assert!(rows.len() >= 2, "the series needs at least two rows");
+    let config = BacktestConfig {
+        capital,
+        rebalance_after,
+        tx_fee_lamports,
+        pool_liquidity: pool_liquidity.unwrap_or(pool.liquidity),
+        invert_price,
+    };
+    println!(
+        "rows:{}, trade_fee_rate:{}, protocol_fee_rate:{}, fund_fee_rate:{}, pool liquidity:{}",
+        rows.len(),
+        amm_config.trade_fee_rate,
+        amm_config.protocol_fee_rate,
+        amm_config.fund_fee_rate,
+        config.pool_liquidity
+    );
+    for width in widths {
+        let result = run_backtest(&pool, &amm_config, &rows, *width, &config);
+        let impermanent_loss = result.lp_value - result.hodl_value;
+        println!("width:{:?}", width);
+        println!(
+            "    fees_0:{}, fees_1:{}, fee value:{:.6}",
+            result.fees_0, result.fees_1, result.fee_value
+        );
+        println!(
+            "    hodl value:{:.6}, lp value:{:.6}, impermanent loss:{:.6}",
+            result.hodl_value, result.lp_value, impermanent_loss
+        );
+        println!(
+            "    rebalances:{}, swap cost:{:.6}, gas lamports:{}",
+            result.rebalances, result.swap_cost_value, result.gas_lamports
+        );
+        println!(
+            "    time in range:{:.2}%, net vs hodl:{:.6}",
+            if result.seconds_total > 0 {
+                result.seconds_in_range as f64 / result.seconds_total as f64 * 100.0
+            } else {
+                0.0
+            },
+            impermanent_loss + result.fee_value
+        );
+    }
+}
@@ -0,0 +1,231 @@
+//! Packs independent groups of instructions into as few transactions as fit the packet size
+//! and compute unit limits.
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    message::Message,
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::Transaction,
+};
+
+use super::utils::get_token_account_balances;
+use crate::try_send_txn;
+
+/// Most compute units a single transaction may request.
+pub const MAX_COMPUTE_UNITS: u32 = 1_400_000;
+
+/// Instructions that must land in the same transaction, with the compute units they need.
+pub struct InstructionGroup {
+    pub instructions: Vec<Instruction>,
+    pub compute_units: u32,
+    /// Keypairs besides the payer that sign the group, such as the mint of a new position NFT.
+    pub signers: Vec<Keypair>,
+}
+
+/// Serialized size of a transaction carrying `instructions`, with placeholder signatures.
+fn transaction_size(payer: &Pubkey, instructions: &[Instruction]) -> usize {
+    let message = Message::new(instructions, Some(payer));
+    let transaction = Transaction {
+        signatures: vec![Signature::default(); message.header.num_required_signatures as usize],
+        message,
+    };
+    bincode::serialized_size(&transaction).unwrap() as usize
+}
+
+/// The compute budget instruction followed by the instructions of the given groups.
+pub fn batch_instructions(groups: &[InstructionGroup], batch: &[usize]) -> Vec<Instruction> {
+    let compute_units: u32 = batch.iter().map(|index| groups[*index].compute_units).sum();
+    let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(
+        compute_units.min(MAX_COMPUTE_UNITS),
+    )];
+    for index in batch {
+        instructions.extend(groups[*index].instructions.iter().cloned());
+    }
+    instructions
+}
+
+/// Splits `groups` into batches of group indexes, in order, each fitting one transaction. A
+/// group too large on its own still gets a batch of its own.
+pub fn pack_instruction_groups(payer: &Pubkey, groups: &[InstructionGroup]) -> Vec<Vec<usize>> {
+    let mut batches = Vec::new();
+    let mut batch: Vec<usize> = Vec::new();
+    let mut compute_units = 0;
+    for (index, group) in groups.iter().enumerate() {
+        if !batch.is_empty() {
+            let mut candidate = batch.clone();
+            candidate.push(index);
+            if compute_units + group.compute_units > MAX_COMPUTE_UNITS
+                || transaction_size(payer, &batch_instructions(groups, &candidate))
+                    > PACKET_DATA_SIZE
+            {
+                batches.push(std::mem::take(&mut batch));
+                compute_units = 0;
+            }
+        }
+        batch.push(index);
+        compute_units += group.compute_units;
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Sends `groups` packed into batches. A failed batch is retried one group at a time, so one bad
+/// group does not hold back the others. Returns the outcome of every group, in order.
+pub fn send_instruction_groups(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    groups: &[InstructionGroup],
+) -> Vec<Result<Signature, String>> {
+    send_measured_instruction_groups(rpc_client, payer, groups, &[], |_, _, _, _| {})
+}
+
+/// Sends `groups` like `send_instruction_groups`, reading the balances of `balance_accounts`
+/// around every batch. `on_confirmed` is called as soon as a batch confirms, with its signature,
+/// its group indexes and the balances before and after it, so the caller can record each batch
+/// even when a later one fails.
+pub fn send_measured_instruction_groups(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    groups: &[InstructionGroup],
+    balance_accounts: &[Pubkey],
+    mut on_confirmed: impl FnMut(&Signature, &[usize], &[u64], &[u64]),
+) -> Vec<Result<Signature, String>> {
+    let mut outcomes = vec![Err(String::new()); groups.len()];
+    let mut pending_batches = pack_instruction_groups(&payer.pubkey(), groups);
+    pending_batches.reverse();
+    while let Some(batch) = pending_batches.pop() {
+        let instructions = batch_instructions(groups, &batch);
+        let recent_hash = match rpc_client.get_latest_blockhash() {
+            Ok(recent_hash) => recent_hash,
+            Err(err) => {
+                for index in batch {
+                    outcomes[index] = Err(err.to_string());
+                }
+                continue;
+            }
+        };
+        let mut signers = vec![payer];
+        signers.extend(batch.iter().flat_map(|index| groups[*index].signers.iter()));
+        let txn = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&payer.pubkey()),
+            &signers,
+            recent_hash,
+        );
+        let balances_before = if balance_accounts.is_empty() {
+            Vec::new()
+        } else {
+            get_token_account_balances(rpc_client, balance_accounts)
+        };
+        match try_send_txn(rpc_client, &txn, true) {
+            Ok(signature) => {
+                println!("{}", signature);
+                let balances_after = if balance_accounts.is_empty() {
+                    Vec::new()
+                } else {
+                    get_token_account_balances(rpc_client, balance_accounts)
+                };
+                on_confirmed(&signature, &batch, &balances_before, &balances_after);
+                for index in batch {
+                    outcomes[index] = Ok(signature);
+                }
+            }
+            Err(err) if batch.len() > 1 => {
+                println!(
+                    "batch of {} failed, retrying one by one: {}",
+                    batch.len(),
+                    err
+                );
+                pending_batches.extend(batch.into_iter().rev().map(|index| vec![index]));
+            }
+            Err(err) => outcomes[batch[0]] = Err(err.to_string()),
+        }
+    }
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A group of one instruction carrying `data_len` bytes of data.
+    fn group(data_len: usize, compute_units: u32) -> InstructionGroup {
+        InstructionGroup {
+            instructions: vec![Instruction::new_with_bytes(
+                Pubkey::new_unique(),
+                &vec![0u8; data_len],
+                Vec::new(),
+            )],
+            compute_units,
+            signers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn batch_requests_the_summed_compute_units() {
+        let groups = [group(10, 600_000), group(10, 1_000_000)];
+        let instructions = batch_instructions(&groups, &[0]);
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(
+            instructions[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(600_000)
+        );
+        // capped at what a transaction may request
+        let instructions = batch_instructions(&groups, &[0, 1]);
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(
+            instructions[0],
+            ComputeBudgetInstruction::set_compute_unit_limit(MAX_COMPUTE_UNITS)
+        );
+    }
+
+    #[test]
+    fn packs_groups_in_order_within_the_compute_limit() {
+        let payer = Pubkey::new_unique();
+        let groups = [
+            group(10, 600_000),
+            group(10, 600_000),
+            group(10, 600_000),
+            group(10, 100_000),
+        ];
+        assert_eq!(
+            pack_instruction_groups(&payer, &groups),
+            vec![vec![0, 1], vec![2, 3]]
+        );
+    }
+
+    #[test]
+    fn packs_groups_within_the_packet_size() {
+        let payer = Pubkey::new_unique();
+        let groups = [group(100, 1_000), group(100, 1_000), group(800, 1_000)];
+        let batches = pack_instruction_groups(&payer, &groups);
+        assert_eq!(batches, vec![vec![0, 1], vec![2]]);
+        for batch in &batches {
+            assert!(
+                transaction_size(&payer, &batch_instructions(&groups, batch)) <= PACKET_DATA_SIZE
+            );
+        }
+    }
+
+    #[test]
+    fn oversized_group_gets_a_batch_of_its_own() {
+        let payer = Pubkey::new_unique();
+        let groups = [group(10, 1_000), group(2_000, 1_000), group(10, 1_000)];
+        assert_eq!(
+            pack_instruction_groups(&payer, &groups),
+            vec![vec![0], vec![1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn no_groups_no_batches() {
+        assert!(pack_instruction_groups(&Pubkey::new_unique(), &[]).is_empty());
+    }
+}
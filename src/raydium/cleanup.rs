@@ -0,0 +1,224 @@
+use std::rc::Rc;
+
+use anchor_client::Client;
+use raydium_amm_v3::states::PoolState;
+use solana_account_decoder::{UiAccountData, parse_token::TokenAccountType};
+use solana_client::{rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_token_2022::{
+    extension::{BaseStateWithExtensions, StateWithExtensions, transfer_fee::TransferFeeAmount},
+    state::Account as TokenAccount,
+};
+
+use super::{
+    batch::{InstructionGroup, send_instruction_groups},
+    close::close_personal_position_instr,
+    position::get_all_personal_positions_by_owner,
+    utils::deserialize_anchor_account,
+};
+
+/// Compute units requested for each position close in a batch.
+const CLOSE_POSITION_COMPUTE_UNITS: u32 = 100_000;
+/// Compute units requested for each token account close in a batch.
+const CLOSE_TOKEN_ACCOUNT_COMPUTE_UNITS: u32 = 10_000;
+
+/// Empty token accounts of `owner` that can be closed: zero balance, no withheld transfer fees,
+/// and not for one of the `keep_mints`.
+fn get_empty_token_accounts(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    keep_mints: &[Pubkey],
+) -> Vec<(Pubkey, Pubkey)> {
+    let mut candidates = Vec::new();
+    for token_program in [spl_token::id(), spl_token_2022::id()] {
+        let token_accounts = rpc_client
+            .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(token_program))
+            .unwrap();
+        for keyed_account in token_accounts {
+            if let UiAccountData::Json(parsed_account) = keyed_account.account.data {
+                if let Ok(TokenAccountType::Account(ui_token_account)) =
+                    serde_json::from_value(parsed_account.parsed)
+                {
+                    let mint = ui_token_account.mint.parse::<Pubkey>().unwrap();
+                    if ui_token_account.token_amount.amount == "0" && !keep_mints.contains(&mint) {
+                        candidates.push((
+                            keyed_account.pubkey.parse::<Pubkey>().unwrap(),
+                            token_program,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    // withheld transfer fees must be harvested before a Token-2022 account can be closed
+    let keys: Vec<Pubkey> = candidates.iter().map(|(key, _)| *key).collect();
+    let rsps = rpc_client.get_multiple_accounts(&keys).unwrap();
+    candidates
+        .into_iter()
+        .zip(rsps)
+        .filter(|(_, rsp)| {
+            rsp.as_ref().is_some_and(|account| {
+                StateWithExtensions::<TokenAccount>::unpack(&account.data).is_ok_and(|state| {
+                    state.base.amount == 0
+                        && state
+                            .get_extension::<TransferFeeAmount>()
+                            .map_or(0, |fee| u64::from(fee.withheld_amount))
+                            == 0
+                })
+            })
+        })
+        .map(|(candidate, _)| candidate)
+        .collect()
+}
+
+/// Closes positions with no liquidity, fees or rewards left, then empty token accounts, and
+/// reports the rent reclaimed. Accounts of the `keep_mints` and of the tokens and rewards of
+/// every pool where a position stays open are kept. With `dry_run`, only lists what would be
+/// closed.
+pub fn cleanup(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    keep_mints: &[Pubkey],
+    dry_run: bool,
+) {
+    let mut instruction_groups = Vec::new();
+    // accounts each group closes, to measure the rent they held
+    let mut closed_accounts: Vec<Vec<Pubkey>> = Vec::new();
+    let mut descriptions = Vec::new();
+
+    let personal_positions =
+        get_all_personal_positions_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program);
+    let mut position_nft_accounts = Vec::new();
+    let mut open_pools = Vec::new();
+    for (nft_info, position) in &personal_positions {
+        position_nft_accounts.push(nft_info.key);
+        let reward_owed = position
+            .reward_infos
+            .iter()
+            .any(|reward_info| reward_info.reward_amount_owed != 0);
+        if position.liquidity != 0
+            || position.token_fees_owed_0 != 0
+            || position.token_fees_owed_1 != 0
+            || reward_owed
+        {
+            if !open_pools.contains(&position.pool_id) {
+                open_pools.push(position.pool_id);
+            }
+            continue;
+        }
+        instruction_groups.push(InstructionGroup {
+            instructions: close_personal_position_instr(
+                client.clone(),
+                raydium_v3_program,
+                position.nft_mint,
+                nft_info.key,
+                nft_info.program,
+            ),
+            compute_units: CLOSE_POSITION_COMPUTE_UNITS,
+            signers: Vec::new(),
+        });
+        closed_accounts.push(vec![nft_info.position, nft_info.key, nft_info.mint]);
+        descriptions.push(format!("position nft_mint:{}", position.nft_mint));
+    }
+
+    // open positions keep paying out their pool tokens and rewards
+    let mut keep_mints = keep_mints.to_vec();
+    if !open_pools.is_empty() {
+        for rsp in rpc_client.get_multiple_accounts(&open_pools).unwrap() {
+            let pool = deserialize_anchor_account::<PoolState>(&rsp.unwrap()).unwrap();
+            keep_mints.extend([pool.token_mint_0, pool.token_mint_1]);
+            keep_mints.extend(
+                pool.reward_infos
+                    .iter()
+                    .filter(|reward_info| reward_info.token_mint != Pubkey::default())
+                    .map(|reward_info| reward_info.token_mint),
+            );
+        }
+    }
+    for (token_account, token_program) in
+        get_empty_token_accounts(&rpc_client, &payer.pubkey(), &keep_mints)
+    {
+        // position NFT accounts are closed together with their position
+        if position_nft_accounts.contains(&token_account) {
+            continue;
+        }
+        let close_instr = if token_program == spl_token::id() {
+            spl_token::instruction::close_account(
+                &token_program,
+                &token_account,
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &[],
+            )
+            .unwrap()
+        } else {
+            spl_token_2022::instruction::close_account(
+                &token_program,
+                &token_account,
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &[],
+            )
+            .unwrap()
+        };
+        instruction_groups.push(InstructionGroup {
+            instructions: vec![close_instr],
+            compute_units: CLOSE_TOKEN_ACCOUNT_COMPUTE_UNITS,
+            signers: Vec::new(),
+        });
+        closed_accounts.push(vec![token_account]);
+        descriptions.push(format!("token account:{}", token_account));
+    }
+    if instruction_groups.is_empty() {
+        println!("nothing to clean up");
+        return;
+    }
+    if dry_run {
+        for description in &descriptions {
+            println!("{}, would be closed", description);
+        }
+        return;
+    }
+
+    let lamports_before: Vec<Vec<u64>> = closed_accounts
+        .iter()
+        .map(|accounts| {
+            rpc_client
+                .get_multiple_accounts(accounts)
+                .unwrap()
+                .iter()
+                .map(|rsp| rsp.as_ref().map_or(0, |account| account.lamports))
+                .collect()
+        })
+        .collect();
+    let outcomes = send_instruction_groups(&rpc_client, &payer, &instruction_groups);
+    let mut total_reclaimed = 0;
+    for (((accounts, lamports), description), outcome) in closed_accounts
+        .iter()
+        .zip(lamports_before)
+        .zip(descriptions)
+        .zip(outcomes)
+    {
+        match outcome {
+            Ok(signature) => {
+                // only accounts that are really gone count as reclaimed
+                let rsps = rpc_client.get_multiple_accounts(accounts).unwrap();
+                let reclaimed: u64 = lamports
+                    .iter()
+                    .zip(rsps)
+                    .filter(|(_, rsp)| rsp.is_none())
+                    .map(|(lamports, _)| *lamports)
+                    .sum();
+                total_reclaimed += reclaimed;
+                println!(
+                    "{}, closed, reclaimed lamports:{}, signature:{}",
+                    description, reclaimed, signature
+                );
+            }
+            Err(err) => println!("{}, failed: {}", description, err),
+        }
+    }
+    println!("total reclaimed lamports:{}", total_reclaimed);
+}
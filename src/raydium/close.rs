@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{path::Path, rc::Rc};
 
 use anchor_client::{Client, Program};
 use anchor_lang::prelude::AccountMeta;
@@ -11,26 +11,44 @@ use raydium_amm_v3::{
         DecreaseLiquidityV2 as DecreaseLiquidityV2Instruction,
     },
     libraries::{liquidity_math, tick_math},
-    states::{POSITION_SEED, TICK_ARRAY_SEED},
+    states::{POSITION_SEED, PersonalPositionState, PoolState, TICK_ARRAY_SEED},
 };
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
-    instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer, system_program,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    system_program,
     transaction::Transaction,
 };
 use spl_associated_token_account::get_associated_token_address;
 
-use crate::send_txn;
+use crate::{
+    journal::{JournalEntry, MovementKind, Operation, append_journal_entry},
+    save::park::{ParkConfig, park_idle_tokens},
+    send_txn,
+};
 
 use super::{
-    position::get_all_nft_and_position_by_owner,
-    utils::{amount_with_slippage, deserialize_anchor_account, get_pool_mints_transfer_fee, price_to_sqrt_price_x64, tick_with_spacing},
+    growth::{
+        get_fee_growth_inside, get_pending_fees, get_pending_rewards, get_reward_growths_inside,
+        update_reward_infos,
+    },
+    position::{get_all_nft_and_position_by_owner, get_position_tick_states},
+    utils::{
+        amount_with_slippage, deserialize_anchor_account, get_chain_timestamp, get_mint_decimals,
+        get_pool_mints_inverse_fee, get_pool_mints_transfer_fee, get_token_account_balances,
+        price_to_sqrt_price_x64, sqrt_price_x64_to_price, tick_with_spacing,
+    },
 };
 
+/// Closes the payer's position at the given prices in the pool. Returns whether there was one to
+/// close.
 pub fn close(
     client: Rc<Client<Rc<Keypair>>>,
-    rpc_client: RpcClient,
-    payer: Keypair,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
     raydium_v3_program: Pubkey,
     program: Program<Rc<Keypair>>,
     pool_id_account: Pubkey,
@@ -40,7 +58,9 @@ pub fn close(
     tick_lower_price: f64,
     tick_upper_price: f64,
     slippage: f64,
-) {
+    park: Option<&ParkConfig>,
+    data_dir: &Path,
+) -> bool {
     // let tick_lower_index = -tick_lower_index;
     // load pool to get observation
     let pool: raydium_amm_v3::states::PoolState = program.account(pool_id_account).unwrap();
@@ -70,7 +90,7 @@ pub fn close(
         );
     // load position
     let position_nft_infos =
-        get_all_nft_and_position_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program);
+        get_all_nft_and_position_by_owner(rpc_client, &payer.pubkey(), &raydium_v3_program);
     let positions: Vec<Pubkey> = position_nft_infos
         .iter()
         .map(|item| item.position)
@@ -115,6 +135,52 @@ pub fn close(
                 reward_vault_with_user_vault.push(item.token_mint);
             }
         }
+        // estimate rewards off-chain, then compare with what the reward accounts receive
+        let reward_infos = update_reward_infos(&pool, get_chain_timestamp(rpc_client));
+        let (tick_lower_state, tick_upper_state) = get_position_tick_states(
+            rpc_client,
+            &raydium_v3_program,
+            &pool_id_account,
+            pool.tick_spacing,
+            tick_lower_index,
+            tick_upper_index,
+        );
+        let (fee_growth_inside_0_x64, fee_growth_inside_1_x64) = get_fee_growth_inside(
+            &tick_lower_state,
+            &tick_upper_state,
+            pool.tick_current,
+            pool.fee_growth_global_0_x64,
+            pool.fee_growth_global_1_x64,
+        );
+        let (pending_fee_0, pending_fee_1) = get_pending_fees(
+            &find_position,
+            fee_growth_inside_0_x64,
+            fee_growth_inside_1_x64,
+        );
+        println!("pending fee_0:{}, fee_1:{}", pending_fee_0, pending_fee_1);
+        let pending_rewards = get_pending_rewards(
+            &find_position,
+            &get_reward_growths_inside(
+                &tick_lower_state,
+                &tick_upper_state,
+                pool.tick_current,
+                &reward_infos,
+            ),
+        );
+        let mut reward_receipts: Vec<(Pubkey, Pubkey, u64)> = Vec::new();
+        for (reward_info, pending_reward) in reward_infos.iter().zip(pending_rewards) {
+            if reward_info.initialized() {
+                reward_receipts.push((
+                    reward_info.token_mint,
+                    get_associated_token_address(&payer.pubkey(), &reward_info.token_mint),
+                    pending_reward,
+                ));
+            }
+        }
+        let reward_accounts: Vec<Pubkey> = reward_receipts
+            .iter()
+            .map(|(_, user_account, _)| *user_account)
+            .collect();
         let liquidity = find_position.liquidity;
         let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
             pool.tick_current,
@@ -127,7 +193,7 @@ pub fn close(
         let amount_0_with_slippage = amount_with_slippage(amount_0, slippage, false);
         let amount_1_with_slippage = amount_with_slippage(amount_1, slippage, false);
         let transfer_fee = get_pool_mints_transfer_fee(
-            &rpc_client,
+            rpc_client,
             pool.token_mint_0,
             pool.token_mint_1,
             amount_0_with_slippage,
@@ -140,6 +206,19 @@ pub fn close(
             .checked_sub(transfer_fee.1.transfer_fee)
             .unwrap();
 
+        let user_token_account_0 =
+            spl_associated_token_account::get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &mint0,
+                &transfer_fee.0.owner,
+            );
+        let user_token_account_1 =
+            spl_associated_token_account::get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &mint1,
+                &transfer_fee.1.owner,
+            );
+
         let mut remaining_accounts = Vec::new();
         remaining_accounts.push(AccountMeta::new(tickarray_bitmap_extension, false));
 
@@ -159,16 +238,8 @@ pub fn close(
             pool.token_mint_1,
             find_position.nft_mint,
             user_nft_token_info.key,
-            spl_associated_token_account::get_associated_token_address_with_program_id(
-                &payer.pubkey(),
-                &mint0,
-                &transfer_fee.0.owner,
-            ),
-            spl_associated_token_account::get_associated_token_address_with_program_id(
-                &payer.pubkey(),
-                &mint1,
-                &transfer_fee.1.owner,
-            ),
+            user_token_account_0,
+            user_token_account_1,
             remaining_accounts,
             liquidity,
             amount_0_min,
@@ -189,7 +260,7 @@ pub fn close(
             decrease_instr.extend(close_position_instr);
         }
         // send
-        let signers = vec![&payer];
+        let signers = vec![payer];
         let recent_hash = rpc_client.get_latest_blockhash().unwrap();
         let txn = Transaction::new_signed_with_payer(
             &decrease_instr,
@@ -198,14 +269,158 @@ pub fn close(
             recent_hash,
         );
 
-        let signature = send_txn(&rpc_client, &txn, true);
+        let mut balance_accounts = vec![user_token_account_0, user_token_account_1];
+        balance_accounts.extend(reward_accounts);
+        let balances_before = get_token_account_balances(rpc_client, &balance_accounts);
+        let signature = send_txn(rpc_client, &txn, true);
         println!("{}", signature);
+        let balances_after = get_token_account_balances(rpc_client, &balance_accounts);
+        let received: Vec<u64> = balances_before
+            .iter()
+            .zip(balances_after)
+            .map(|(before, after)| after.saturating_sub(*before))
+            .collect();
+        let reward_receipts: Vec<(Pubkey, u64, u64)> = reward_receipts
+            .iter()
+            .zip(&received[2..])
+            .map(|((reward_mint, _, pending_reward), reward_received)| {
+                (*reward_mint, *pending_reward, *reward_received)
+            })
+            .collect();
+        journal_close(
+            rpc_client,
+            data_dir,
+            &signature,
+            &pool,
+            &find_position,
+            find_position.liquidity,
+            (received[0], received[1]),
+            (pending_fee_0, pending_fee_1),
+            &reward_receipts,
+        );
+        if let Some(park) = park {
+            park_idle_tokens(
+                rpc_client,
+                payer,
+                park,
+                &[pool.token_mint_0, pool.token_mint_1],
+                &balances_before[..2],
+                data_dir,
+            );
+        }
+        true
     } else {
         // personal position not exist
         println!("personal position exist:{:?}", find_position);
+        false
     }
 }
 
+/// Journals a closed position, or a decreased one when `liquidity` is below the position's, from
+/// what reached the wallet: `received` in the pool token accounts and, for each
+/// `(mint, pending, received)` reward, what its own account received.
+/// Rewards paid in a pool token and the pending fees are carved out of the pool token amounts,
+/// the rest is the withdrawn principal.
+pub(super) fn journal_close(
+    rpc_client: &RpcClient,
+    data_dir: &Path,
+    signature: &Signature,
+    pool: &PoolState,
+    position: &PersonalPositionState,
+    liquidity: u128,
+    received: (u64, u64),
+    pending_fees: (u64, u64),
+    rewards: &[(Pubkey, u64, u64)],
+) {
+    // what reached the wallet is net of any Token-2022 fee withheld on the way out, so the
+    // withdrawals and fees are journaled gross and the withheld fee as an outflow
+    let withheld_fee = get_pool_mints_inverse_fee(
+        rpc_client,
+        pool.token_mint_0,
+        pool.token_mint_1,
+        received.0,
+        received.1,
+    );
+    let mut received_0 = received.0 + withheld_fee.0.transfer_fee;
+    let mut received_1 = received.1 + withheld_fee.1.transfer_fee;
+
+    let operation = if liquidity < position.liquidity {
+        Operation::DecreaseLiquidity
+    } else {
+        Operation::ClosePosition
+    };
+    let mut entry = JournalEntry::new(rpc_client, signature, operation);
+    entry.pool = Some(position.pool_id.to_string());
+    entry.position_nft = Some(position.nft_mint.to_string());
+    entry.tick_lower_index = Some(position.tick_lower_index);
+    entry.tick_upper_index = Some(position.tick_upper_index);
+    entry.liquidity = Some(liquidity);
+    entry.sqrt_price_x64 = Some(pool.sqrt_price_x64);
+    entry.price = Some(sqrt_price_x64_to_price(
+        pool.sqrt_price_x64,
+        pool.mint_decimals_0,
+        pool.mint_decimals_1,
+    ));
+    for (reward_mint, pending_reward, reward_received) in rewards {
+        // a reward paid in a pool token lands in the same account as the principal
+        let (reward_received, decimals) = if *reward_mint == pool.token_mint_0 {
+            let reward_received = (*pending_reward).min(received_0);
+            received_0 -= reward_received;
+            (reward_received, pool.mint_decimals_0)
+        } else if *reward_mint == pool.token_mint_1 {
+            let reward_received = (*pending_reward).min(received_1);
+            received_1 -= reward_received;
+            (reward_received, pool.mint_decimals_1)
+        } else {
+            (*reward_received, get_mint_decimals(rpc_client, reward_mint))
+        };
+        println!(
+            "reward mint:{}, expected:{}, received:{}",
+            reward_mint, pending_reward, reward_received
+        );
+        entry.push_movement(reward_mint, MovementKind::Reward, reward_received, decimals);
+    }
+    let fee_0 = pending_fees.0.min(received_0);
+    let fee_1 = pending_fees.1.min(received_1);
+    entry.push_movement(
+        &pool.token_mint_0,
+        MovementKind::Withdraw,
+        received_0 - fee_0,
+        pool.mint_decimals_0,
+    );
+    entry.push_movement(
+        &pool.token_mint_1,
+        MovementKind::Withdraw,
+        received_1 - fee_1,
+        pool.mint_decimals_1,
+    );
+    entry.push_movement(
+        &pool.token_mint_0,
+        MovementKind::Fee,
+        fee_0,
+        pool.mint_decimals_0,
+    );
+    entry.push_movement(
+        &pool.token_mint_1,
+        MovementKind::Fee,
+        fee_1,
+        pool.mint_decimals_1,
+    );
+    entry.push_movement(
+        &pool.token_mint_0,
+        MovementKind::TransferFee,
+        withheld_fee.0.transfer_fee,
+        pool.mint_decimals_0,
+    );
+    entry.push_movement(
+        &pool.token_mint_1,
+        MovementKind::TransferFee,
+        withheld_fee.1.transfer_fee,
+        pool.mint_decimals_1,
+    );
+    append_journal_entry(data_dir, &entry);
+}
+
 pub fn decrease_liquidity_instr(
     client: Rc<Client<Rc<Keypair>>>,
     raydium_v3_program: Pubkey,
@@ -0,0 +1,319 @@
+use std::{collections::HashMap, path::Path, rc::Rc};
+
+use anchor_client::{Client, Program};
+use anchor_lang::prelude::AccountMeta;
+use raydium_amm_v3::{
+    libraries::liquidity_math,
+    states::{POOL_TICK_ARRAY_BITMAP_SEED, PersonalPositionState, PoolState, TickArrayState},
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::save::park::{ParkConfig, park_idle_tokens};
+
+use super::{
+    batch::{InstructionGroup, send_measured_instruction_groups},
+    close::{close_personal_position_instr, decrease_liquidity_instr, journal_close},
+    growth::update_reward_infos,
+    position::{
+        PositionNftTokenInfo, get_all_personal_positions_by_owner, get_position_pending_amounts,
+    },
+    utils::{
+        amount_with_slippage, get_chain_timestamp, get_pool_mints_transfer_fee,
+        get_token_account_balances,
+    },
+};
+
+/// Compute units requested for each decrease and close pair in a batch.
+const CLOSE_POSITION_COMPUTE_UNITS: u32 = 400_000;
+
+/// A position to close or decrease, with what it is expected to pay into each wallet account.
+/// The estimates only split the measured balance changes of a batch between its positions.
+struct PlannedClose {
+    position: PersonalPositionState,
+    /// Liquidity withdrawn, below `position.liquidity` when the position stays open.
+    liquidity: u128,
+    fee_0: u64,
+    fee_1: u64,
+    /// `(token account, expected amount)` for both pool tokens then each reward.
+    receipts: Vec<(Pubkey, u64)>,
+    /// `(mint, pending amount)` of each reward.
+    rewards: Vec<(Pubkey, u64)>,
+}
+
+/// Closes every position of the payer, or only those in `pool_filter`, batching the decrease and
+/// close instructions and reporting the outcome of each position. With `park`, the tokens received
+/// are then deposited into Save.
+pub fn close_all(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    pool_filter: Option<Pubkey>,
+    slippage: f64,
+    park: Option<&ParkConfig>,
+    data_dir: &Path,
+) {
+    let selected =
+        get_all_personal_positions_by_owner(rpc_client, &payer.pubkey(), &raydium_v3_program)
+            .into_iter()
+            .filter(|(_, position)| pool_filter.is_none_or(|pool_id| position.pool_id == pool_id))
+            .map(|(nft_info, position)| {
+                let liquidity = position.liquidity;
+                (nft_info, position, liquidity)
+            })
+            .collect();
+    close_positions(
+        client,
+        rpc_client,
+        payer,
+        raydium_v3_program,
+        program,
+        selected,
+        slippage,
+        park,
+        data_dir,
+    );
+}
+
+/// Withdraws the given liquidity from each `(nft, position, liquidity)`, batched as `close_all`
+/// does. A position giving up all its liquidity is closed, the others are only decreased and
+/// journaled as such.
+pub fn close_positions(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    selected: Vec<(PositionNftTokenInfo, PersonalPositionState, u128)>,
+    slippage: f64,
+    park: Option<&ParkConfig>,
+    data_dir: &Path,
+) {
+    let curr_timestamp = get_chain_timestamp(rpc_client);
+    let mut pools: HashMap<Pubkey, PoolState> = HashMap::new();
+    let mut planned_closes = Vec::new();
+    let mut instruction_groups = Vec::new();
+    for (nft_info, position, liquidity) in selected {
+        assert!(
+            liquidity <= position.liquidity,
+            "cannot withdraw more liquidity than the position holds"
+        );
+        let pool = *pools
+            .entry(position.pool_id)
+            .or_insert_with(|| program.account(position.pool_id).unwrap());
+        let reward_infos = update_reward_infos(&pool, curr_timestamp);
+        let ((fee_0, fee_1), pending_rewards) = get_position_pending_amounts(
+            rpc_client,
+            &raydium_v3_program,
+            &pool,
+            &reward_infos,
+            &position,
+        );
+        let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+            pool.tick_current,
+            pool.sqrt_price_x64,
+            position.tick_lower_index,
+            position.tick_upper_index,
+            -(liquidity as i128),
+        )
+        .unwrap();
+        let amount_0_with_slippage = amount_with_slippage(amount_0, slippage, false);
+        let amount_1_with_slippage = amount_with_slippage(amount_1, slippage, false);
+        let transfer_fee = get_pool_mints_transfer_fee(
+            rpc_client,
+            pool.token_mint_0,
+            pool.token_mint_1,
+            amount_0_with_slippage,
+            amount_1_with_slippage,
+        );
+        let user_token_account_0 =
+            spl_associated_token_account::get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &pool.token_mint_0,
+                &transfer_fee.0.owner,
+            );
+        let user_token_account_1 =
+            spl_associated_token_account::get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &pool.token_mint_1,
+                &transfer_fee.1.owner,
+            );
+
+        let tickarray_bitmap_extension = Pubkey::find_program_address(
+            &[
+                POOL_TICK_ARRAY_BITMAP_SEED.as_bytes(),
+                position.pool_id.to_bytes().as_ref(),
+            ],
+            &raydium_v3_program,
+        )
+        .0;
+        let mut remaining_accounts = vec![AccountMeta::new(tickarray_bitmap_extension, false)];
+        let mut receipts = vec![
+            (user_token_account_0, amount_0 + fee_0),
+            (user_token_account_1, amount_1 + fee_1),
+        ];
+        let mut rewards = Vec::new();
+        for (reward_info, pending_reward) in reward_infos.iter().zip(pending_rewards) {
+            if reward_info.initialized() {
+                let reward_account =
+                    get_associated_token_address(&payer.pubkey(), &reward_info.token_mint);
+                remaining_accounts.push(AccountMeta::new(reward_info.token_vault, false));
+                remaining_accounts.push(AccountMeta::new(reward_account, false));
+                remaining_accounts.push(AccountMeta::new(reward_info.token_mint, false));
+                receipts.push((reward_account, pending_reward));
+                rewards.push((reward_info.token_mint, pending_reward));
+            }
+        }
+
+        let mut instructions = decrease_liquidity_instr(
+            client.clone(),
+            raydium_v3_program,
+            position.pool_id,
+            pool.token_vault_0,
+            pool.token_vault_1,
+            pool.token_mint_0,
+            pool.token_mint_1,
+            position.nft_mint,
+            nft_info.key,
+            user_token_account_0,
+            user_token_account_1,
+            remaining_accounts,
+            liquidity,
+            amount_0_with_slippage
+                .checked_sub(transfer_fee.0.transfer_fee)
+                .unwrap(),
+            amount_1_with_slippage
+                .checked_sub(transfer_fee.1.transfer_fee)
+                .unwrap(),
+            position.tick_lower_index,
+            position.tick_upper_index,
+            TickArrayState::get_array_start_index(position.tick_lower_index, pool.tick_spacing),
+            TickArrayState::get_array_start_index(position.tick_upper_index, pool.tick_spacing),
+        );
+        if liquidity == position.liquidity {
+            instructions.extend(close_personal_position_instr(
+                client.clone(),
+                raydium_v3_program,
+                position.nft_mint,
+                nft_info.key,
+                nft_info.program,
+            ));
+        }
+        instruction_groups.push(InstructionGroup {
+            instructions,
+            compute_units: CLOSE_POSITION_COMPUTE_UNITS,
+            signers: Vec::new(),
+        });
+        planned_closes.push(PlannedClose {
+            position,
+            liquidity,
+            fee_0,
+            fee_1,
+            receipts,
+            rewards,
+        });
+    }
+    if planned_closes.is_empty() {
+        println!("no positions to close");
+        return;
+    }
+
+    // the balances before the batch tell what the closes paid out
+    let parked_balances = park.map(|_| {
+        let mut mints: Vec<Pubkey> = pools
+            .values()
+            .flat_map(|pool| [pool.token_mint_0, pool.token_mint_1])
+            .collect();
+        mints.sort();
+        mints.dedup();
+        let token_accounts: Vec<Pubkey> = mints
+            .iter()
+            .map(|mint| get_associated_token_address(&payer.pubkey(), mint))
+            .collect();
+        let balances_before = get_token_account_balances(rpc_client, &token_accounts);
+        (mints, balances_before)
+    });
+    let mut balance_accounts: Vec<Pubkey> = planned_closes
+        .iter()
+        .flat_map(|planned_close| planned_close.receipts.iter().map(|(account, _)| *account))
+        .collect();
+    balance_accounts.sort();
+    balance_accounts.dedup();
+    let outcomes = send_measured_instruction_groups(
+        rpc_client,
+        payer,
+        &instruction_groups,
+        &balance_accounts,
+        |signature, batch, balances_before, balances_after| {
+            // each account's balance change is split between the positions of the batch by what
+            // they were expected to pay into it
+            let received = |account: &Pubkey, expected: u64| -> u64 {
+                let index = balance_accounts.binary_search(account).unwrap();
+                let delta = balances_after[index].saturating_sub(balances_before[index]);
+                let expected_total: u64 = batch
+                    .iter()
+                    .flat_map(|index| &planned_closes[*index].receipts)
+                    .filter(|(receipt_account, _)| receipt_account == account)
+                    .map(|(_, amount)| *amount)
+                    .sum();
+                if expected_total == 0 {
+                    0
+                } else {
+                    (delta as u128 * expected as u128 / expected_total as u128) as u64
+                }
+            };
+            for index in batch {
+                let planned_close = &planned_closes[*index];
+                let pool = &pools[&planned_close.position.pool_id];
+                let receipts: Vec<u64> = planned_close
+                    .receipts
+                    .iter()
+                    .map(|(account, expected)| received(account, *expected))
+                    .collect();
+                let rewards: Vec<(Pubkey, u64, u64)> = planned_close
+                    .rewards
+                    .iter()
+                    .zip(&receipts[2..])
+                    .map(|((reward_mint, pending_reward), reward_received)| {
+                        (*reward_mint, *pending_reward, *reward_received)
+                    })
+                    .collect();
+                journal_close(
+                    rpc_client,
+                    data_dir,
+                    signature,
+                    pool,
+                    &planned_close.position,
+                    planned_close.liquidity,
+                    (receipts[0], receipts[1]),
+                    (planned_close.fee_0, planned_close.fee_1),
+                    &rewards,
+                );
+            }
+        },
+    );
+    for (planned_close, outcome) in planned_closes.iter().zip(outcomes) {
+        match outcome {
+            Ok(signature) if planned_close.liquidity < planned_close.position.liquidity => {
+                println!(
+                    "position nft_mint:{}, decreased by liquidity:{}, signature:{}",
+                    planned_close.position.nft_mint, planned_close.liquidity, signature
+                )
+            }
+            Ok(signature) => println!(
+                "position nft_mint:{}, closed, signature:{}",
+                planned_close.position.nft_mint, signature
+            ),
+            Err(err) => println!(
+                "position nft_mint:{}, failed: {}",
+                planned_close.position.nft_mint, err
+            ),
+        }
+    }
+    if let (Some(park), Some((mints, balances_before))) = (park, parked_balances) {
+        park_idle_tokens(rpc_client, payer, park, &mints, &balances_before, data_dir);
+    }
+}
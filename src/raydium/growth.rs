@@ -0,0 +1,240 @@
+//! Off-chain replicas of Raydium's per-position fee and reward accounting, so pending amounts
+//! can be shown without sending an update instruction.
+
+use raydium_amm_v3::{
+    libraries::{big_num::U256, fixed_point_64},
+    states::{PersonalPositionState, PoolState, REWARD_NUM, RewardInfo, TickState},
+};
+
+/// Fee growth per unit of liquidity between two ticks, mirroring `get_fee_growth_inside`.
+pub fn get_fee_growth_inside(
+    tick_lower: &TickState,
+    tick_upper: &TickState,
+    tick_current: i32,
+    fee_growth_global_0_x64: u128,
+    fee_growth_global_1_x64: u128,
+) -> (u128, u128) {
+    let (fee_growth_below_0_x64, fee_growth_below_1_x64) = if tick_current >= tick_lower.tick {
+        (
+            tick_lower.fee_growth_outside_0_x64,
+            tick_lower.fee_growth_outside_1_x64,
+        )
+    } else {
+        (
+            fee_growth_global_0_x64.wrapping_sub(tick_lower.fee_growth_outside_0_x64),
+            fee_growth_global_1_x64.wrapping_sub(tick_lower.fee_growth_outside_1_x64),
+        )
+    };
+    let (fee_growth_above_0_x64, fee_growth_above_1_x64) = if tick_current < tick_upper.tick {
+        (
+            tick_upper.fee_growth_outside_0_x64,
+            tick_upper.fee_growth_outside_1_x64,
+        )
+    } else {
+        (
+            fee_growth_global_0_x64.wrapping_sub(tick_upper.fee_growth_outside_0_x64),
+            fee_growth_global_1_x64.wrapping_sub(tick_upper.fee_growth_outside_1_x64),
+        )
+    };
+    (
+        fee_growth_global_0_x64
+            .wrapping_sub(fee_growth_below_0_x64)
+            .wrapping_sub(fee_growth_above_0_x64),
+        fee_growth_global_1_x64
+            .wrapping_sub(fee_growth_below_1_x64)
+            .wrapping_sub(fee_growth_above_1_x64),
+    )
+}
+
+/// Fees the position could collect now: the owed amounts plus growth since its last update.
+pub fn get_pending_fees(
+    position: &PersonalPositionState,
+    fee_growth_inside_0_x64: u128,
+    fee_growth_inside_1_x64: u128,
+) -> (u64, u64) {
+    let fee_growth_delta_0 =
+        fee_growth_inside_0_x64.wrapping_sub(position.fee_growth_inside_0_last_x64);
+    let fee_growth_delta_1 =
+        fee_growth_inside_1_x64.wrapping_sub(position.fee_growth_inside_1_last_x64);
+    (
+        position
+            .token_fees_owed_0
+            .checked_add(growth_to_amount(fee_growth_delta_0, position.liquidity))
+            .unwrap(),
+        position
+            .token_fees_owed_1
+            .checked_add(growth_to_amount(fee_growth_delta_1, position.liquidity))
+            .unwrap(),
+    )
+}
+
+/// Brings the pool's reward growth up to `curr_timestamp`, as `PoolState::update_reward_infos`
+/// does on-chain before any position is touched.
+pub fn update_reward_infos(pool: &PoolState, curr_timestamp: u64) -> [RewardInfo; REWARD_NUM] {
+    let pool_liquidity = pool.liquidity;
+    let mut reward_infos = pool.reward_infos;
+    for reward_info in reward_infos.iter_mut() {
+        if !reward_info.initialized() || curr_timestamp <= reward_info.open_time {
+            continue;
+        }
+        let latest_update_timestamp = curr_timestamp.min(reward_info.end_time);
+        if pool_liquidity != 0 && latest_update_timestamp > reward_info.last_update_time {
+            let time_delta = latest_update_timestamp - reward_info.last_update_time;
+            let reward_growth_delta = U256::from(time_delta)
+                * U256::from(reward_info.emissions_per_second_x64)
+                / U256::from(pool_liquidity);
+            reward_info.reward_growth_global_x64 = reward_info
+                .reward_growth_global_x64
+                .wrapping_add(reward_growth_delta.as_u128());
+        }
+        reward_info.last_update_time = latest_update_timestamp;
+    }
+    reward_infos
+}
+
+/// Reward growth per unit of liquidity between two ticks, mirroring `get_reward_growths_inside`.
+pub fn get_reward_growths_inside(
+    tick_lower: &TickState,
+    tick_upper: &TickState,
+    tick_current: i32,
+    reward_infos: &[RewardInfo; REWARD_NUM],
+) -> [u128; REWARD_NUM] {
+    let lower_outside = tick_lower.reward_growths_outside_x64;
+    let upper_outside = tick_upper.reward_growths_outside_x64;
+    let mut reward_growths_inside = [0u128; REWARD_NUM];
+    for i in 0..REWARD_NUM {
+        if !reward_infos[i].initialized() {
+            continue;
+        }
+        let reward_growth_global = reward_infos[i].reward_growth_global_x64;
+        let reward_growth_below = if tick_current >= tick_lower.tick {
+            lower_outside[i]
+        } else {
+            reward_growth_global.wrapping_sub(lower_outside[i])
+        };
+        let reward_growth_above = if tick_current < tick_upper.tick {
+            upper_outside[i]
+        } else {
+            reward_growth_global.wrapping_sub(upper_outside[i])
+        };
+        reward_growths_inside[i] = reward_growth_global
+            .wrapping_sub(reward_growth_below)
+            .wrapping_sub(reward_growth_above);
+    }
+    reward_growths_inside
+}
+
+/// Rewards the position could collect now: the owed amount plus growth since its last update.
+pub fn get_pending_rewards(
+    position: &PersonalPositionState,
+    reward_growths_inside: &[u128; REWARD_NUM],
+) -> [u64; REWARD_NUM] {
+    let mut pending_rewards = [0u64; REWARD_NUM];
+    for i in 0..REWARD_NUM {
+        let position_reward = position.reward_infos[i];
+        let growth_delta =
+            reward_growths_inside[i].wrapping_sub(position_reward.growth_inside_last_x64);
+        pending_rewards[i] = position_reward
+            .reward_amount_owed
+            .checked_add(growth_to_amount(growth_delta, position.liquidity))
+            .unwrap();
+    }
+    pending_rewards
+}
+
+/// Converts a Q64.64 growth per unit of liquidity into a token amount, rounding down.
+fn growth_to_amount(growth_delta_x64: u128, liquidity: u128) -> u64 {
+    (U256::from(growth_delta_x64) * U256::from(liquidity) / U256::from(fixed_point_64::Q64))
+        .as_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::pubkey::Pubkey;
+
+    fn tick(
+        tick: i32,
+        fee_growth_outside_0_x64: u128,
+        fee_growth_outside_1_x64: u128,
+    ) -> TickState {
+        let mut tick_state = TickState::default();
+        tick_state.tick = tick;
+        tick_state.fee_growth_outside_0_x64 = fee_growth_outside_0_x64;
+        tick_state.fee_growth_outside_1_x64 = fee_growth_outside_1_x64;
+        tick_state
+    }
+
+    #[test]
+    fn fee_growth_inside_depends_on_the_current_tick() {
+        let tick_lower = tick(-100, 30, 40);
+        let tick_upper = tick(100, 10, 20);
+        // in range, the growth outside both ticks is below and above the current tick
+        assert_eq!(
+            get_fee_growth_inside(&tick_lower, &tick_upper, 0, 1_000, 2_000),
+            (960, 1_940)
+        );
+        // below the range, the lower tick's outside growth is the one above the current tick
+        assert_eq!(
+            get_fee_growth_inside(&tick_lower, &tick_upper, -200, 1_000, 2_000),
+            (20, 20)
+        );
+        // the subtractions wrap around like the program's
+        assert_eq!(
+            get_fee_growth_inside(&tick_lower, &tick_upper, 200, 1_000, 2_000),
+            (0u128.wrapping_sub(20), 0u128.wrapping_sub(20))
+        );
+    }
+
+    #[test]
+    fn pending_fees_add_growth_since_the_last_update() {
+        let mut position = PersonalPositionState::default();
+        position.liquidity = 1_000;
+        position.fee_growth_inside_0_last_x64 = fixed_point_64::Q64;
+        position.token_fees_owed_0 = 5;
+        position.token_fees_owed_1 = 7;
+        assert_eq!(
+            get_pending_fees(&position, 3 * fixed_point_64::Q64, fixed_point_64::Q64 / 2),
+            (2_005, 507)
+        );
+    }
+
+    #[test]
+    fn reward_growth_accrues_until_the_end_time() {
+        let mut reward_info = RewardInfo::default();
+        reward_info.token_mint = Pubkey::new_unique();
+        reward_info.open_time = 100;
+        reward_info.end_time = 200;
+        reward_info.last_update_time = 150;
+        reward_info.emissions_per_second_x64 = 10 * fixed_point_64::Q64;
+        let mut pool = PoolState::default();
+        pool.liquidity = 1_000;
+        pool.reward_infos = [reward_info, RewardInfo::default(), RewardInfo::default()];
+        let reward_infos = update_reward_infos(&pool, 300);
+        // the fields of the packed reward infos are copied out before comparing
+        let (last_update_time, reward_growth_global_x64, uninitialized_growth_x64) = (
+            reward_infos[0].last_update_time,
+            reward_infos[0].reward_growth_global_x64,
+            reward_infos[1].reward_growth_global_x64,
+        );
+        assert_eq!(last_update_time, 200);
+        // 50 seconds at 10 per second shared by 1000 liquidity
+        assert_eq!(
+            reward_growth_global_x64,
+            50 * 10 * fixed_point_64::Q64 / 1_000
+        );
+        // uninitialized rewards are left alone
+        assert_eq!(uninitialized_growth_x64, 0);
+
+        let mut position = PersonalPositionState::default();
+        position.liquidity = 100;
+        let tick_lower = tick(-100, 0, 0);
+        let tick_upper = tick(100, 0, 0);
+        let reward_growths_inside =
+            get_reward_growths_inside(&tick_lower, &tick_upper, 0, &reward_infos);
+        assert_eq!(
+            get_pending_rewards(&position, &reward_growths_inside)[0],
+            50
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Leveraged liquidity: one pool token deposited as Save collateral, the other borrowed against
+//! it, and both provided to the pool.
+
+use std::{collections::HashMap, path::Path, rc::Rc};
+
+use anchor_client::{Client, Program};
+use raydium_amm_v3::{libraries::liquidity_math, states::PoolState};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::save::{
+    borrow::{borrow, check_borrow, repay, try_repay},
+    deposit::deposit,
+    obligation::{ObligationHealth, compute_health, find_obligation},
+    oracle::refresh_market_prices,
+    state::{Reserve, get_reserves},
+    withdraw::{WithdrawAmount, withdraw},
+};
+
+use super::{
+    close::close,
+    open::{LiquidityInput, open},
+    position::get_all_personal_positions_by_owner,
+    range::RangeInput,
+    utils::get_token_account_balances,
+};
+
+/// The pool mint that is not `collateral_mint`, which is borrowed.
+fn borrowed_mint(pool: &PoolState, collateral_mint: &Pubkey) -> Pubkey {
+    if *collateral_mint == pool.token_mint_0 {
+        pool.token_mint_1
+    } else if *collateral_mint == pool.token_mint_1 {
+        pool.token_mint_0
+    } else {
+        panic!("collateral mint must be one of the pool mints")
+    }
+}
+
+/// Deposits `collateral_amount` of `collateral_mint` as collateral, borrows `borrow_amount` of
+/// the other pool mint and opens a position with the borrowed tokens and `pair_amount` of the
+/// collateral mint. Refused up front when the borrow would take the obligation, with the new
+/// collateral, above `max_ltv`; a step that fails stops the ones after it. When the open fails,
+/// the borrow is repaid from the wallet and the commands finishing the unwind are printed.
+pub fn leverage_open(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    pool_id_account: Pubkey,
+    tickarray_bitmap_extension: Pubkey,
+    save_program: Pubkey,
+    lending_market: Pubkey,
+    collateral_mint: Pubkey,
+    collateral_amount: u64,
+    borrow_amount: u64,
+    pair_amount: u64,
+    range_input: RangeInput,
+    max_ltv: f64,
+    slippage: f64,
+    invert_price: bool,
+    data_dir: &Path,
+) {
+    let pool: PoolState = program.account(pool_id_account).unwrap();
+    let borrow_mint = borrowed_mint(&pool, &collateral_mint);
+    let mut reserves = get_reserves(&rpc_client, &save_program, &lending_market);
+    let find_reserve = |reserves: &[(Pubkey, Reserve)], mint: &Pubkey| {
+        *reserves
+            .iter()
+            .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+            .expect("no reserve for the pool mint in the lending market")
+    };
+    let obligation = find_obligation(&rpc_client, &save_program, &lending_market, &payer.pubkey());
+    let mut required = obligation
+        .as_ref()
+        .map_or_else(Vec::new, |(_, obligation)| obligation.reserve_keys());
+    required.push(find_reserve(&reserves, &collateral_mint).0);
+    required.push(find_reserve(&reserves, &borrow_mint).0);
+    if let Err(err) = refresh_market_prices(&rpc_client, &mut reserves, &required) {
+        println!("refusing to open: {}", err);
+        return;
+    }
+    let (collateral_reserve_key, collateral_reserve) = find_reserve(&reserves, &collateral_mint);
+    let (_, borrow_reserve) = find_reserve(&reserves, &borrow_mint);
+
+    let held = get_token_account_balances(
+        &rpc_client,
+        &[get_associated_token_address(
+            &payer.pubkey(),
+            &collateral_mint,
+        )],
+    )[0];
+    if held < collateral_amount + pair_amount {
+        println!(
+            "refusing to open: the wallet holds {} of mint:{}, {} needed",
+            held,
+            collateral_mint,
+            collateral_amount + pair_amount
+        );
+        return;
+    }
+    // the obligation as it will be once the collateral is in, before anything is sent
+    let slot = rpc_client.get_slot().unwrap();
+    let reserve_map: HashMap<Pubkey, Reserve> = reserves.iter().cloned().collect();
+    let mut health = obligation.map_or_else(ObligationHealth::default, |(_, obligation)| {
+        compute_health(&obligation, &reserve_map, slot)
+    });
+    health.push_collateral(
+        collateral_reserve_key,
+        &collateral_reserve,
+        collateral_reserve.liquidity_to_collateral(collateral_amount),
+    );
+    if let Err(err) = check_borrow(&health, &borrow_reserve, borrow_amount, max_ltv) {
+        println!("refusing to open: {}", err);
+        return;
+    }
+    if borrow_amount > borrow_reserve.available_amount {
+        println!(
+            "refusing to open: the borrow reserve only has {} available",
+            borrow_reserve.available_amount
+        );
+        return;
+    }
+
+    let deposited = deposit(
+        &rpc_client,
+        &payer,
+        &save_program,
+        &lending_market,
+        &collateral_mint,
+        collateral_amount,
+        true,
+        None,
+        data_dir,
+    );
+    if deposited == 0 {
+        println!("no collateral deposited, stopping before the borrow");
+        return;
+    }
+    let borrowed = borrow(
+        &rpc_client,
+        &payer,
+        &save_program,
+        &lending_market,
+        &borrow_mint,
+        borrow_amount,
+        max_ltv,
+        data_dir,
+    );
+    if borrowed == 0 {
+        println!(
+            "nothing borrowed, the collateral stays in the obligation, take it back with `withdraw --collateral`"
+        );
+        return;
+    }
+    let (amount_0, amount_1) = if collateral_mint == pool.token_mint_0 {
+        (pair_amount, borrowed)
+    } else {
+        (borrowed, pair_amount)
+    };
+    let opened = open(
+        client.clone(),
+        &rpc_client,
+        &payer,
+        raydium_v3_program,
+        client.program(raydium_v3_program).unwrap(),
+        pool_id_account,
+        tickarray_bitmap_extension,
+        pool.token_mint_0,
+        pool.token_mint_1,
+        &[(
+            range_input,
+            LiquidityInput::BothAmounts { amount_0, amount_1 },
+        )],
+        slippage,
+        invert_price,
+        false,
+        false,
+        false,
+        None,
+        data_dir,
+    );
+    if opened == 0 {
+        // the deposit and borrow are journaled, only the open is missing
+        println!(
+            "the open failed after depositing {} of mint:{} as collateral and borrowing {} of mint:{}",
+            deposited, collateral_mint, borrowed, borrow_mint
+        );
+        println!(
+            "to finish unwinding, repay what is left with `repay {} --all` and take the collateral back with `withdraw {} --collateral --all`",
+            borrow_mint, collateral_mint
+        );
+        println!("repaying the borrow from the wallet");
+        if let Err(err) = try_repay(
+            &rpc_client,
+            &payer,
+            &save_program,
+            &lending_market,
+            &borrow_mint,
+            None,
+            data_dir,
+        ) {
+            println!("repay failed: {}, the borrow is still owed", err);
+        }
+    }
+    print_combined_health(
+        &rpc_client,
+        &payer.pubkey(),
+        &raydium_v3_program,
+        &program,
+        &pool_id_account,
+        &save_program,
+        &lending_market,
+    );
+}
+
+/// Unwinds a leveraged position in reverse: closes the position at the given prices, repays the
+/// borrowed mint and withdraws the collateral. The collateral stays deposited while any of the
+/// debt is left, and its withdrawal is refused when other borrows would break `max_ltv`.
+pub fn leverage_close(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    pool_id_account: Pubkey,
+    tickarray_bitmap_extension: Pubkey,
+    save_program: Pubkey,
+    lending_market: Pubkey,
+    collateral_mint: Pubkey,
+    tick_lower_price: f64,
+    tick_upper_price: f64,
+    max_ltv: f64,
+    slippage: f64,
+    data_dir: &Path,
+) {
+    let pool: PoolState = program.account(pool_id_account).unwrap();
+    let borrow_mint = borrowed_mint(&pool, &collateral_mint);
+    let closed = close(
+        client.clone(),
+        &rpc_client,
+        &payer,
+        raydium_v3_program,
+        client.program(raydium_v3_program).unwrap(),
+        pool_id_account,
+        tickarray_bitmap_extension,
+        pool.token_mint_0,
+        pool.token_mint_1,
+        tick_lower_price,
+        tick_upper_price,
+        slippage,
+        None,
+        data_dir,
+    );
+    if !closed {
+        // the borrow was not spent on this position, so nothing is repaid from the wallet
+        println!(
+            "no position between {} and {} in pool:{}, nothing repaid or withdrawn",
+            tick_lower_price, tick_upper_price, pool_id_account
+        );
+        return;
+    }
+    repay(
+        &rpc_client,
+        &payer,
+        &save_program,
+        &lending_market,
+        &borrow_mint,
+        None,
+        data_dir,
+    );
+
+    let reserves = get_reserves(&rpc_client, &save_program, &lending_market);
+    let (borrow_reserve_key, _) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == borrow_mint)
+        .expect("no reserve for the pool mint in the lending market");
+    let (_, obligation) =
+        find_obligation(&rpc_client, &save_program, &lending_market, &payer.pubkey())
+            .expect("no obligation in the lending market");
+    if obligation
+        .borrows
+        .iter()
+        .any(|borrow| borrow.borrow_reserve == borrow_reserve_key)
+    {
+        println!(
+            "mint:{} is still owed, keeping the collateral deposited until it is repaid",
+            borrow_mint
+        );
+    } else {
+        withdraw(
+            &rpc_client,
+            &payer,
+            &save_program,
+            &lending_market,
+            &collateral_mint,
+            WithdrawAmount::All,
+            true,
+            max_ltv,
+            None,
+            data_dir,
+        );
+    }
+    print_combined_health(
+        &rpc_client,
+        &payer.pubkey(),
+        &raydium_v3_program,
+        &program,
+        &pool_id_account,
+        &save_program,
+        &lending_market,
+    );
+}
+
+/// Prints the owner's obligation together with their positions in the pool, all valued at the
+/// reserves' oracle prices: the equity left after the borrows, the leverage and the obligation's
+/// distance to liquidation.
+fn print_combined_health(
+    rpc_client: &RpcClient,
+    owner: &Pubkey,
+    raydium_v3_program: &Pubkey,
+    program: &Program<Rc<Keypair>>,
+    pool_id_account: &Pubkey,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+) {
+    let pool: PoolState = program.account(*pool_id_account).unwrap();
+    let mut reserves = get_reserves(rpc_client, save_program, lending_market);
+    let obligation = find_obligation(rpc_client, save_program, lending_market, owner);
+    let required = obligation
+        .as_ref()
+        .map_or_else(Vec::new, |(_, obligation)| obligation.reserve_keys());
+    if let Err(err) = refresh_market_prices(rpc_client, &mut reserves, &required) {
+        println!("cannot value the obligation: {}", err);
+        return;
+    }
+    let reserves: HashMap<Pubkey, Reserve> = reserves.into_iter().collect();
+    let slot = rpc_client.get_slot().unwrap();
+    let health = obligation.map_or_else(ObligationHealth::default, |(_, obligation)| {
+        compute_health(&obligation, &reserves, slot)
+    });
+    let (mut amount_0, mut amount_1) = (0u64, 0u64);
+    for (_, position) in get_all_personal_positions_by_owner(rpc_client, owner, raydium_v3_program)
+        .iter()
+        .filter(|(_, position)| position.pool_id == *pool_id_account)
+    {
+        let (position_amount_0, position_amount_1) = liquidity_math::get_delta_amounts_signed(
+            pool.tick_current,
+            pool.sqrt_price_x64,
+            position.tick_lower_index,
+            position.tick_upper_index,
+            -(position.liquidity as i128),
+        )
+        .unwrap();
+        amount_0 += position_amount_0;
+        amount_1 += position_amount_1;
+    }
+    let value = |mint: &Pubkey, amount: u64, decimals: u8| {
+        reserves
+            .values()
+            .find(|reserve| reserve.liquidity_mint == *mint)
+            .map_or(0.0, |reserve| {
+                amount as f64 / 10f64.powi(decimals as i32) * reserve.market_price()
+            })
+    };
+    let lp_value = value(&pool.token_mint_0, amount_0, pool.mint_decimals_0)
+        + value(&pool.token_mint_1, amount_1, pool.mint_decimals_1);
+    let equity = health.deposited_value + lp_value - health.borrowed_value;
+    println!(
+        "positions amount_0:{}, amount_1:{}, value:{:.6}",
+        amount_0, amount_1, lp_value
+    );
+    println!(
+        "collateral:{:.6}, borrowed:{:.6}, equity:{:.6}, leverage:{:.2}x",
+        health.deposited_value,
+        health.borrowed_value,
+        equity,
+        if equity > 0.0 {
+            (health.deposited_value + lp_value) / equity
+        } else {
+            0.0
+        }
+    );
+    println!(
+        "ltv:{:.2}%, liquidation ltv:{:.2}%, collateral drop to liquidation:{:.2}%",
+        health.loan_to_value() * 100.0,
+        health.liquidation_loan_to_value() * 100.0,
+        health.distance_to_liquidation() * 100.0
+    );
+}
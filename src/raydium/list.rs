@@ -0,0 +1,87 @@
+use std::rc::Rc;
+
+use anchor_client::Program;
+use raydium_amm_v3::libraries::{liquidity_math, tick_math};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+
+use super::{
+    growth::update_reward_infos,
+    position::{get_all_personal_positions_by_owner, get_position_pending_amounts},
+    utils::{get_chain_timestamp, orient_price_range, sqrt_price_x64_to_price},
+};
+
+pub fn list_positions(
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    pool_id_account: Pubkey,
+    invert_price: bool,
+) {
+    let pool: raydium_amm_v3::states::PoolState = program.account(pool_id_account).unwrap();
+    let tick_current = pool.tick_current;
+    let reward_infos = update_reward_infos(&pool, get_chain_timestamp(&rpc_client));
+
+    let personal_positions =
+        get_all_personal_positions_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program);
+    for (_, position) in personal_positions
+        .iter()
+        .filter(|(_, position)| position.pool_id == pool_id_account)
+    {
+        let (lower_price, upper_price) = orient_price_range(
+            sqrt_price_x64_to_price(
+                tick_math::get_sqrt_price_at_tick(position.tick_lower_index).unwrap(),
+                pool.mint_decimals_0,
+                pool.mint_decimals_1,
+            ),
+            sqrt_price_x64_to_price(
+                tick_math::get_sqrt_price_at_tick(position.tick_upper_index).unwrap(),
+                pool.mint_decimals_0,
+                pool.mint_decimals_1,
+            ),
+            invert_price,
+        );
+        let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+            tick_current,
+            pool.sqrt_price_x64,
+            position.tick_lower_index,
+            position.tick_upper_index,
+            -(position.liquidity as i128),
+        )
+        .unwrap();
+        println!(
+            "position nft_mint:{}, tick_lower_index:{}, tick_upper_index:{}, lower price:{}, upper price:{}, in range:{}",
+            position.nft_mint,
+            position.tick_lower_index,
+            position.tick_upper_index,
+            lower_price,
+            upper_price,
+            position.tick_lower_index <= tick_current && tick_current < position.tick_upper_index
+        );
+        println!(
+            "    liquidity:{}, amount_0:{}, amount_1:{}",
+            position.liquidity, amount_0, amount_1
+        );
+
+        let ((pending_fee_0, pending_fee_1), pending_rewards) = get_position_pending_amounts(
+            &rpc_client,
+            &raydium_v3_program,
+            &pool,
+            &reward_infos,
+            position,
+        );
+        println!(
+            "    pending fee_0:{}, fee_1:{}",
+            pending_fee_0, pending_fee_1
+        );
+        for (reward_info, pending_reward) in reward_infos.iter().zip(pending_rewards) {
+            if reward_info.initialized() {
+                println!(
+                    "    pending reward mint:{}, amount:{}",
+                    reward_info.token_mint, pending_reward
+                );
+            }
+        }
+    }
+}
@@ -5,25 +5,49 @@ use raydium_amm_v3::states::POOL_TICK_ARRAY_BITMAP_SEED;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{pubkey::Pubkey, signature::Keypair};
 
-use crate::cli::{self, Cli};
+use crate::{
+    cli::{self, Cli},
+    save::{park::ParkConfig, state::get_reserves},
+};
 
+pub mod allocate;
+pub mod backtest;
+pub mod batch;
+pub mod cleanup;
 pub mod close;
+pub mod close_all;
+pub mod growth;
+pub mod leverage;
+pub mod list;
 pub mod open;
+pub mod pnl;
 pub mod position;
+pub mod range;
+pub mod timeline;
+pub mod transfer;
 pub mod utils;
 
 pub fn run(args: &Cli, rpc_client: RpcClient, anchor_client: Client<Rc<Keypair>>, payer: Keypair) {
     let program = anchor_client.program(args.raydium_v3_program).unwrap();
 
-    let pool_id_account = {
-        let mut mint0 = args.mint0;
-        let mut mint1 = args.mint1;
-        if mint0 > mint1 {
-            let temp_mint = mint0;
-            mint0 = mint1;
-            mint1 = temp_mint;
-        }
+    let mut mint0 = args.mint0;
+    let mut mint1 = args.mint1;
+    if mint0 > mint1 {
+        let temp_mint = mint0;
+        mint0 = mint1;
+        mint1 = temp_mint;
+    }
+    if let Some(quote_mint) = args.quote_mint {
+        assert!(
+            quote_mint == mint0 || quote_mint == mint1,
+            "quote mint must be one of the pool mints"
+        );
+    }
+    // prices are token_1 per token_0 unless the user asks for the other orientation
+    let invert_price = args.invert_price || args.quote_mint == Some(mint0);
+    let data_dir = crate::expand_home_dir(&args.data_dir);
 
+    let pool_id_account = {
         let (amm_config_key, __bump) = Pubkey::find_program_address(
             &[
                 raydium_amm_v3::states::AMM_CONFIG_SEED.as_bytes(),
@@ -53,35 +77,292 @@ pub fn run(args: &Cli, rpc_client: RpcClient, anchor_client: Client<Rc<Keypair>>
     )
     .0;
 
+    let park = match args.command {
+        cli::CommandsName::ClosePosition {
+            park: true,
+            ref working_reserve,
+            ..
+        } => Some(ParkConfig {
+            save_program: args.save_program,
+            lending_market: args.lending_market(),
+            working_reserves: working_reserve.clone(),
+            pool: None,
+        }),
+        _ => None,
+    };
+
     match args.command {
         cli::CommandsName::OpenPosition {
             tick_lower_price,
             tick_upper_price,
             is_base_0,
-            input_amount,
+            amount,
+            amount_0,
+            amount_1,
+            ref range,
+            ref ladder,
+            preview,
+            with_metadata,
+            legacy_nft,
+            from_save,
         } => {
+            let liquidity_input = match (amount, amount_0, amount_1) {
+                (_, Some(amount_0), Some(amount_1)) => {
+                    open::LiquidityInput::BothAmounts { amount_0, amount_1 }
+                }
+                (Some(amount), _, _) => open::LiquidityInput::SingleAmount { is_base_0, amount },
+                _ => panic!("--amount or both --amount-0 and --amount-1 must be provided"),
+            };
+            let range_input = match (
+                tick_lower_price,
+                tick_upper_price,
+                range.around_current(invert_price),
+            ) {
+                (Some(lower), Some(upper), _) => {
+                    let (lower, upper) = utils::orient_price_range(lower, upper, invert_price);
+                    Some(range::RangeInput::Prices { lower, upper })
+                }
+                (_, _, Some(range_input)) => Some(range_input),
+                _ if !ladder.is_empty() => None,
+                _ => panic!("tick prices, a --range-* width or --ladder must be provided"),
+            };
+            let position_inputs: Vec<(range::RangeInput, open::LiquidityInput)> = match range_input
+            {
+                Some(range_input) => vec![(range_input, liquidity_input)],
+                None => {
+                    // the budget is split between the rungs by weight
+                    let total_weight: f64 = ladder.iter().map(|rung| rung.weight).sum();
+                    ladder
+                        .iter()
+                        .map(|rung| {
+                            let range_input = match rung.range {
+                                range::RangeInput::Prices { lower, upper } => {
+                                    let (lower, upper) =
+                                        utils::orient_price_range(lower, upper, invert_price);
+                                    range::RangeInput::Prices { lower, upper }
+                                }
+                                around_current => around_current,
+                            };
+                            (
+                                range_input,
+                                liquidity_input.scaled(rung.weight / total_weight),
+                            )
+                        })
+                        .collect()
+                }
+            };
             open::open(
-                anchor_client,
+                Rc::new(anchor_client),
+                &rpc_client,
+                &payer,
+                args.raydium_v3_program,
+                program,
+                pool_id_account,
+                tickarray_bitmap_extension,
+                mint0,
+                mint1,
+                &position_inputs,
+                args.slippage,
+                invert_price,
+                preview,
+                with_metadata,
+                legacy_nft,
+                from_save.then(|| (args.save_program, args.lending_market())),
+                &data_dir,
+            );
+        }
+        cli::CommandsName::ListPositions => {
+            list::list_positions(
                 rpc_client,
                 payer,
                 args.raydium_v3_program,
                 program,
                 pool_id_account,
+                invert_price,
+            );
+        }
+        cli::CommandsName::Pnl => {
+            pnl::pnl_report(
+                rpc_client,
+                payer,
+                args.raydium_v3_program,
+                program,
+                pool_id_account,
+                invert_price,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::Backtest {
+            ref input,
+            ref widths_pct,
+            ref widths_ticks,
+            capital,
+            rebalance_after,
+            tx_fee_lamports,
+            pool_liquidity,
+        } => {
+            let widths: Vec<range::RangeWidth> = widths_pct
+                .iter()
+                .map(|pct| range::RangeWidth::Percent(*pct))
+                .chain(
+                    widths_ticks
+                        .iter()
+                        .map(|ticks| range::RangeWidth::Ticks(*ticks)),
+                )
+                .collect();
+            backtest::backtest(
+                program,
+                pool_id_account,
+                input,
+                &widths,
+                capital,
+                rebalance_after,
+                tx_fee_lamports,
+                pool_liquidity,
+                invert_price,
+            );
+        }
+        cli::CommandsName::PositionTimeline { position_nft } => {
+            timeline::position_timeline(rpc_client, args.raydium_v3_program, position_nft);
+        }
+        cli::CommandsName::TransferPosition {
+            position_nft,
+            destination,
+        } => {
+            transfer::transfer_position(
+                rpc_client,
+                payer,
+                args.raydium_v3_program,
+                position_nft,
+                destination,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::Cleanup { dry_run } => {
+            // the wallet's Save deposits and cTokens go through these accounts
+            let mut keep_mints = vec![mint0, mint1];
+            if let Some(lending_market) = args.lending_market {
+                for (_, reserve) in get_reserves(&rpc_client, &args.save_program, &lending_market) {
+                    keep_mints.extend([reserve.liquidity_mint, reserve.collateral_mint]);
+                }
+            }
+            cleanup::cleanup(
+                Rc::new(anchor_client),
+                rpc_client,
+                payer,
+                args.raydium_v3_program,
+                &keep_mints,
+                dry_run,
+            );
+        }
+        cli::CommandsName::ClosePosition {
+            all: true, pool, ..
+        } => {
+            close_all::close_all(
+                Rc::new(anchor_client),
+                &rpc_client,
+                &payer,
+                args.raydium_v3_program,
+                program,
+                pool,
+                args.slippage,
+                park.as_ref(),
+                &data_dir,
+            );
+        }
+        cli::CommandsName::ClosePosition {
+            tick_lower_index: Some(tick_lower_index),
+            tick_upper_index: Some(tick_upper_index),
+            ..
+        } => {
+            let (tick_lower_price, tick_upper_price) =
+                utils::orient_price_range(tick_lower_index, tick_upper_index, invert_price);
+            close::close(
+                Rc::new(anchor_client),
+                &rpc_client,
+                &payer,
+                args.raydium_v3_program,
+                program,
+                pool_id_account,
                 tickarray_bitmap_extension,
-                args.mint0,
-                args.mint1,
+                mint0,
+                mint1,
                 tick_lower_price,
                 tick_upper_price,
-                is_base_0,
-                input_amount,
                 args.slippage,
+                park.as_ref(),
+                &data_dir,
             );
         }
-        cli::CommandsName::ClosePosition {
-            tick_lower_index,
-            tick_upper_index,
+        cli::CommandsName::Allocate {
+            range_pct,
+            threshold_pct,
+            min_hold_secs,
+            fee_window_secs,
+            dry_run,
         } => {
-            close::close(
+            let config = allocate::AllocateConfig {
+                range_pct,
+                threshold_pct,
+                min_hold_secs,
+                fee_window_secs,
+                dry_run,
+            };
+            allocate::allocate(
+                Rc::new(anchor_client),
+                rpc_client,
+                payer,
+                args.raydium_v3_program,
+                program,
+                pool_id_account,
+                tickarray_bitmap_extension,
+                args.save_program,
+                args.lending_market(),
+                &config,
+                args.slippage,
+                invert_price,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::LeverageOpen {
+            collateral_mint,
+            collateral_amount,
+            borrow_amount,
+            pair_amount,
+            ref range,
+        } => {
+            let range_input = range
+                .around_current(invert_price)
+                .expect("a --range-* width must be provided");
+            leverage::leverage_open(
+                Rc::new(anchor_client),
+                rpc_client,
+                payer,
+                args.raydium_v3_program,
+                program,
+                pool_id_account,
+                tickarray_bitmap_extension,
+                args.save_program,
+                args.lending_market(),
+                collateral_mint,
+                collateral_amount,
+                borrow_amount,
+                pair_amount,
+                range_input,
+                args.max_ltv / 100.0,
+                args.slippage,
+                invert_price,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::LeverageClose {
+            collateral_mint,
+            tick_lower_price,
+            tick_upper_price,
+        } => {
+            let (tick_lower_price, tick_upper_price) =
+                utils::orient_price_range(tick_lower_price, tick_upper_price, invert_price);
+            leverage::leverage_close(
                 Rc::new(anchor_client),
                 rpc_client,
                 payer,
@@ -89,11 +370,14 @@ pub fn run(args: &Cli, rpc_client: RpcClient, anchor_client: Client<Rc<Keypair>>
                 program,
                 pool_id_account,
                 tickarray_bitmap_extension,
-                args.mint0,
-                args.mint1,
-                tick_lower_index,
-                tick_upper_index,
+                args.save_program,
+                args.lending_market(),
+                collateral_mint,
+                tick_lower_price,
+                tick_upper_price,
+                args.max_ltv / 100.0,
                 args.slippage,
+                &data_dir,
             );
         }
         _ => panic!("unhandled"),
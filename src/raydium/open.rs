@@ -1,130 +1,108 @@
-use std::rc::Rc;
+use std::{path::Path, rc::Rc};
 
 use anchor_client::{Client, Program};
 use anchor_lang::prelude::AccountMeta;
 use rand::rngs::OsRng;
 use raydium_amm_v3::{
-    accounts::OpenPositionWithToken22Nft as OpenPositionWithToken22NftAccounts,
-    instruction::OpenPositionWithToken22Nft as OpenPositionWithToken22NftInstruction,
+    accounts::{
+        OpenPositionV2 as OpenPositionV2Accounts,
+        OpenPositionWithToken22Nft as OpenPositionWithToken22NftAccounts,
+    },
+    instruction::{
+        OpenPositionV2 as OpenPositionV2Instruction,
+        OpenPositionWithToken22Nft as OpenPositionWithToken22NftInstruction,
+    },
     libraries::{liquidity_math, tick_math},
     states::{POSITION_SEED, TICK_ARRAY_SEED},
 };
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
-    compute_budget::ComputeBudgetInstruction, instruction::Instruction, pubkey::Pubkey,
-    signature::Keypair, signer::Signer, system_program, sysvar, transaction::Transaction,
+    instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer, system_program,
+    sysvar,
 };
 
 use crate::{
+    journal::{JournalEntry, MovementKind, Operation, append_journal_entry},
     raydium::{
+        batch::{InstructionGroup, send_measured_instruction_groups},
         position::get_all_nft_and_position_by_owner,
+        range::{RangeInput, print_range_preview},
         utils::{
             amount_with_slippage, deserialize_anchor_account, get_pool_mints_inverse_fee,
-            price_to_sqrt_price_x64, tick_with_spacing,
+            get_pool_mints_transfer_fee, sqrt_price_x64_to_price,
         },
     },
-    send_txn,
+    save::park::withdraw_shortfall,
 };
 
+/// How the liquidity of a new position is derived from the user's token amounts.
+#[derive(Clone, Copy, Debug)]
+pub enum LiquidityInput {
+    /// Liquidity is computed from a single token amount, the other side follows the price.
+    SingleAmount { is_base_0: bool, amount: u64 },
+    /// Liquidity is the largest one that fits in both desired amounts.
+    BothAmounts { amount_0: u64, amount_1: u64 },
+}
+
+impl LiquidityInput {
+    /// The same input with every amount scaled by `fraction`, to split a budget.
+    pub fn scaled(&self, fraction: f64) -> Self {
+        let scale = |amount: u64| (amount as f64 * fraction) as u64;
+        match *self {
+            LiquidityInput::SingleAmount { is_base_0, amount } => LiquidityInput::SingleAmount {
+                is_base_0,
+                amount: scale(amount),
+            },
+            LiquidityInput::BothAmounts { amount_0, amount_1 } => LiquidityInput::BothAmounts {
+                amount_0: scale(amount_0),
+                amount_1: scale(amount_1),
+            },
+        }
+    }
+}
+
+/// Compute units requested for each open position instruction in a batch.
+const OPEN_POSITION_COMPUTE_UNITS: u32 = 500_000;
+
+/// A position ready to be opened, its NFT mint keypair signs the instruction group.
+struct PlannedPosition {
+    nft_mint: Pubkey,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    liquidity: u128,
+    amount_0: u64,
+    amount_1: u64,
+}
+
+/// Opens one position per `(range, liquidity)` pair, packing as many of them per transaction as
+/// fit, and journals each one. With `from_save`, the Save program and lending market, what the
+/// wallet lacks of the maximum amounts is first withdrawn from the payer's cTokens. Returns the
+/// number of positions opened.
 pub fn open(
-    client: Client<Rc<Keypair>>,
-    rpc_client: RpcClient,
-    payer: Keypair,
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
     raydium_v3_program: Pubkey,
     program: Program<Rc<Keypair>>,
     pool_id_account: Pubkey,
     tickarray_bitmap_extension: Pubkey,
     mint0: Pubkey,
     mint1: Pubkey,
-    tick_lower_price: f64,
-    tick_upper_price: f64,
-    is_base_0: bool,
-    input_amount: u64,
+    position_inputs: &[(RangeInput, LiquidityInput)],
     slippage: f64,
-) {
+    invert_price: bool,
+    preview: bool,
+    with_metadata: bool,
+    legacy_nft: bool,
+    from_save: Option<(Pubkey, Pubkey)>,
+    data_dir: &Path,
+) -> usize {
     // load pool to get observation
     let pool: raydium_amm_v3::states::PoolState = program.account(pool_id_account).unwrap();
 
-    let tick_lower_price_x64 =
-        price_to_sqrt_price_x64(tick_lower_price, pool.mint_decimals_0, pool.mint_decimals_1);
-    let tick_upper_price_x64 =
-        price_to_sqrt_price_x64(tick_upper_price, pool.mint_decimals_0, pool.mint_decimals_1);
-    let tick_lower_index = tick_with_spacing(
-        tick_math::get_tick_at_sqrt_price(tick_lower_price_x64).unwrap(),
-        pool.tick_spacing.into(),
-    );
-    let tick_upper_index = tick_with_spacing(
-        tick_math::get_tick_at_sqrt_price(tick_upper_price_x64).unwrap(),
-        pool.tick_spacing.into(),
-    );
-    println!(
-        "tick_lower_index:{}, tick_upper_index:{}",
-        tick_lower_index, tick_upper_index
-    );
-    let tick_lower_price_x64 = tick_math::get_sqrt_price_at_tick(tick_lower_index).unwrap();
-    let tick_upper_price_x64 = tick_math::get_sqrt_price_at_tick(tick_upper_index).unwrap();
-    let liquidity = if is_base_0 {
-        liquidity_math::get_liquidity_from_single_amount_0(
-            pool.sqrt_price_x64,
-            tick_lower_price_x64,
-            tick_upper_price_x64,
-            input_amount,
-        )
-    } else {
-        liquidity_math::get_liquidity_from_single_amount_1(
-            pool.sqrt_price_x64,
-            tick_lower_price_x64,
-            tick_upper_price_x64,
-            input_amount,
-        )
-    };
-    let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
-        pool.tick_current,
-        pool.sqrt_price_x64,
-        tick_lower_index,
-        tick_upper_index,
-        liquidity as i128,
-    )
-    .unwrap();
-    println!(
-        "amount_0:{}, amount_1:{}, liquidity:{}",
-        amount_0, amount_1, liquidity
-    );
-    // calc with slippage
-    let amount_0_with_slippage = amount_with_slippage(amount_0, slippage, true);
-    let amount_1_with_slippage = amount_with_slippage(amount_1, slippage, true);
-    // calc with transfer_fee
-    let transfer_fee = get_pool_mints_inverse_fee(
-        &rpc_client,
-        pool.token_mint_0,
-        pool.token_mint_1,
-        amount_0_with_slippage,
-        amount_1_with_slippage,
-    );
-    println!(
-        "transfer_fee_0:{}, transfer_fee_1:{}",
-        transfer_fee.0.transfer_fee, transfer_fee.1.transfer_fee
-    );
-    let amount_0_max = (amount_0_with_slippage as u64)
-        .checked_add(transfer_fee.0.transfer_fee)
-        .unwrap();
-    let amount_1_max = (amount_1_with_slippage as u64)
-        .checked_add(transfer_fee.1.transfer_fee)
-        .unwrap();
-
-    let tick_array_lower_start_index =
-        raydium_amm_v3::states::TickArrayState::get_array_start_index(
-            tick_lower_index,
-            pool.tick_spacing.into(),
-        );
-    let tick_array_upper_start_index =
-        raydium_amm_v3::states::TickArrayState::get_array_start_index(
-            tick_upper_index,
-            pool.tick_spacing.into(),
-        );
     // load position
     let position_nft_infos =
-        get_all_nft_and_position_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program);
+        get_all_nft_and_position_by_owner(rpc_client, &payer.pubkey(), &raydium_v3_program);
     let positions: Vec<Pubkey> = position_nft_infos
         .iter()
         .map(|item| item.position)
@@ -143,75 +121,335 @@ pub fn open(
             }
         }
     }
-    let mut find_position = raydium_amm_v3::states::PersonalPositionState::default();
-    for position in user_positions {
-        if position.pool_id == pool_id_account
-            && position.tick_lower_index == tick_lower_index
-            && position.tick_upper_index == tick_upper_index
+
+    let mut planned_positions = Vec::new();
+    let mut instruction_groups = Vec::new();
+    let mut user_token_accounts = None;
+    let (mut needed_0, mut needed_1) = (0u64, 0u64);
+    for (range_input, liquidity_input) in position_inputs {
+        let (tick_lower_index, tick_upper_index) = range_input.tick_indexes(&pool);
+        println!(
+            "tick_lower_index:{}, tick_upper_index:{}",
+            tick_lower_index, tick_upper_index
+        );
+        let tick_lower_price_x64 = tick_math::get_sqrt_price_at_tick(tick_lower_index).unwrap();
+        let tick_upper_price_x64 = tick_math::get_sqrt_price_at_tick(tick_upper_index).unwrap();
+        let liquidity = match *liquidity_input {
+            LiquidityInput::SingleAmount {
+                is_base_0: true,
+                amount,
+            } => liquidity_math::get_liquidity_from_single_amount_0(
+                pool.sqrt_price_x64,
+                tick_lower_price_x64,
+                tick_upper_price_x64,
+                amount,
+            ),
+            LiquidityInput::SingleAmount {
+                is_base_0: false,
+                amount,
+            } => liquidity_math::get_liquidity_from_single_amount_1(
+                pool.sqrt_price_x64,
+                tick_lower_price_x64,
+                tick_upper_price_x64,
+                amount,
+            ),
+            LiquidityInput::BothAmounts { amount_0, amount_1 } => {
+                liquidity_math::get_liquidity_from_amounts(
+                    pool.sqrt_price_x64,
+                    tick_lower_price_x64,
+                    tick_upper_price_x64,
+                    amount_0,
+                    amount_1,
+                )
+            }
+        };
+        let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+            pool.tick_current,
+            pool.sqrt_price_x64,
+            tick_lower_index,
+            tick_upper_index,
+            liquidity as i128,
+        )
+        .unwrap();
+        println!(
+            "amount_0:{}, amount_1:{}, liquidity:{}",
+            amount_0, amount_1, liquidity
+        );
+        if let LiquidityInput::BothAmounts {
+            amount_0: desired_amount_0,
+            amount_1: desired_amount_1,
+        } = *liquidity_input
         {
-            find_position = position.clone();
+            println!(
+                "unused_amount_0:{}, unused_amount_1:{}",
+                desired_amount_0.saturating_sub(amount_0),
+                desired_amount_1.saturating_sub(amount_1)
+            );
         }
-    }
-    if find_position.nft_mint == Pubkey::default() {
-        // personal position not exist
+        if preview {
+            print_range_preview(
+                &pool,
+                tick_lower_index,
+                tick_upper_index,
+                amount_0,
+                amount_1,
+                invert_price,
+            );
+            continue;
+        }
+        if liquidity == 0 {
+            println!("liquidity is zero, skipping range");
+            continue;
+        }
+        if let Some(find_position) = user_positions.iter().find(|position| {
+            position.pool_id == pool_id_account
+                && position.tick_lower_index == tick_lower_index
+                && position.tick_upper_index == tick_upper_index
+        }) {
+            // personal position exist
+            println!("personal position exist:{:?}", find_position);
+            continue;
+        }
+        // calc with slippage
+        let amount_0_with_slippage = amount_with_slippage(amount_0, slippage, true);
+        let amount_1_with_slippage = amount_with_slippage(amount_1, slippage, true);
+        // calc with transfer_fee
+        let transfer_fee = get_pool_mints_inverse_fee(
+            rpc_client,
+            pool.token_mint_0,
+            pool.token_mint_1,
+            amount_0_with_slippage,
+            amount_1_with_slippage,
+        );
+        println!(
+            "transfer_fee_0:{}, transfer_fee_1:{}",
+            transfer_fee.0.transfer_fee, transfer_fee.1.transfer_fee
+        );
+        let amount_0_max = (amount_0_with_slippage as u64)
+            .checked_add(transfer_fee.0.transfer_fee)
+            .unwrap();
+        let amount_1_max = (amount_1_with_slippage as u64)
+            .checked_add(transfer_fee.1.transfer_fee)
+            .unwrap();
+        needed_0 += amount_0_max;
+        needed_1 += amount_1_max;
+
+        let tick_array_lower_start_index =
+            raydium_amm_v3::states::TickArrayState::get_array_start_index(
+                tick_lower_index,
+                pool.tick_spacing.into(),
+            );
+        let tick_array_upper_start_index =
+            raydium_amm_v3::states::TickArrayState::get_array_start_index(
+                tick_upper_index,
+                pool.tick_spacing.into(),
+            );
         // new nft mint
         let nft_mint = Keypair::generate(&mut OsRng);
         let mut remaining_accounts = Vec::new();
         remaining_accounts.push(AccountMeta::new(tickarray_bitmap_extension, false));
 
-        let mut instructions = Vec::new();
-        let request_inits_instr = ComputeBudgetInstruction::set_compute_unit_limit(1400_000u32);
-        instructions.push(request_inits_instr);
-        let open_position_instr = open_position_with_token22_nft_instr(
-            client,
-            raydium_v3_program,
-            pool_id_account,
-            pool.token_vault_0,
-            pool.token_vault_1,
-            pool.token_mint_0,
-            pool.token_mint_1,
-            nft_mint.pubkey(),
-            payer.pubkey(),
-            spl_associated_token_account::get_associated_token_address_with_program_id(
-                &payer.pubkey(),
-                &mint0,
-                &transfer_fee.0.owner,
-            ),
-            spl_associated_token_account::get_associated_token_address_with_program_id(
-                &payer.pubkey(),
-                &mint1,
-                &transfer_fee.1.owner,
-            ),
-            remaining_accounts,
-            liquidity,
-            amount_0_max,
-            amount_1_max,
+        let (user_token_account_0, user_token_account_1) =
+            *user_token_accounts.get_or_insert_with(|| {
+                (
+                    spl_associated_token_account::get_associated_token_address_with_program_id(
+                        &payer.pubkey(),
+                        &mint0,
+                        &transfer_fee.0.owner,
+                    ),
+                    spl_associated_token_account::get_associated_token_address_with_program_id(
+                        &payer.pubkey(),
+                        &mint1,
+                        &transfer_fee.1.owner,
+                    ),
+                )
+            });
+        let open_position_instr = if legacy_nft {
+            open_position_instr(
+                client.clone(),
+                raydium_v3_program,
+                pool_id_account,
+                pool.token_vault_0,
+                pool.token_vault_1,
+                pool.token_mint_0,
+                pool.token_mint_1,
+                nft_mint.pubkey(),
+                payer.pubkey(),
+                user_token_account_0,
+                user_token_account_1,
+                remaining_accounts,
+                liquidity,
+                amount_0_max,
+                amount_1_max,
+                tick_lower_index,
+                tick_upper_index,
+                tick_array_lower_start_index,
+                tick_array_upper_start_index,
+                with_metadata,
+            )
+        } else {
+            open_position_with_token22_nft_instr(
+                client.clone(),
+                raydium_v3_program,
+                pool_id_account,
+                pool.token_vault_0,
+                pool.token_vault_1,
+                pool.token_mint_0,
+                pool.token_mint_1,
+                nft_mint.pubkey(),
+                payer.pubkey(),
+                user_token_account_0,
+                user_token_account_1,
+                remaining_accounts,
+                liquidity,
+                amount_0_max,
+                amount_1_max,
+                tick_lower_index,
+                tick_upper_index,
+                tick_array_lower_start_index,
+                tick_array_upper_start_index,
+                with_metadata,
+            )
+        };
+        planned_positions.push(PlannedPosition {
+            nft_mint: nft_mint.pubkey(),
             tick_lower_index,
             tick_upper_index,
-            tick_array_lower_start_index,
-            tick_array_upper_start_index,
-            false,
-        );
-        instructions.extend(open_position_instr);
-        // send
-        let signers = vec![&payer, &nft_mint];
-        let recent_hash = rpc_client.get_latest_blockhash().unwrap();
-        let txn = Transaction::new_signed_with_payer(
-            &instructions,
-            Some(&payer.pubkey()),
-            &signers,
-            recent_hash,
-        );
-        let signature = send_txn(&rpc_client, &txn, true);
-        println!("{}", signature);
-    } else {
-        // personal position exist
-        println!("personal position exist:{:?}", find_position);
+            liquidity,
+            amount_0,
+            amount_1,
+        });
+        instruction_groups.push(InstructionGroup {
+            instructions: open_position_instr,
+            compute_units: OPEN_POSITION_COMPUTE_UNITS,
+            signers: vec![nft_mint],
+        });
+    }
+    let Some((user_token_account_0, user_token_account_1)) = user_token_accounts else {
+        return 0;
+    };
+    if let Some((save_program, lending_market)) = from_save {
+        for (mint, token_account, needed) in [
+            (pool.token_mint_0, user_token_account_0, needed_0),
+            (pool.token_mint_1, user_token_account_1, needed_1),
+        ] {
+            withdraw_shortfall(
+                rpc_client,
+                payer,
+                &save_program,
+                &lending_market,
+                &mint,
+                &token_account,
+                needed,
+                data_dir,
+            );
+        }
     }
+
+    // each batch is journaled as soon as it confirms, so a later failure loses nothing
+    let balance_accounts = [user_token_account_0, user_token_account_1];
+    let outcomes = send_measured_instruction_groups(
+        rpc_client,
+        payer,
+        &instruction_groups,
+        &balance_accounts,
+        |signature, batch, balances_before, balances_after| {
+            // the wallet pays the deposits plus whatever Token-2022 withholds on the transfers,
+            // split between the positions of the transaction by their expected amounts
+            let paid_0 = balances_before[0].saturating_sub(balances_after[0]);
+            let paid_1 = balances_before[1].saturating_sub(balances_after[1]);
+            let expected_0: u64 = batch
+                .iter()
+                .map(|index| planned_positions[*index].amount_0)
+                .sum();
+            let expected_1: u64 = batch
+                .iter()
+                .map(|index| planned_positions[*index].amount_1)
+                .sum();
+            let share = |paid: u64, amount: u64, expected: u64| {
+                if expected == 0 {
+                    0
+                } else {
+                    (paid as u128 * amount as u128 / expected as u128) as u64
+                }
+            };
+            for index in batch {
+                let position = &planned_positions[*index];
+                let paid_0 = share(paid_0, position.amount_0, expected_0);
+                let paid_1 = share(paid_1, position.amount_1, expected_1);
+                let paid_fee = get_pool_mints_transfer_fee(
+                    rpc_client,
+                    pool.token_mint_0,
+                    pool.token_mint_1,
+                    paid_0,
+                    paid_1,
+                );
+
+                let mut entry = JournalEntry::new(rpc_client, signature, Operation::OpenPosition);
+                entry.pool = Some(pool_id_account.to_string());
+                entry.position_nft = Some(position.nft_mint.to_string());
+                entry.tick_lower_index = Some(position.tick_lower_index);
+                entry.tick_upper_index = Some(position.tick_upper_index);
+                entry.liquidity = Some(position.liquidity);
+                entry.sqrt_price_x64 = Some(pool.sqrt_price_x64);
+                entry.price = Some(sqrt_price_x64_to_price(
+                    pool.sqrt_price_x64,
+                    pool.mint_decimals_0,
+                    pool.mint_decimals_1,
+                ));
+                entry.push_movement(
+                    &pool.token_mint_0,
+                    MovementKind::Deposit,
+                    paid_0 - paid_fee.0.transfer_fee,
+                    pool.mint_decimals_0,
+                );
+                entry.push_movement(
+                    &pool.token_mint_1,
+                    MovementKind::Deposit,
+                    paid_1 - paid_fee.1.transfer_fee,
+                    pool.mint_decimals_1,
+                );
+                entry.push_movement(
+                    &pool.token_mint_0,
+                    MovementKind::TransferFee,
+                    paid_fee.0.transfer_fee,
+                    pool.mint_decimals_0,
+                );
+                entry.push_movement(
+                    &pool.token_mint_1,
+                    MovementKind::TransferFee,
+                    paid_fee.1.transfer_fee,
+                    pool.mint_decimals_1,
+                );
+                append_journal_entry(data_dir, &entry);
+            }
+        },
+    );
+    let mut opened = 0;
+    for (position, outcome) in planned_positions.iter().zip(&outcomes) {
+        match outcome {
+            Ok(signature) => {
+                opened += 1;
+                println!(
+                    "position nft_mint:{}, tick_lower_index:{}, tick_upper_index:{}, opened, signature:{}",
+                    position.nft_mint,
+                    position.tick_lower_index,
+                    position.tick_upper_index,
+                    signature
+                );
+            }
+            Err(err) => println!(
+                "position tick_lower_index:{}, tick_upper_index:{}, failed: {}",
+                position.tick_lower_index, position.tick_upper_index, err
+            ),
+        }
+    }
+    println!("opened {} of {} positions", opened, planned_positions.len());
+    opened
 }
 
 pub fn open_position_with_token22_nft_instr(
-    client: Client<Rc<Keypair>>,
+    client: Rc<Client<Rc<Keypair>>>,
     raydium_v3_program: Pubkey,
     pool_account_key: Pubkey,
     token_vault_0: Pubkey,
@@ -308,3 +546,103 @@ pub fn open_position_with_token22_nft_instr(
         .unwrap();
     instructions
 }
+
+/// Opens a position whose NFT is a legacy SPL Token mint, with optional Metaplex metadata.
+pub fn open_position_instr(
+    client: Rc<Client<Rc<Keypair>>>,
+    raydium_v3_program: Pubkey,
+    pool_account_key: Pubkey,
+    token_vault_0: Pubkey,
+    token_vault_1: Pubkey,
+    token_mint_0: Pubkey,
+    token_mint_1: Pubkey,
+    nft_mint_key: Pubkey,
+    nft_to_owner: Pubkey,
+    user_token_account_0: Pubkey,
+    user_token_account_1: Pubkey,
+    remaining_accounts: Vec<AccountMeta>,
+    liquidity: u128,
+    amount_0_max: u64,
+    amount_1_max: u64,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    tick_array_lower_start_index: i32,
+    tick_array_upper_start_index: i32,
+    with_metadata: bool,
+) -> Vec<Instruction> {
+    let program = client.program(raydium_v3_program).unwrap();
+    let nft_ata_token_account =
+        spl_associated_token_account::get_associated_token_address(&program.payer(), &nft_mint_key);
+    let (metadata_account_key, __bump) =
+        mpl_token_metadata::pda::find_metadata_account(&nft_mint_key);
+    let (protocol_position_key, __bump) = Pubkey::find_program_address(
+        &[
+            POSITION_SEED.as_bytes(),
+            pool_account_key.to_bytes().as_ref(),
+            &tick_lower_index.to_be_bytes(),
+            &tick_upper_index.to_be_bytes(),
+        ],
+        &program.id(),
+    );
+    let (tick_array_lower, __bump) = Pubkey::find_program_address(
+        &[
+            TICK_ARRAY_SEED.as_bytes(),
+            pool_account_key.to_bytes().as_ref(),
+            &tick_array_lower_start_index.to_be_bytes(),
+        ],
+        &program.id(),
+    );
+    let (tick_array_upper, __bump) = Pubkey::find_program_address(
+        &[
+            TICK_ARRAY_SEED.as_bytes(),
+            pool_account_key.to_bytes().as_ref(),
+            &tick_array_upper_start_index.to_be_bytes(),
+        ],
+        &program.id(),
+    );
+    let (personal_position_key, __bump) = Pubkey::find_program_address(
+        &[POSITION_SEED.as_bytes(), nft_mint_key.to_bytes().as_ref()],
+        &program.id(),
+    );
+    let instructions = program
+        .request()
+        .accounts(OpenPositionV2Accounts {
+            payer: program.payer(),
+            position_nft_owner: nft_to_owner,
+            position_nft_mint: nft_mint_key,
+            position_nft_account: nft_ata_token_account,
+            metadata_account: metadata_account_key,
+            pool_state: pool_account_key,
+            protocol_position: protocol_position_key,
+            tick_array_lower,
+            tick_array_upper,
+            personal_position: personal_position_key,
+            token_account_0: user_token_account_0,
+            token_account_1: user_token_account_1,
+            token_vault_0,
+            token_vault_1,
+            rent: sysvar::rent::id(),
+            system_program: system_program::id(),
+            token_program: spl_token::id(),
+            associated_token_program: spl_associated_token_account::id(),
+            metadata_program: mpl_token_metadata::id(),
+            token_program_2022: spl_token_2022::id(),
+            vault_0_mint: token_mint_0,
+            vault_1_mint: token_mint_1,
+        })
+        .accounts(remaining_accounts)
+        .args(OpenPositionV2Instruction {
+            liquidity,
+            amount_0_max,
+            amount_1_max,
+            tick_lower_index,
+            tick_upper_index,
+            tick_array_lower_start_index,
+            tick_array_upper_start_index,
+            with_metadata,
+            base_flag: None,
+        })
+        .instructions()
+        .unwrap();
+    instructions
+}
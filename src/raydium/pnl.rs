@@ -0,0 +1,314 @@
+use std::{path::Path, rc::Rc};
+
+use anchor_client::Program;
+use raydium_amm_v3::libraries::liquidity_math;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+
+use super::{
+    growth::update_reward_infos,
+    position::{get_all_personal_positions_by_owner, get_position_pending_amounts},
+    utils::{get_chain_timestamp, multipler, orient_price, sqrt_price_x64_to_price},
+};
+use crate::journal::{
+    JournalEntry, MovementKind, Operation, import_legacy_position_entries, load_journal,
+};
+
+/// Values raw token amounts in the quote token, `price` is token_1 per token_0.
+pub(super) fn quote_value(
+    amount_0: u64,
+    amount_1: u64,
+    price: f64,
+    decimals_0: u8,
+    decimals_1: u8,
+    quote_in_token_0: bool,
+) -> f64 {
+    let amount_0 = amount_0 as f64 / multipler(decimals_0);
+    let amount_1 = amount_1 as f64 / multipler(decimals_1);
+    if quote_in_token_0 {
+        amount_0 + amount_1 / price
+    } else {
+        amount_0 * price + amount_1
+    }
+}
+
+/// Compares each journaled position with holding its entry amounts, valued in the quote token:
+/// the open positions at the current price with their uncollected and collected fees and
+/// rewards and the principal decreases withdrew, and the closed ones at the price they were
+/// closed at.
+pub fn pnl_report(
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    program: Program<Rc<Keypair>>,
+    pool_id_account: Pubkey,
+    invert_price: bool,
+    data_dir: &Path,
+) {
+    let pool: raydium_amm_v3::states::PoolState = program.account(pool_id_account).unwrap();
+    let price = sqrt_price_x64_to_price(
+        pool.sqrt_price_x64,
+        pool.mint_decimals_0,
+        pool.mint_decimals_1,
+    );
+    let (decimals_0, decimals_1) = (pool.mint_decimals_0, pool.mint_decimals_1);
+    let value = |amount_0: u64, amount_1: u64, price: f64| {
+        quote_value(
+            amount_0,
+            amount_1,
+            price,
+            decimals_0,
+            decimals_1,
+            invert_price,
+        )
+    };
+    let imported = import_legacy_position_entries(data_dir, &pool_id_account, &pool);
+    if imported > 0 {
+        println!(
+            "imported {} position entries from positions.jsonl",
+            imported
+        );
+    }
+    let reward_infos = update_reward_infos(&pool, get_chain_timestamp(&rpc_client));
+    let pool_key = pool_id_account.to_string();
+    let entries: Vec<JournalEntry> = load_journal(data_dir)
+        .into_iter()
+        .filter(|entry| entry.pool.as_ref() == Some(&pool_key))
+        .collect();
+    // fees and rewards journaled for the position, valued at `price`
+    let collected = |nft_mint: &str, price: f64| {
+        let (mut fee_value, mut reward_value) = (0.0, 0.0);
+        for movement in entries
+            .iter()
+            .filter(|entry| entry.position_nft.as_deref() == Some(nft_mint))
+            .flat_map(|entry| &entry.movements)
+        {
+            let movement_value = if movement.mint == pool.token_mint_0.to_string() {
+                value(movement.amount, 0, price)
+            } else if movement.mint == pool.token_mint_1.to_string() {
+                value(0, movement.amount, price)
+            } else {
+                if movement.kind == MovementKind::Reward {
+                    println!(
+                        "    collected reward mint:{}, amount:{} not valued",
+                        movement.mint, movement.amount
+                    );
+                }
+                continue;
+            };
+            match movement.kind {
+                MovementKind::Fee => fee_value += movement_value,
+                MovementKind::Reward => reward_value += movement_value,
+                _ => {}
+            }
+        }
+        (fee_value, reward_value)
+    };
+    // principal journaled as withdrawn from the position by decreases and its close
+    let withdrawn = |nft_mint: &str, mint: &Pubkey| -> u64 {
+        entries
+            .iter()
+            .filter(|entry| {
+                matches!(
+                    entry.operation,
+                    Operation::ClosePosition | Operation::DecreaseLiquidity
+                ) && entry.position_nft.as_deref() == Some(nft_mint)
+            })
+            .map(|entry| entry.movement_amount(mint, MovementKind::Withdraw))
+            .sum()
+    };
+    println!(
+        "current price:{}, values quoted in {}",
+        orient_price(price, invert_price),
+        if invert_price {
+            pool.token_mint_0
+        } else {
+            pool.token_mint_1
+        }
+    );
+
+    let personal_positions: Vec<_> =
+        get_all_personal_positions_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program)
+            .into_iter()
+            .filter(|(_, position)| position.pool_id == pool_id_account)
+            .collect();
+    let open_entry = |nft_mint: &str| {
+        entries
+            .iter()
+            .rev()
+            .filter(|entry| {
+                entry.operation == Operation::OpenPosition
+                    && entry.position_nft.as_deref() == Some(nft_mint)
+            })
+            .find_map(|entry| Some((entry, entry.sqrt_price_x64?)))
+    };
+    for (_, position) in &personal_positions {
+        let nft_mint = position.nft_mint.to_string();
+        let Some((entry, entry_sqrt_price_x64)) = open_entry(&nft_mint) else {
+            println!("position nft_mint:{}, no entry recorded", nft_mint);
+            continue;
+        };
+        let entry_price = sqrt_price_x64_to_price(
+            entry_sqrt_price_x64,
+            pool.mint_decimals_0,
+            pool.mint_decimals_1,
+        );
+        let entry_amount_0 = entry.movement_amount(&pool.token_mint_0, MovementKind::Deposit);
+        let entry_amount_1 = entry.movement_amount(&pool.token_mint_1, MovementKind::Deposit);
+        let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+            pool.tick_current,
+            pool.sqrt_price_x64,
+            position.tick_lower_index,
+            position.tick_upper_index,
+            -(position.liquidity as i128),
+        )
+        .unwrap();
+        let ((pending_fee_0, pending_fee_1), pending_rewards) = get_position_pending_amounts(
+            &rpc_client,
+            &raydium_v3_program,
+            &pool,
+            &reward_infos,
+            position,
+        );
+        // rewards paid in one of the pool tokens can be valued at the pool price
+        let mut reward_value = 0.0;
+        for (reward_info, pending_reward) in reward_infos.iter().zip(pending_rewards) {
+            if !reward_info.initialized() {
+                continue;
+            }
+            if reward_info.token_mint == pool.token_mint_0 {
+                reward_value += value(pending_reward, 0, price);
+            } else if reward_info.token_mint == pool.token_mint_1 {
+                reward_value += value(0, pending_reward, price);
+            } else {
+                println!(
+                    "    reward mint:{}, amount:{} not valued",
+                    reward_info.token_mint, pending_reward
+                );
+            }
+        }
+        let (collected_fee_value, collected_reward_value) = collected(&nft_mint, price);
+        let (withdrawn_0, withdrawn_1) = (
+            withdrawn(&nft_mint, &pool.token_mint_0),
+            withdrawn(&nft_mint, &pool.token_mint_1),
+        );
+
+        let entry_value = value(entry_amount_0, entry_amount_1, entry_price);
+        let hodl_value = value(entry_amount_0, entry_amount_1, price);
+        let lp_value = value(amount_0, amount_1, price);
+        let withdrawn_value = value(withdrawn_0, withdrawn_1, price);
+        let fee_value = value(pending_fee_0, pending_fee_1, price);
+        let impermanent_loss = lp_value + withdrawn_value - hodl_value;
+        let net_pnl = lp_value
+            + withdrawn_value
+            + fee_value
+            + reward_value
+            + collected_fee_value
+            + collected_reward_value
+            - entry_value;
+        println!(
+            "position nft_mint:{}, entry price:{}, entry amount_0:{}, amount_1:{}",
+            nft_mint,
+            orient_price(entry_price, invert_price),
+            entry_amount_0,
+            entry_amount_1
+        );
+        println!(
+            "    entry value:{:.6}, hodl value:{:.6}, lp value:{:.6}, withdrawn value:{:.6}",
+            entry_value, hodl_value, lp_value, withdrawn_value
+        );
+        println!(
+            "    uncollected fees:{:.6}, pending rewards:{:.6}, collected fees:{:.6}, collected rewards:{:.6}",
+            fee_value, reward_value, collected_fee_value, collected_reward_value
+        );
+        print_pnl(impermanent_loss, hodl_value, net_pnl, entry_value);
+    }
+
+    // journaled positions of the pool that are no longer open
+    let mut closed_nft_mints: Vec<&str> = entries
+        .iter()
+        .filter(|entry| entry.operation == Operation::OpenPosition)
+        .filter_map(|entry| entry.position_nft.as_deref())
+        .filter(|nft_mint| {
+            !personal_positions
+                .iter()
+                .any(|(_, position)| position.nft_mint.to_string() == *nft_mint)
+        })
+        .collect();
+    closed_nft_mints.sort_unstable();
+    closed_nft_mints.dedup();
+    for nft_mint in closed_nft_mints {
+        let Some((entry, entry_sqrt_price_x64)) = open_entry(nft_mint) else {
+            continue;
+        };
+        let Some(exit_sqrt_price_x64) = entries
+            .iter()
+            .rev()
+            .find(|entry| {
+                entry.operation == Operation::ClosePosition
+                    && entry.position_nft.as_deref() == Some(nft_mint)
+            })
+            .and_then(|entry| entry.sqrt_price_x64)
+        else {
+            println!(
+                "closed position nft_mint:{}, not closed by this tool, no exit recorded",
+                nft_mint
+            );
+            continue;
+        };
+        let entry_price = sqrt_price_x64_to_price(
+            entry_sqrt_price_x64,
+            pool.mint_decimals_0,
+            pool.mint_decimals_1,
+        );
+        let exit_price = sqrt_price_x64_to_price(
+            exit_sqrt_price_x64,
+            pool.mint_decimals_0,
+            pool.mint_decimals_1,
+        );
+        let entry_amount_0 = entry.movement_amount(&pool.token_mint_0, MovementKind::Deposit);
+        let entry_amount_1 = entry.movement_amount(&pool.token_mint_1, MovementKind::Deposit);
+        let (withdrawn_0, withdrawn_1) = (
+            withdrawn(nft_mint, &pool.token_mint_0),
+            withdrawn(nft_mint, &pool.token_mint_1),
+        );
+        let (collected_fee_value, collected_reward_value) = collected(nft_mint, exit_price);
+
+        let entry_value = value(entry_amount_0, entry_amount_1, entry_price);
+        let hodl_value = value(entry_amount_0, entry_amount_1, exit_price);
+        let exit_value = value(withdrawn_0, withdrawn_1, exit_price);
+        let impermanent_loss = exit_value - hodl_value;
+        let net_pnl = exit_value + collected_fee_value + collected_reward_value - entry_value;
+        println!(
+            "closed position nft_mint:{}, entry price:{}, exit price:{}, withdrawn amount_0:{}, amount_1:{}",
+            nft_mint,
+            orient_price(entry_price, invert_price),
+            orient_price(exit_price, invert_price),
+            withdrawn_0,
+            withdrawn_1
+        );
+        println!(
+            "    entry value:{:.6}, hodl value:{:.6}, exit value:{:.6}",
+            entry_value, hodl_value, exit_value
+        );
+        println!(
+            "    collected fees:{:.6}, collected rewards:{:.6}",
+            collected_fee_value, collected_reward_value
+        );
+        print_pnl(impermanent_loss, hodl_value, net_pnl, entry_value);
+    }
+}
+
+fn print_pnl(impermanent_loss: f64, hodl_value: f64, net_pnl: f64, entry_value: f64) {
+    println!(
+        "    impermanent loss:{:.6} ({:.4}%), net pnl:{:.6}, pnl vs hodl:{:.6}",
+        impermanent_loss,
+        if hodl_value > 0.0 {
+            impermanent_loss / hodl_value * 100.0
+        } else {
+            0.0
+        },
+        net_pnl,
+        net_pnl + entry_value - hodl_value
+    );
+}
@@ -1,3 +1,7 @@
+use raydium_amm_v3::states::{
+    PersonalPositionState, PoolState, REWARD_NUM, RewardInfo, TICK_ARRAY_SEED, TickArrayState,
+    TickState,
+};
 use solana_account_decoder::{
     UiAccountData,
     parse_token::{TokenAccountType, UiAccountState},
@@ -5,6 +9,13 @@ use solana_account_decoder::{
 use solana_client::{rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
 use solana_sdk::pubkey::Pubkey;
 
+use super::{
+    growth::{
+        get_fee_growth_inside, get_pending_fees, get_pending_rewards, get_reward_growths_inside,
+    },
+    utils::deserialize_anchor_account,
+};
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PositionNftTokenInfo {
     pub key: Pubkey,
@@ -95,3 +106,116 @@ fn get_nft_account_and_position_by_owner(
     }
     position_nft_accounts
 }
+
+/// Loads the personal position behind every position NFT held by `owner`.
+pub fn get_all_personal_positions_by_owner(
+    client: &RpcClient,
+    owner: &Pubkey,
+    raydium_amm_v3_program: &Pubkey,
+) -> Vec<(PositionNftTokenInfo, PersonalPositionState)> {
+    let position_nft_infos =
+        get_all_nft_and_position_by_owner(client, owner, raydium_amm_v3_program);
+    let mut personal_positions = Vec::new();
+    for nft_infos in position_nft_infos.chunks(100) {
+        let positions: Vec<Pubkey> = nft_infos.iter().map(|item| item.position).collect();
+        let rsps = client.get_multiple_accounts(&positions).unwrap();
+        for (nft_info, rsp) in nft_infos.iter().zip(rsps) {
+            match rsp {
+                None => continue,
+                Some(rsp) => {
+                    let position =
+                        deserialize_anchor_account::<PersonalPositionState>(&rsp).unwrap();
+                    personal_positions.push((nft_info.clone(), position));
+                }
+            }
+        }
+    }
+    personal_positions
+}
+
+/// Loads the lower and upper `TickState` of a position from their tick arrays.
+pub fn get_position_tick_states(
+    client: &RpcClient,
+    raydium_amm_v3_program: &Pubkey,
+    pool_id: &Pubkey,
+    tick_spacing: u16,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+) -> (TickState, TickState) {
+    let tick_array_keys: Vec<Pubkey> = [tick_lower_index, tick_upper_index]
+        .iter()
+        .map(|tick_index| {
+            let start_index = TickArrayState::get_array_start_index(*tick_index, tick_spacing);
+            Pubkey::find_program_address(
+                &[
+                    TICK_ARRAY_SEED.as_bytes(),
+                    pool_id.to_bytes().as_ref(),
+                    &start_index.to_be_bytes(),
+                ],
+                raydium_amm_v3_program,
+            )
+            .0
+        })
+        .collect();
+    let rsps = client.get_multiple_accounts(&tick_array_keys).unwrap();
+    let tick_array_lower = deserialize_anchor_account::<TickArrayState>(
+        rsps[0].as_ref().expect("tick array lower not found"),
+    )
+    .unwrap();
+    let tick_array_upper = deserialize_anchor_account::<TickArrayState>(
+        rsps[1].as_ref().expect("tick array upper not found"),
+    )
+    .unwrap();
+    (
+        tick_state_in_array(&tick_array_lower, tick_lower_index, tick_spacing),
+        tick_state_in_array(&tick_array_upper, tick_upper_index, tick_spacing),
+    )
+}
+
+fn tick_state_in_array(
+    tick_array: &TickArrayState,
+    tick_index: i32,
+    tick_spacing: u16,
+) -> TickState {
+    let start_tick_index = tick_array.start_tick_index;
+    let offset = (tick_index - start_tick_index) / i32::from(tick_spacing);
+    let ticks = tick_array.ticks;
+    ticks[offset as usize]
+}
+
+/// Fees and rewards the position could collect now. `reward_infos` must already be brought up
+/// to date with `update_reward_infos`.
+pub fn get_position_pending_amounts(
+    client: &RpcClient,
+    raydium_amm_v3_program: &Pubkey,
+    pool: &PoolState,
+    reward_infos: &[RewardInfo; REWARD_NUM],
+    position: &PersonalPositionState,
+) -> ((u64, u64), [u64; REWARD_NUM]) {
+    let (tick_lower_state, tick_upper_state) = get_position_tick_states(
+        client,
+        raydium_amm_v3_program,
+        &position.pool_id,
+        pool.tick_spacing,
+        position.tick_lower_index,
+        position.tick_upper_index,
+    );
+    let (fee_growth_inside_0_x64, fee_growth_inside_1_x64) = get_fee_growth_inside(
+        &tick_lower_state,
+        &tick_upper_state,
+        pool.tick_current,
+        pool.fee_growth_global_0_x64,
+        pool.fee_growth_global_1_x64,
+    );
+    let pending_fees = get_pending_fees(position, fee_growth_inside_0_x64, fee_growth_inside_1_x64);
+    let pending_rewards = get_pending_rewards(
+        position,
+        &get_reward_growths_inside(
+            &tick_lower_state,
+            &tick_upper_state,
+            pool.tick_current,
+            reward_infos,
+        ),
+    );
+    (pending_fees, pending_rewards)
+}
@@ -0,0 +1,302 @@
+use std::str::FromStr;
+
+use raydium_amm_v3::{libraries::tick_math, states::PoolState};
+
+use super::utils::{
+    multipler, orient_price, orient_price_range, price_to_sqrt_price_x64, sqrt_price_x64_to_price,
+    tick_with_spacing,
+};
+
+/// Width of a range placed around the current pool price.
+#[derive(Clone, Copy, Debug)]
+pub enum RangeWidth {
+    /// Distance from the current price to each bound, in percent of the price.
+    Percent(f64),
+    /// Distance from the current tick to each bound, in multiples of `tick_spacing`.
+    Ticks(u32),
+}
+
+/// How the bounds of a new position are chosen.
+#[derive(Clone, Copy, Debug)]
+pub enum RangeInput {
+    /// Absolute lower and upper prices.
+    Prices { lower: f64, upper: f64 },
+    /// A width around the current price, `skew` from -1 (all below) to 1 (all above).
+    AroundCurrent { width: RangeWidth, skew: f64 },
+}
+
+/// One range of a ladder and its share of the budget, parsed from `LOWER..UPPER:WEIGHT` for
+/// prices or `PCT%:WEIGHT` for a width around the current price.
+#[derive(Clone, Copy, Debug)]
+pub struct LadderRung {
+    pub range: RangeInput,
+    pub weight: f64,
+}
+
+impl FromStr for LadderRung {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number: {}", value))
+        };
+        let (range, weight) = s
+            .rsplit_once(':')
+            .ok_or_else(|| "expected RANGE:WEIGHT".to_string())?;
+        let weight = parse(weight)?;
+        if weight <= 0.0 {
+            return Err("weight must be positive".to_string());
+        }
+        let range = match range.strip_suffix('%') {
+            Some(percent) => RangeInput::AroundCurrent {
+                width: RangeWidth::Percent(parse(percent)?),
+                skew: 0.0,
+            },
+            None => {
+                let (lower, upper) = range
+                    .split_once("..")
+                    .ok_or_else(|| "expected LOWER..UPPER or PCT%".to_string())?;
+                RangeInput::Prices {
+                    lower: parse(lower)?,
+                    upper: parse(upper)?,
+                }
+            }
+        };
+        Ok(LadderRung { range, weight })
+    }
+}
+
+impl RangeInput {
+    pub fn tick_indexes(&self, pool: &PoolState) -> (i32, i32) {
+        match *self {
+            RangeInput::Prices { lower, upper } => {
+                let tick_lower_price_x64 =
+                    price_to_sqrt_price_x64(lower, pool.mint_decimals_0, pool.mint_decimals_1);
+                let tick_upper_price_x64 =
+                    price_to_sqrt_price_x64(upper, pool.mint_decimals_0, pool.mint_decimals_1);
+                let tick_lower_index = tick_with_spacing(
+                    tick_math::get_tick_at_sqrt_price(tick_lower_price_x64).unwrap(),
+                    pool.tick_spacing.into(),
+                );
+                let tick_upper_index = tick_with_spacing(
+                    tick_math::get_tick_at_sqrt_price(tick_upper_price_x64).unwrap(),
+                    pool.tick_spacing.into(),
+                );
+                (tick_lower_index, tick_upper_index)
+            }
+            RangeInput::AroundCurrent { width, skew } => ticks_around_current(pool, width, skew),
+        }
+    }
+}
+
+/// Computes spacing aligned ticks around `pool.sqrt_price_x64`. The lower bound is rounded
+/// down and the upper bound up, so the range is never narrower than requested.
+pub fn ticks_around_current(pool: &PoolState, width: RangeWidth, skew: f64) -> (i32, i32) {
+    assert!(
+        (-1.0..=1.0).contains(&skew),
+        "skew must be between -1 and 1"
+    );
+    let tick_spacing: i32 = pool.tick_spacing.into();
+    let (tick_lower, tick_upper) = match width {
+        RangeWidth::Percent(percent) => {
+            let half_width = percent / 100.0;
+            let lower_factor = 1.0 - half_width * (1.0 - skew);
+            let upper_factor = 1.0 + half_width * (1.0 + skew);
+            assert!(
+                lower_factor > 0.0,
+                "range width must keep the lower price positive"
+            );
+            let sqrt_price = pool.sqrt_price_x64 as f64;
+            let lower_sqrt_price_x64 = (sqrt_price * lower_factor.sqrt()) as u128;
+            let upper_sqrt_price_x64 = (sqrt_price * upper_factor.sqrt()) as u128;
+            let tick_lower = tick_math::get_tick_at_sqrt_price(
+                lower_sqrt_price_x64.max(tick_math::MIN_SQRT_PRICE_X64),
+            )
+            .unwrap();
+            let tick_upper = tick_math::get_tick_at_sqrt_price(
+                upper_sqrt_price_x64.min(tick_math::MAX_SQRT_PRICE_X64 - 1),
+            )
+            .unwrap();
+            let tick_upper_aligned = tick_with_spacing(tick_upper, tick_spacing);
+            let tick_upper = if tick_upper_aligned < tick_upper {
+                tick_upper_aligned + tick_spacing
+            } else {
+                tick_upper_aligned
+            };
+            (tick_with_spacing(tick_lower, tick_spacing), tick_upper)
+        }
+        RangeWidth::Ticks(count) => {
+            let below = (count as f64 * (1.0 - skew)).round() as i32;
+            let above = (count as f64 * (1.0 + skew)).round() as i32;
+            let base = tick_with_spacing(pool.tick_current, tick_spacing);
+            (
+                base - below * tick_spacing,
+                base + (above + 1) * tick_spacing,
+            )
+        }
+    };
+    // a fully skewed range leaves the current price out even when its near bound rounds onto
+    // the current tick's spacing interval
+    let current_base = tick_with_spacing(pool.tick_current, tick_spacing);
+    let tick_lower = if skew >= 1.0 {
+        tick_lower.max(current_base + tick_spacing)
+    } else {
+        tick_lower
+    };
+    let tick_upper = if skew <= -1.0 {
+        tick_upper.min(current_base)
+    } else {
+        tick_upper
+    };
+    let min_tick = tick_with_spacing(tick_math::MIN_TICK, tick_spacing) + tick_spacing;
+    let max_tick = tick_with_spacing(tick_math::MAX_TICK, tick_spacing);
+    let tick_lower = tick_lower.max(min_tick);
+    let tick_upper = tick_upper.min(max_tick);
+    if tick_upper <= tick_lower {
+        (tick_lower, tick_lower + tick_spacing)
+    } else {
+        (tick_lower, tick_upper)
+    }
+}
+
+/// Prints the aligned ticks, their prices and the value split between both tokens.
+pub fn print_range_preview(
+    pool: &PoolState,
+    tick_lower_index: i32,
+    tick_upper_index: i32,
+    amount_0: u64,
+    amount_1: u64,
+    invert_price: bool,
+) {
+    let price = sqrt_price_x64_to_price(
+        pool.sqrt_price_x64,
+        pool.mint_decimals_0,
+        pool.mint_decimals_1,
+    );
+    let lower_price = sqrt_price_x64_to_price(
+        tick_math::get_sqrt_price_at_tick(tick_lower_index).unwrap(),
+        pool.mint_decimals_0,
+        pool.mint_decimals_1,
+    );
+    let upper_price = sqrt_price_x64_to_price(
+        tick_math::get_sqrt_price_at_tick(tick_upper_index).unwrap(),
+        pool.mint_decimals_0,
+        pool.mint_decimals_1,
+    );
+    let (lower_price, upper_price) = orient_price_range(lower_price, upper_price, invert_price);
+    // copied out of the packed pool, formatting cannot borrow an unaligned field
+    let tick_current = pool.tick_current;
+    println!(
+        "current tick:{}, price:{}",
+        tick_current,
+        orient_price(price, invert_price)
+    );
+    println!(
+        "tick_lower_index:{}, tick_upper_index:{}, lower price:{}, upper price:{}",
+        tick_lower_index, tick_upper_index, lower_price, upper_price
+    );
+    let value_0 = amount_0 as f64 / multipler(pool.mint_decimals_0) * price;
+    let value_1 = amount_1 as f64 / multipler(pool.mint_decimals_1);
+    let total_value = value_0 + value_1;
+    if total_value > 0.0 {
+        println!(
+            "token ratio by value, token_0:{:.2}%, token_1:{:.2}%",
+            value_0 / total_value * 100.0,
+            value_1 / total_value * 100.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A pool with a tick spacing of 10 whose price sits in the middle of tick 105.
+    fn pool() -> PoolState {
+        let mut pool = PoolState::default();
+        pool.tick_spacing = 10;
+        pool.tick_current = 105;
+        pool.sqrt_price_x64 = (tick_math::get_sqrt_price_at_tick(105).unwrap()
+            + tick_math::get_sqrt_price_at_tick(106).unwrap())
+            / 2;
+        pool
+    }
+
+    #[test]
+    fn ladder_rung_parses_percent_and_prices() {
+        let rung: LadderRung = "0.05%:60".parse().unwrap();
+        assert_eq!(rung.weight, 60.0);
+        match rung.range {
+            RangeInput::AroundCurrent {
+                width: RangeWidth::Percent(percent),
+                skew,
+            } => {
+                assert_eq!(percent, 0.05);
+                assert_eq!(skew, 0.0);
+            }
+            range => panic!("unexpected range {:?}", range),
+        }
+        let rung: LadderRung = " 0.995..0.9995 : 20".parse().unwrap();
+        assert_eq!(rung.weight, 20.0);
+        match rung.range {
+            RangeInput::Prices { lower, upper } => {
+                assert_eq!(lower, 0.995);
+                assert_eq!(upper, 0.9995);
+            }
+            range => panic!("unexpected range {:?}", range),
+        }
+    }
+
+    #[test]
+    fn ladder_rung_rejects_malformed_input() {
+        assert!("0.05%".parse::<LadderRung>().is_err());
+        assert!("0.05%:0".parse::<LadderRung>().is_err());
+        assert!("0.05%:-1".parse::<LadderRung>().is_err());
+        assert!("0.995:20".parse::<LadderRung>().is_err());
+        assert!("a..b:20".parse::<LadderRung>().is_err());
+    }
+
+    #[test]
+    fn ticks_width_is_centered_on_the_current_spacing() {
+        assert_eq!(
+            ticks_around_current(&pool(), RangeWidth::Ticks(2), 0.0),
+            (80, 130)
+        );
+    }
+
+    #[test]
+    fn fully_skewed_ticks_leave_the_current_price_out() {
+        let pool = pool();
+        let (tick_lower, tick_upper) = ticks_around_current(&pool, RangeWidth::Ticks(2), 1.0);
+        assert_eq!((tick_lower, tick_upper), (110, 150));
+        assert!(tick_lower > pool.tick_current);
+        let (tick_lower, tick_upper) = ticks_around_current(&pool, RangeWidth::Ticks(2), -1.0);
+        assert_eq!((tick_lower, tick_upper), (60, 100));
+        assert!(tick_upper <= pool.tick_current);
+    }
+
+    #[test]
+    fn percent_width_is_never_narrower_than_requested() {
+        let pool = pool();
+        let (tick_lower, tick_upper) = ticks_around_current(&pool, RangeWidth::Percent(1.0), 0.0);
+        assert_eq!(tick_lower % 10, 0);
+        assert_eq!(tick_upper % 10, 0);
+        let sqrt_price = pool.sqrt_price_x64 as f64;
+        let lower_sqrt_price = tick_math::get_sqrt_price_at_tick(tick_lower).unwrap() as f64;
+        let upper_sqrt_price = tick_math::get_sqrt_price_at_tick(tick_upper).unwrap() as f64;
+        assert!((lower_sqrt_price / sqrt_price).powi(2) <= 0.99);
+        assert!((upper_sqrt_price / sqrt_price).powi(2) >= 1.01);
+    }
+
+    #[test]
+    fn range_stays_within_the_tick_bounds() {
+        let (tick_lower, tick_upper) =
+            ticks_around_current(&pool(), RangeWidth::Ticks(100_000), 0.0);
+        assert!(tick_lower >= tick_math::MIN_TICK);
+        assert!(tick_upper <= tick_math::MAX_TICK);
+        assert!(tick_lower < tick_upper);
+    }
+}
@@ -0,0 +1,213 @@
+//! Rebuilds a position's history from its on-chain transactions, for positions that were not
+//! opened through this tool or predate the journal.
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use raydium_amm_v3::{
+    instruction as raydium_instruction,
+    states::{
+        CollectPersonalFeeEvent, DecreaseLiquidityEvent, IncreaseLiquidityEvent, POSITION_SEED,
+    },
+};
+use solana_client::{
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_config::RpcTransactionConfig,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{UiLoadedAddresses, UiTransactionEncoding};
+
+const PROGRAM_DATA_LOG: &str = "Program data: ";
+
+/// Raydium instruction names by discriminator, for the instructions that touch a position.
+fn position_instruction_name(data: &[u8]) -> Option<&'static str> {
+    let discriminator: [u8; 8] = data.get(..8)?.try_into().ok()?;
+    [
+        (
+            raydium_instruction::OpenPosition::DISCRIMINATOR,
+            "open_position",
+        ),
+        (
+            raydium_instruction::OpenPositionV2::DISCRIMINATOR,
+            "open_position_v2",
+        ),
+        (
+            raydium_instruction::OpenPositionWithToken22Nft::DISCRIMINATOR,
+            "open_position_with_token22_nft",
+        ),
+        (
+            raydium_instruction::IncreaseLiquidity::DISCRIMINATOR,
+            "increase_liquidity",
+        ),
+        (
+            raydium_instruction::IncreaseLiquidityV2::DISCRIMINATOR,
+            "increase_liquidity_v2",
+        ),
+        (
+            raydium_instruction::DecreaseLiquidity::DISCRIMINATOR,
+            "decrease_liquidity",
+        ),
+        (
+            raydium_instruction::DecreaseLiquidityV2::DISCRIMINATOR,
+            "decrease_liquidity_v2",
+        ),
+        (
+            raydium_instruction::ClosePosition::DISCRIMINATOR,
+            "close_position",
+        ),
+    ]
+    .into_iter()
+    .find(|(instruction_discriminator, _)| *instruction_discriminator == discriminator)
+    .map(|(_, name)| name)
+}
+
+/// Signatures touching `address`, oldest first, skipping failed transactions.
+fn get_all_signatures_for_address(rpc_client: &RpcClient, address: &Pubkey) -> Vec<Signature> {
+    let mut signatures = Vec::new();
+    let mut before = None;
+    loop {
+        let page = rpc_client
+            .get_signatures_for_address_with_config(
+                address,
+                GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until: None,
+                    limit: None,
+                    commitment: Some(CommitmentConfig::confirmed()),
+                },
+            )
+            .unwrap();
+        let Some(last) = page.last() else {
+            break;
+        };
+        before = Some(last.signature.parse::<Signature>().unwrap());
+        signatures.extend(
+            page.iter()
+                .filter(|status| status.err.is_none())
+                .map(|status| status.signature.parse::<Signature>().unwrap()),
+        );
+    }
+    signatures.reverse();
+    signatures
+}
+
+/// Prints the liquidity and fee collection timeline of the position minted as `position_nft`.
+pub fn position_timeline(rpc_client: RpcClient, raydium_v3_program: Pubkey, position_nft: Pubkey) {
+    let (personal_position, _) = Pubkey::find_program_address(
+        &[POSITION_SEED.as_bytes(), position_nft.to_bytes().as_ref()],
+        &raydium_v3_program,
+    );
+    println!("personal position:{}", personal_position);
+
+    let mut liquidity: u128 = 0;
+    let (mut deposited_0, mut deposited_1) = (0u64, 0u64);
+    let (mut withdrawn_0, mut withdrawn_1) = (0u64, 0u64);
+    let (mut collected_fee_0, mut collected_fee_1) = (0u64, 0u64);
+    for signature in get_all_signatures_for_address(&rpc_client, &personal_position) {
+        let txn = rpc_client
+            .get_transaction_with_config(
+                &signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Base64),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                    max_supported_transaction_version: Some(0),
+                },
+            )
+            .unwrap();
+        let Some(meta) = txn.transaction.meta else {
+            continue;
+        };
+        let versioned_txn = txn.transaction.transaction.decode().unwrap();
+
+        // lookup table addresses follow the static keys, writable ones first
+        let mut account_keys = versioned_txn.message.static_account_keys().to_vec();
+        if let Some(loaded_addresses) = Option::<UiLoadedAddresses>::from(meta.loaded_addresses) {
+            account_keys.extend(
+                loaded_addresses
+                    .writable
+                    .iter()
+                    .chain(&loaded_addresses.readonly)
+                    .map(|key| key.parse::<Pubkey>().unwrap()),
+            );
+        }
+        let instruction_names: Vec<&str> = versioned_txn
+            .message
+            .instructions()
+            .iter()
+            .filter(|ix| account_keys[ix.program_id_index as usize] == raydium_v3_program)
+            .filter(|ix| {
+                ix.accounts
+                    .iter()
+                    .any(|index| account_keys[*index as usize] == personal_position)
+            })
+            .filter_map(|ix| position_instruction_name(&ix.data))
+            .collect();
+        println!(
+            "{} slot:{} signature:{}",
+            txn.block_time.unwrap_or_default(),
+            txn.slot,
+            signature
+        );
+        if !instruction_names.is_empty() {
+            println!("    instructions:{}", instruction_names.join(", "));
+        }
+
+        let log_messages = Option::<Vec<String>>::from(meta.log_messages).unwrap_or_default();
+        for data in log_messages
+            .iter()
+            .filter_map(|log| log.strip_prefix(PROGRAM_DATA_LOG))
+            .filter_map(|data| STANDARD.decode(data).ok())
+            .filter(|data| data.len() >= 8)
+        {
+            let (discriminator, mut event_data) = data.split_at(8);
+            if discriminator == IncreaseLiquidityEvent::DISCRIMINATOR {
+                let event = IncreaseLiquidityEvent::deserialize(&mut event_data).unwrap();
+                if event.position_nft_mint != position_nft {
+                    continue;
+                }
+                liquidity += event.liquidity;
+                deposited_0 += event.amount_0;
+                deposited_1 += event.amount_1;
+                println!(
+                    "    increase liquidity:{}, amount_0:{}, amount_1:{}, total liquidity:{}",
+                    event.liquidity, event.amount_0, event.amount_1, liquidity
+                );
+            } else if discriminator == DecreaseLiquidityEvent::DISCRIMINATOR {
+                let event = DecreaseLiquidityEvent::deserialize(&mut event_data).unwrap();
+                if event.position_nft_mint != position_nft {
+                    continue;
+                }
+                liquidity = liquidity.saturating_sub(event.liquidity);
+                withdrawn_0 += event.decrease_amount_0;
+                withdrawn_1 += event.decrease_amount_1;
+                println!(
+                    "    decrease liquidity:{}, amount_0:{}, amount_1:{}, total liquidity:{}",
+                    event.liquidity, event.decrease_amount_0, event.decrease_amount_1, liquidity
+                );
+                println!(
+                    "    fee_0:{}, fee_1:{}, rewards:{:?}",
+                    event.fee_amount_0, event.fee_amount_1, event.reward_amounts
+                );
+            } else if discriminator == CollectPersonalFeeEvent::DISCRIMINATOR {
+                // fees are only counted here, the decrease event repeats the same amounts
+                let event = CollectPersonalFeeEvent::deserialize(&mut event_data).unwrap();
+                if event.position_nft_mint != position_nft {
+                    continue;
+                }
+                collected_fee_0 += event.amount_0;
+                collected_fee_1 += event.amount_1;
+                println!(
+                    "    collect fee amount_0:{}, amount_1:{}",
+                    event.amount_0, event.amount_1
+                );
+            }
+        }
+    }
+    println!(
+        "liquidity:{}, deposited amount_0:{}, amount_1:{}, withdrawn amount_0:{}, amount_1:{}",
+        liquidity, deposited_0, deposited_1, withdrawn_0, withdrawn_1
+    );
+    println!(
+        "collected fee_0:{}, fee_1:{}",
+        collected_fee_0, collected_fee_1
+    );
+}
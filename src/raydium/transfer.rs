@@ -0,0 +1,122 @@
+use std::{path::Path, time::Duration};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+
+use crate::{
+    journal::{JournalEntry, Operation, append_journal_entry},
+    send_txn,
+};
+
+use super::{
+    position::{get_all_nft_and_position_by_owner, get_all_personal_positions_by_owner},
+    utils::deserialize_anchor_account,
+};
+
+/// Times the new owner's positions are looked up before giving up on finding the transferred one.
+const VERIFY_ATTEMPTS: usize = 10;
+
+/// Moves the position NFT to `destination`, creating its token account when needed, then checks
+/// that the position is found from the new owner.
+pub fn transfer_position(
+    rpc_client: RpcClient,
+    payer: Keypair,
+    raydium_v3_program: Pubkey,
+    position_nft: Pubkey,
+    destination: Pubkey,
+    data_dir: &Path,
+) {
+    let position_nft_infos =
+        get_all_nft_and_position_by_owner(&rpc_client, &payer.pubkey(), &raydium_v3_program);
+    let nft_info = position_nft_infos
+        .iter()
+        .find(|nft_info| nft_info.mint == position_nft)
+        .expect("position nft not held by the payer");
+    let position = deserialize_anchor_account::<raydium_amm_v3::states::PersonalPositionState>(
+        &rpc_client.get_account(&nft_info.position).unwrap(),
+    )
+    .unwrap();
+
+    // the destination account must belong to the same token program as the NFT mint
+    let destination_token_account = get_associated_token_address_with_program_id(
+        &destination,
+        &position_nft,
+        &nft_info.program,
+    );
+    let transfer_instr = if nft_info.program == spl_token::id() {
+        spl_token::instruction::transfer_checked(
+            &nft_info.program,
+            &nft_info.key,
+            &position_nft,
+            &destination_token_account,
+            &payer.pubkey(),
+            &[],
+            nft_info.amount,
+            nft_info.decimals,
+        )
+        .unwrap()
+    } else {
+        spl_token_2022::instruction::transfer_checked(
+            &nft_info.program,
+            &nft_info.key,
+            &position_nft,
+            &destination_token_account,
+            &payer.pubkey(),
+            &[],
+            nft_info.amount,
+            nft_info.decimals,
+        )
+        .unwrap()
+    };
+    let instructions = vec![
+        create_associated_token_account_idempotent(
+            &payer.pubkey(),
+            &destination,
+            &position_nft,
+            &nft_info.program,
+        ),
+        transfer_instr,
+    ];
+    let recent_hash = rpc_client.get_latest_blockhash().unwrap();
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[&payer],
+        recent_hash,
+    );
+    let signature = send_txn(&rpc_client, &txn, true);
+    println!("{}", signature);
+
+    let mut entry = JournalEntry::new(&rpc_client, &signature, Operation::TransferPosition);
+    entry.pool = Some(position.pool_id.to_string());
+    entry.position_nft = Some(position_nft.to_string());
+    entry.tick_lower_index = Some(position.tick_lower_index);
+    entry.tick_upper_index = Some(position.tick_upper_index);
+    entry.liquidity = Some(position.liquidity);
+    entry.destination = Some(destination.to_string());
+    append_journal_entry(data_dir, &entry);
+
+    // token accounts of the new owner can lag behind the confirmed transfer
+    for _ in 0..VERIFY_ATTEMPTS {
+        let found =
+            get_all_personal_positions_by_owner(&rpc_client, &destination, &raydium_v3_program)
+                .iter()
+                .any(|(_, position)| position.nft_mint == position_nft);
+        if found {
+            println!(
+                "position nft_mint:{} is now held by {} in {}",
+                position_nft, destination, destination_token_account
+            );
+            return;
+        }
+        std::thread::sleep(Duration::from_secs(2));
+    }
+    println!(
+        "position nft_mint:{} was sent but is not found from {}",
+        position_nft, destination
+    );
+}
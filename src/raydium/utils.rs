@@ -3,8 +3,10 @@ use anyhow::Result;
 use raydium_amm_v3::libraries::fixed_point_64;
 use raydium_amm_v3::libraries::*;
 use raydium_amm_v3::states::*;
-use solana_client::rpc_client::RpcClient;
-use solana_sdk::{account::Account, pubkey::Pubkey};
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{
+    account::Account, clock::Clock, commitment_config::CommitmentConfig, pubkey::Pubkey, sysvar,
+};
 use spl_token_2022::{
     extension::{
         BaseState, BaseStateWithExtensions, ExtensionType, StateWithExtensions,
@@ -19,7 +21,7 @@ use spl_token_2022::{
         permanent_delegate::PermanentDelegate,
         transfer_fee::{MAX_FEE_BASIS_POINTS, TransferFeeAmount, TransferFeeConfig},
     },
-    state::Mint,
+    state::{Account as TokenAccount, Mint},
 };
 use std::collections::VecDeque;
 use std::ops::{DerefMut, Mul, Neg};
@@ -53,6 +55,50 @@ pub struct TransferFeeInfo {
     pub transfer_fee: u64,
 }
 
+/// Reads the cluster's unix timestamp from the clock sysvar.
+pub fn get_chain_timestamp(rpc_client: &RpcClient) -> u64 {
+    try_get_chain_timestamp(rpc_client).unwrap()
+}
+
+/// Like `get_chain_timestamp`, but hands a failed read back to the caller.
+pub fn try_get_chain_timestamp(rpc_client: &RpcClient) -> Result<u64, ClientError> {
+    let clock_account = rpc_client.get_account(&sysvar::clock::id())?;
+    let clock: Clock = bincode::deserialize(&clock_account.data).unwrap();
+    Ok(clock.unix_timestamp as u64)
+}
+
+/// Loads token account balances at confirmed commitment, missing accounts count as zero.
+pub fn get_token_account_balances(rpc_client: &RpcClient, token_accounts: &[Pubkey]) -> Vec<u64> {
+    try_get_token_account_balances(rpc_client, token_accounts).unwrap()
+}
+
+/// Like `get_token_account_balances`, but hands a failed read back to the caller.
+pub fn try_get_token_account_balances(
+    rpc_client: &RpcClient,
+    token_accounts: &[Pubkey],
+) -> Result<Vec<u64>, ClientError> {
+    let rsps = rpc_client
+        .get_multiple_accounts_with_commitment(token_accounts, CommitmentConfig::confirmed())?
+        .value;
+    Ok(rsps
+        .into_iter()
+        .map(|rsp| match rsp {
+            None => 0,
+            Some(account) => StateWithExtensions::<TokenAccount>::unpack(&account.data)
+                .map(|token_account| token_account.base.amount)
+                .unwrap_or(0),
+        })
+        .collect())
+}
+
+pub fn get_mint_decimals(rpc_client: &RpcClient, mint: &Pubkey) -> u8 {
+    let mint_account = rpc_client.get_account(mint).unwrap();
+    StateWithExtensions::<Mint>::unpack(&mint_account.data)
+        .unwrap()
+        .base
+        .decimals
+}
+
 pub fn amount_with_slippage(amount: u64, slippage: f64, round_up: bool) -> u64 {
     if round_up {
         (amount as f64).mul(1_f64 + slippage).ceil() as u64
@@ -275,6 +321,20 @@ pub fn sqrt_price_x64_to_price(price: u128, decimals_0: u8, decimals_1: u8) -> f
     from_x64_price(price).powi(2) * multipler(decimals_0) / multipler(decimals_1)
 }
 
+/// Converts a price between the pool orientation (token_1 per token_0) and the inverted one.
+pub fn orient_price(price: f64, invert: bool) -> f64 {
+    if invert { 1.0 / price } else { price }
+}
+
+/// Converts range bounds between orientations, swapping them when the prices are inverted.
+pub fn orient_price_range(lower: f64, upper: f64, invert: bool) -> (f64, f64) {
+    if invert {
+        (1.0 / upper, 1.0 / lower)
+    } else {
+        (lower, upper)
+    }
+}
+
 // the top level state of the swap, the results of which are recorded in storage at the end
 #[derive(Debug)]
 pub struct SwapState {
@@ -393,6 +453,73 @@ fn swap_compute(
     }
     let mut tick_array_start_index_vec = VecDeque::new();
     tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
+    swap_steps(
+        &mut state,
+        zero_for_one,
+        is_base_input,
+        fee,
+        sqrt_price_limit_x64,
+        |tick| {
+            // save the bitmap, and the tick account if it is initialized
+            let mut next_initialized_tick = if let Some(tick_state) = tick_array_current
+                .next_initialized_tick(tick, pool_state.tick_spacing, zero_for_one)
+                .unwrap()
+            {
+                *tick_state
+            } else {
+                if !tick_match_current_tick_array {
+                    tick_match_current_tick_array = true;
+                    *tick_array_current
+                        .first_initialized_tick(zero_for_one)
+                        .unwrap()
+                } else {
+                    TickState::default()
+                }
+            };
+            if !next_initialized_tick.is_initialized() {
+                let current_vaild_tick_array_start_index = pool_state
+                    .next_initialized_tick_array_start_index(
+                        &Some(*tickarray_bitmap_extension),
+                        current_vaild_tick_array_start_index,
+                        zero_for_one,
+                    )
+                    .unwrap();
+                tick_array_current = tick_arrays.pop_front().unwrap();
+                if current_vaild_tick_array_start_index.is_none() {
+                    return Result::Err("tick array start tick index out of range limit");
+                }
+                if tick_array_current.start_tick_index
+                    != current_vaild_tick_array_start_index.unwrap()
+                {
+                    return Result::Err("tick array start tick index does not match");
+                }
+                tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
+                let mut first_initialized_tick = tick_array_current
+                    .first_initialized_tick(zero_for_one)
+                    .unwrap();
+
+                next_initialized_tick = *first_initialized_tick.deref_mut();
+            }
+            Ok(next_initialized_tick)
+        },
+        |_, _| {},
+    )?;
+
+    Ok((state.amount_calculated, tick_array_start_index_vec))
+}
+
+/// Runs the swap loop from `state` until the amount is used up or the limit price is reached.
+/// `next_tick` returns the next initialized tick in the swap direction from the given tick, and
+/// `on_step` is called with each step and the liquidity it was swapped against.
+pub fn swap_steps(
+    state: &mut SwapState,
+    zero_for_one: bool,
+    is_base_input: bool,
+    fee: u32,
+    sqrt_price_limit_x64: u128,
+    mut next_tick: impl FnMut(i32) -> Result<TickState, &'static str>,
+    mut on_step: impl FnMut(&swap_math::SwapStep, u128),
+) -> Result<(), &'static str> {
     let mut loop_count = 0;
     // loop across ticks until input liquidity is consumed, or the limit price is reached
     while state.amount_specified_remaining != 0
@@ -405,47 +532,7 @@ fn swap_compute(
         }
         let mut step = StepComputations::default();
         step.sqrt_price_start_x64 = state.sqrt_price_x64;
-        // save the bitmap, and the tick account if it is initialized
-        let mut next_initialized_tick = if let Some(tick_state) = tick_array_current
-            .next_initialized_tick(state.tick, pool_state.tick_spacing, zero_for_one)
-            .unwrap()
-        {
-            Box::new(*tick_state)
-        } else {
-            if !tick_match_current_tick_array {
-                tick_match_current_tick_array = true;
-                Box::new(
-                    *tick_array_current
-                        .first_initialized_tick(zero_for_one)
-                        .unwrap(),
-                )
-            } else {
-                Box::new(TickState::default())
-            }
-        };
-        if !next_initialized_tick.is_initialized() {
-            let current_vaild_tick_array_start_index = pool_state
-                .next_initialized_tick_array_start_index(
-                    &Some(*tickarray_bitmap_extension),
-                    current_vaild_tick_array_start_index,
-                    zero_for_one,
-                )
-                .unwrap();
-            tick_array_current = tick_arrays.pop_front().unwrap();
-            if current_vaild_tick_array_start_index.is_none() {
-                return Result::Err("tick array start tick index out of range limit");
-            }
-            if tick_array_current.start_tick_index != current_vaild_tick_array_start_index.unwrap()
-            {
-                return Result::Err("tick array start tick index does not match");
-            }
-            tick_array_start_index_vec.push_back(tick_array_current.start_tick_index);
-            let mut first_initialized_tick = tick_array_current
-                .first_initialized_tick(zero_for_one)
-                .unwrap();
-
-            next_initialized_tick = Box::new(*first_initialized_tick.deref_mut());
-        }
+        let next_initialized_tick = next_tick(state.tick)?;
         step.tick_next = next_initialized_tick.tick;
         step.initialized = next_initialized_tick.is_initialized();
         if step.tick_next < MIN_TICK {
@@ -474,6 +561,7 @@ fn swap_compute(
             1,
         )
         .unwrap();
+        on_step(&swap_step, state.liquidity);
         state.sqrt_price_x64 = swap_step.sqrt_price_next_x64;
         step.amount_in = swap_step.amount_in;
         step.amount_out = swap_step.amount_out;
@@ -522,5 +610,13 @@ fn swap_compute(
         loop_count += 1;
     }
 
-    Ok((state.amount_calculated, tick_array_start_index_vec))
+    Ok(())
+}
+
+/// Part of a swap fee left to the liquidity providers once the protocol and fund fees are taken,
+/// rounded the way the swap instruction rounds them.
+pub fn lp_fee_amount(fee_amount: u64, amm_config: &AmmConfig) -> u64 {
+    let share =
+        |rate: u32| (fee_amount as u128 * rate as u128 / FEE_RATE_DENOMINATOR_VALUE as u128) as u64;
+    fee_amount - share(amm_config.protocol_fee_rate) - share(amm_config.fund_fee_rate)
 }
@@ -0,0 +1,299 @@
+use std::{collections::HashMap, path::Path};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+
+use crate::{
+    journal::{JournalEntry, MovementKind, Operation, append_journal_entry},
+    raydium::utils::{get_token_account_balances, try_get_token_account_balances},
+    send_txn, try_send_txn,
+};
+
+use super::{
+    instructions::{
+        borrow_obligation_liquidity_instr, refresh_obligation_instrs, refresh_reserve_instr,
+        repay_obligation_liquidity_instr,
+    },
+    obligation::{ObligationHealth, compute_health, find_obligation, try_find_obligation},
+    oracle::refresh_market_prices,
+    state::{Reserve, WAD, get_reserves, try_get_reserves},
+};
+
+/// Borrow fee the reserve adds to the debt on top of `amount`, rounded up as the program does.
+pub fn borrow_fee(reserve: &Reserve, amount: u64) -> u64 {
+    let fee_wads = amount as u128 * reserve.config.borrow_fee_wad as u128;
+    fee_wads.div_ceil(WAD) as u64
+}
+
+/// Checks that borrowing `amount` from `reserve` keeps the obligation at or below `max_ltv`,
+/// itself below the liquidation LTV, and within the borrow limit. The LTV is at market prices
+/// like liquidations, the borrow limit at the bounds of the market and smoothed prices like the
+/// program's own check. Returns the LTV after the borrow.
+pub fn check_borrow(
+    health: &ObligationHealth,
+    reserve: &Reserve,
+    amount: u64,
+    max_ltv: f64,
+) -> Result<f64, String> {
+    if health.deposited_value == 0.0 {
+        return Err("the obligation has no collateral".to_string());
+    }
+    if max_ltv >= health.liquidation_loan_to_value() {
+        return Err(format!(
+            "ltv ceiling {:.2}% is not below the liquidation ltv {:.2}%",
+            max_ltv * 100.0,
+            health.liquidation_loan_to_value() * 100.0
+        ));
+    }
+    let debt = amount + borrow_fee(reserve, amount);
+    let weighted_debt = debt as f64 / 10f64.powi(reserve.liquidity_mint_decimals as i32)
+        * (1.0 + reserve.config.added_borrow_weight_bps as f64 / 10_000.0);
+    let weighted_borrowed_value =
+        health.weighted_borrowed_value + weighted_debt * reserve.market_price();
+    let weighted_borrowed_value_upper_bound = health.weighted_borrowed_value_upper_bound
+        + weighted_debt * reserve.market_price_upper_bound();
+    let ltv = weighted_borrowed_value / health.deposited_value;
+    if ltv > max_ltv {
+        return Err(format!(
+            "ltv would rise to {:.2}%, above the {:.2}% ceiling",
+            ltv * 100.0,
+            max_ltv * 100.0
+        ));
+    }
+    if weighted_borrowed_value_upper_bound > health.allowed_borrow_value {
+        return Err(format!(
+            "borrows would be worth {:.6}, above the borrow limit {:.6}",
+            weighted_borrowed_value_upper_bound, health.allowed_borrow_value
+        ));
+    }
+    Ok(ltv)
+}
+
+/// Borrows `amount` of `mint` against the payer's obligation, unless the pre-check at the oracle
+/// prices refuses it. Returns the liquidity received.
+pub fn borrow(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+    max_ltv: f64,
+    data_dir: &Path,
+) -> u64 {
+    let (obligation_key, obligation) =
+        find_obligation(rpc_client, save_program, lending_market, &payer.pubkey())
+            .expect("no obligation in the lending market, deposit collateral first");
+    let mut reserves = get_reserves(rpc_client, save_program, lending_market);
+    let (reserve_key, _) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+        .expect("no reserve for the mint in the lending market");
+    let mut required = obligation.reserve_keys();
+    required.push(reserve_key);
+    if let Err(err) = refresh_market_prices(rpc_client, &mut reserves, &required) {
+        println!("refusing to borrow: {}", err);
+        return 0;
+    }
+    let (_, reserve) = *reserves
+        .iter()
+        .find(|(key, _)| *key == reserve_key)
+        .unwrap();
+    let slot = rpc_client.get_slot().unwrap();
+    let reserve_map: HashMap<Pubkey, Reserve> = reserves.iter().cloned().collect();
+    let health = compute_health(&obligation, &reserve_map, slot);
+    let ltv = match check_borrow(&health, &reserve, amount, max_ltv) {
+        Ok(ltv) => ltv,
+        Err(err) => {
+            println!("refusing to borrow: {}", err);
+            return 0;
+        }
+    };
+    if amount > reserve.available_amount {
+        println!(
+            "refusing to borrow: the reserve only has {} available",
+            reserve.available_amount
+        );
+        return 0;
+    }
+
+    let destination_liquidity = get_associated_token_address(&payer.pubkey(), mint);
+    let mut instructions = vec![create_associated_token_account_idempotent(
+        &payer.pubkey(),
+        &payer.pubkey(),
+        mint,
+        &spl_token::id(),
+    )];
+    instructions.extend(refresh_obligation_instrs(
+        save_program,
+        &obligation_key,
+        &obligation,
+        &reserves,
+        &[reserve_key],
+    ));
+    instructions.push(borrow_obligation_liquidity_instr(
+        save_program,
+        amount,
+        &reserve_key,
+        &reserve,
+        &destination_liquidity,
+        &obligation_key,
+        &payer.pubkey(),
+    ));
+    let balance_before = get_token_account_balances(rpc_client, &[destination_liquidity])[0];
+    let recent_hash = rpc_client.get_latest_blockhash().unwrap();
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let signature = send_txn(rpc_client, &txn, true);
+    println!("{}", signature);
+    let received = get_token_account_balances(rpc_client, &[destination_liquidity])[0]
+        .saturating_sub(balance_before);
+    println!(
+        "borrowed {} of mint:{}, ltv is now about {:.2}%",
+        received,
+        mint,
+        ltv * 100.0
+    );
+
+    let mut entry = JournalEntry::new(rpc_client, &signature, Operation::Borrow);
+    entry.reserve = Some(reserve_key.to_string());
+    entry.obligation = Some(obligation_key.to_string());
+    entry.push_movement(
+        mint,
+        MovementKind::Borrow,
+        received,
+        reserve.liquidity_mint_decimals,
+    );
+    append_journal_entry(data_dir, &entry);
+    received
+}
+
+/// Repays `amount` of the payer's `mint` debt, or all of it when `None`. A full repay the wallet
+/// cannot cover repays what the wallet holds instead. Returns the amount repaid.
+pub fn repay(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    amount: Option<u64>,
+    data_dir: &Path,
+) -> u64 {
+    try_repay(
+        rpc_client,
+        payer,
+        save_program,
+        lending_market,
+        mint,
+        amount,
+        data_dir,
+    )
+    .unwrap()
+}
+
+/// Like `repay`, but hands a failed read or transaction back to the caller.
+pub fn try_repay(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    amount: Option<u64>,
+    data_dir: &Path,
+) -> Result<u64, String> {
+    let (obligation_key, obligation) =
+        try_find_obligation(rpc_client, save_program, lending_market, &payer.pubkey())
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| "no obligation in the lending market".to_string())?;
+    let reserves = try_get_reserves(rpc_client, save_program, lending_market)
+        .map_err(|err| err.to_string())?;
+    let (reserve_key, reserve) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+        .ok_or_else(|| format!("no reserve for mint:{} in the lending market", mint))?;
+    let slot = rpc_client.get_slot().map_err(|err| err.to_string())?;
+    let reserve_map: HashMap<Pubkey, Reserve> = reserves.iter().cloned().collect();
+    let health = compute_health(&obligation, &reserve_map, slot);
+    let Some(owed) = health
+        .borrows
+        .iter()
+        .find(|borrow| borrow.reserve == reserve_key)
+        .map(|borrow| borrow.amount.ceil() as u64)
+    else {
+        println!("nothing borrowed from reserve:{}", reserve_key);
+        return Ok(0);
+    };
+
+    let source_liquidity = get_associated_token_address(&payer.pubkey(), mint);
+    let balance_before = try_get_token_account_balances(rpc_client, &[source_liquidity])
+        .map_err(|err| err.to_string())?[0];
+    let repay_amount = match amount {
+        Some(amount) => amount,
+        None if balance_before < owed => {
+            println!(
+                "wallet holds {}, less than the {} owed, repaying what it holds",
+                balance_before, owed
+            );
+            balance_before
+        }
+        // the program caps the maximum at the debt, interest accrued in the same slot included
+        None => u64::MAX,
+    };
+    if repay_amount == 0 {
+        println!("nothing to repay from the wallet");
+        return Ok(0);
+    }
+    let instructions = vec![
+        refresh_reserve_instr(save_program, &reserve_key, &reserve),
+        repay_obligation_liquidity_instr(
+            save_program,
+            repay_amount,
+            &source_liquidity,
+            &reserve_key,
+            &reserve,
+            &obligation_key,
+            &payer.pubkey(),
+        ),
+    ];
+    let recent_hash = rpc_client
+        .get_latest_blockhash()
+        .map_err(|err| err.to_string())?;
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let signature = try_send_txn(rpc_client, &txn, true).map_err(|err| err.to_string())?;
+    println!("{}", signature);
+    // the repay confirmed, so a failed read only loses the exact amount, not the entry
+    let repaid = match try_get_token_account_balances(rpc_client, &[source_liquidity]) {
+        Ok(balances) => balance_before.saturating_sub(balances[0]),
+        Err(_) => repay_amount.min(balance_before).min(owed),
+    };
+    println!(
+        "repaid {} of mint:{}, about {} still owed",
+        repaid,
+        mint,
+        owed.saturating_sub(repaid)
+    );
+
+    let mut entry = JournalEntry::new(rpc_client, &signature, Operation::Repay);
+    entry.reserve = Some(reserve_key.to_string());
+    entry.obligation = Some(obligation_key.to_string());
+    entry.push_movement(
+        mint,
+        MovementKind::Repay,
+        repaid,
+        reserve.liquidity_mint_decimals,
+    );
+    append_journal_entry(data_dir, &entry);
+    Ok(repaid)
+}
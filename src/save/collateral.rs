@@ -0,0 +1,233 @@
+use std::{collections::HashMap, path::Path};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+
+use crate::{
+    journal::{JournalEntry, Operation, append_journal_entry},
+    raydium::utils::get_token_account_balances,
+    send_txn,
+};
+
+use super::{
+    deposit::obligation_for_deposit,
+    instructions::{
+        deposit_obligation_collateral_instr, refresh_obligation_instrs, refresh_reserve_instr,
+        withdraw_obligation_collateral_instr,
+    },
+    obligation::{ObligationHealth, compute_health, find_obligation},
+    oracle::refresh_market_prices,
+    state::{Reserve, get_reserves},
+};
+
+/// Checks that taking `collateral_amount` cTokens of `reserve` out of the obligation keeps its
+/// borrows at or below `max_ltv` and within the borrow limit, the limit valued at the bounds of
+/// the market and smoothed prices like the program's own check. Returns the LTV afterwards.
+pub fn check_collateral_withdrawal(
+    health: &ObligationHealth,
+    reserve: &Reserve,
+    collateral_amount: u64,
+    max_ltv: f64,
+) -> Result<f64, String> {
+    if health.borrows.is_empty() {
+        return Ok(0.0);
+    }
+    let removed_amount = reserve.collateral_to_liquidity(collateral_amount) as f64
+        / 10f64.powi(reserve.liquidity_mint_decimals as i32);
+    let deposited_value = health.deposited_value - removed_amount * reserve.market_price();
+    let allowed_borrow_value = health.allowed_borrow_value
+        - removed_amount
+            * reserve.market_price_lower_bound()
+            * reserve.config.loan_to_value_ratio as f64
+            / 100.0;
+    if deposited_value <= 0.0 {
+        return Err("the borrows would be left without collateral".to_string());
+    }
+    let ltv = health.weighted_borrowed_value / deposited_value;
+    if ltv > max_ltv {
+        return Err(format!(
+            "ltv would rise to {:.2}%, above the {:.2}% ceiling",
+            ltv * 100.0,
+            max_ltv * 100.0
+        ));
+    }
+    if health.weighted_borrowed_value_upper_bound > allowed_borrow_value {
+        return Err(format!(
+            "borrows worth {:.6} would exceed the borrow limit {:.6}",
+            health.weighted_borrowed_value_upper_bound, allowed_borrow_value
+        ));
+    }
+    Ok(ltv)
+}
+
+/// Moves `collateral_amount` cTokens of `mint`'s reserve, or all the wallet holds, into the
+/// payer's obligation, creating it when missing.
+pub fn deposit_collateral(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    collateral_amount: Option<u64>,
+    data_dir: &Path,
+) {
+    let reserves = get_reserves(rpc_client, save_program, lending_market);
+    let (reserve_key, reserve) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+        .expect("no reserve for the mint in the lending market");
+    let source_collateral = get_associated_token_address(&payer.pubkey(), &reserve.collateral_mint);
+    let balance_before = get_token_account_balances(rpc_client, &[source_collateral])[0];
+    let collateral_amount = collateral_amount.unwrap_or(balance_before);
+    if collateral_amount == 0 || collateral_amount > balance_before {
+        println!(
+            "refusing to deposit {} ctokens, the wallet holds {}",
+            collateral_amount, balance_before
+        );
+        return;
+    }
+    let (obligation_key, mut instructions) = match obligation_for_deposit(
+        rpc_client,
+        payer,
+        save_program,
+        lending_market,
+        &reserve_key,
+    ) {
+        Ok(obligation) => obligation,
+        Err(err) => {
+            println!("refusing to deposit: {}", err);
+            return;
+        }
+    };
+    instructions.push(refresh_reserve_instr(save_program, &reserve_key, &reserve));
+    instructions.push(deposit_obligation_collateral_instr(
+        save_program,
+        collateral_amount,
+        &source_collateral,
+        &reserve_key,
+        &reserve,
+        &obligation_key,
+        &payer.pubkey(),
+    ));
+    let recent_hash = rpc_client.get_latest_blockhash().unwrap();
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let signature = send_txn(rpc_client, &txn, true);
+    println!("{}", signature);
+    let deposited = balance_before
+        .saturating_sub(get_token_account_balances(rpc_client, &[source_collateral])[0]);
+    println!(
+        "moved {} ctokens of mint:{} into obligation:{}",
+        deposited, reserve.collateral_mint, obligation_key
+    );
+
+    let mut entry = JournalEntry::new(rpc_client, &signature, Operation::DepositCollateral);
+    entry.reserve = Some(reserve_key.to_string());
+    entry.obligation = Some(obligation_key.to_string());
+    entry.collateral_amount = Some(deposited);
+    append_journal_entry(data_dir, &entry);
+}
+
+/// Moves `collateral_amount` cTokens of `mint`'s reserve, or all of them, out of the payer's
+/// obligation into the wallet, unless the borrows left would break `max_ltv`.
+pub fn withdraw_collateral(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    collateral_amount: Option<u64>,
+    max_ltv: f64,
+    data_dir: &Path,
+) {
+    let (obligation_key, obligation) =
+        find_obligation(rpc_client, save_program, lending_market, &payer.pubkey())
+            .expect("no obligation in the lending market");
+    let mut reserves = get_reserves(rpc_client, save_program, lending_market);
+    if let Err(err) = refresh_market_prices(rpc_client, &mut reserves, &obligation.reserve_keys()) {
+        println!("refusing to withdraw: {}", err);
+        return;
+    }
+    let (reserve_key, reserve) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+        .expect("no reserve for the mint in the lending market");
+    let Some(deposited_amount) = obligation
+        .deposits
+        .iter()
+        .find(|deposit| deposit.deposit_reserve == reserve_key)
+        .map(|deposit| deposit.deposited_amount)
+    else {
+        println!("no collateral from reserve:{}", reserve_key);
+        return;
+    };
+    let collateral_amount = collateral_amount.unwrap_or(deposited_amount);
+    if collateral_amount > deposited_amount {
+        println!(
+            "refusing to withdraw {} ctokens, the obligation holds {}",
+            collateral_amount, deposited_amount
+        );
+        return;
+    }
+    let slot = rpc_client.get_slot().unwrap();
+    let reserve_map: HashMap<Pubkey, Reserve> = reserves.iter().cloned().collect();
+    let health = compute_health(&obligation, &reserve_map, slot);
+    if let Err(err) = check_collateral_withdrawal(&health, &reserve, collateral_amount, max_ltv) {
+        println!("refusing to withdraw: {}", err);
+        return;
+    }
+
+    let destination_collateral =
+        get_associated_token_address(&payer.pubkey(), &reserve.collateral_mint);
+    let mut instructions = vec![create_associated_token_account_idempotent(
+        &payer.pubkey(),
+        &payer.pubkey(),
+        &reserve.collateral_mint,
+        &spl_token::id(),
+    )];
+    instructions.extend(refresh_obligation_instrs(
+        save_program,
+        &obligation_key,
+        &obligation,
+        &reserves,
+        &[],
+    ));
+    instructions.push(withdraw_obligation_collateral_instr(
+        save_program,
+        collateral_amount,
+        &destination_collateral,
+        &reserve_key,
+        &reserve,
+        &obligation_key,
+        &payer.pubkey(),
+    ));
+    let balance_before = get_token_account_balances(rpc_client, &[destination_collateral])[0];
+    let recent_hash = rpc_client.get_latest_blockhash().unwrap();
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let signature = send_txn(rpc_client, &txn, true);
+    println!("{}", signature);
+    let withdrawn = get_token_account_balances(rpc_client, &[destination_collateral])[0]
+        .saturating_sub(balance_before);
+    println!(
+        "moved {} ctokens of mint:{} out of obligation:{}",
+        withdrawn, reserve.collateral_mint, obligation_key
+    );
+
+    let mut entry = JournalEntry::new(rpc_client, &signature, Operation::WithdrawCollateral);
+    entry.reserve = Some(reserve_key.to_string());
+    entry.obligation = Some(obligation_key.to_string());
+    entry.collateral_amount = Some(withdrawn);
+    append_journal_entry(data_dir, &entry);
+}
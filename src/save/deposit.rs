@@ -0,0 +1,191 @@
+use std::path::Path;
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+
+use crate::{
+    journal::{JournalEntry, MovementKind, Operation, append_journal_entry},
+    raydium::utils::get_token_account_balances,
+    send_txn,
+};
+
+use super::{
+    instructions::{
+        deposit_reserve_liquidity_and_obligation_collateral_instr, deposit_reserve_liquidity_instr,
+        init_obligation_instrs, refresh_reserve_instr,
+    },
+    obligation::{find_obligation, get_deposited_collateral},
+    state::{MAX_OBLIGATION_RESERVES, OBLIGATION_LEN, WAD, get_reserves, obligation_address},
+};
+
+/// The payer's obligation in the lending market, with the instructions creating it when it does
+/// not exist yet. Refuses an obligation that has no room left for a deposit in `reserve_key`.
+pub fn obligation_for_deposit(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    reserve_key: &Pubkey,
+) -> Result<(Pubkey, Vec<Instruction>), String> {
+    match find_obligation(rpc_client, save_program, lending_market, &payer.pubkey()) {
+        Some((obligation_key, obligation)) => {
+            let holds_reserve = obligation
+                .deposits
+                .iter()
+                .any(|deposit| deposit.deposit_reserve == *reserve_key);
+            if !holds_reserve
+                && obligation.deposits.len() + obligation.borrows.len() >= MAX_OBLIGATION_RESERVES
+            {
+                return Err(format!(
+                    "obligation:{} already uses {} reserves",
+                    obligation_key, MAX_OBLIGATION_RESERVES
+                ));
+            }
+            Ok((obligation_key, Vec::new()))
+        }
+        None => {
+            let lamports = rpc_client
+                .get_minimum_balance_for_rent_exemption(OBLIGATION_LEN)
+                .unwrap();
+            Ok((
+                obligation_address(save_program, lending_market, &payer.pubkey()),
+                init_obligation_instrs(save_program, lending_market, &payer.pubkey(), lamports),
+            ))
+        }
+    }
+}
+
+/// Supplies `amount` of `mint` to its reserve. The cTokens stay in the wallet, or with
+/// `as_collateral` go into the payer's obligation, which is created when missing. `pool` is the
+/// pool the tokens came out of, recorded in the journal. Returns the liquidity deposited.
+pub fn deposit(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    amount: u64,
+    as_collateral: bool,
+    pool: Option<&Pubkey>,
+    data_dir: &Path,
+) -> u64 {
+    let reserves = get_reserves(rpc_client, save_program, lending_market);
+    let (reserve_key, reserve) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+        .expect("no reserve for the mint in the lending market");
+    let total_liquidity = (reserve.total_liquidity_wads() / WAD) as u64;
+    if total_liquidity + amount > reserve.config.deposit_limit {
+        println!(
+            "refusing to deposit: the reserve holds {} of its {} deposit limit",
+            total_liquidity, reserve.config.deposit_limit
+        );
+        return 0;
+    }
+
+    let source_liquidity = get_associated_token_address(&payer.pubkey(), mint);
+    let user_collateral = get_associated_token_address(&payer.pubkey(), &reserve.collateral_mint);
+    let mut instructions = vec![create_associated_token_account_idempotent(
+        &payer.pubkey(),
+        &payer.pubkey(),
+        &reserve.collateral_mint,
+        &spl_token::id(),
+    )];
+    let mut obligation_key = None;
+    if as_collateral {
+        match obligation_for_deposit(
+            rpc_client,
+            payer,
+            save_program,
+            lending_market,
+            &reserve_key,
+        ) {
+            Ok((key, init_instructions)) => {
+                instructions.extend(init_instructions);
+                obligation_key = Some(key);
+            }
+            Err(err) => {
+                println!("refusing to deposit: {}", err);
+                return 0;
+            }
+        }
+    }
+    instructions.push(refresh_reserve_instr(save_program, &reserve_key, &reserve));
+    instructions.push(match obligation_key {
+        Some(obligation_key) => deposit_reserve_liquidity_and_obligation_collateral_instr(
+            save_program,
+            amount,
+            &source_liquidity,
+            &user_collateral,
+            &reserve_key,
+            &reserve,
+            &obligation_key,
+            &payer.pubkey(),
+        ),
+        None => deposit_reserve_liquidity_instr(
+            save_program,
+            amount,
+            &source_liquidity,
+            &user_collateral,
+            &reserve_key,
+            &reserve,
+            &payer.pubkey(),
+        ),
+    });
+
+    let balances_before =
+        get_token_account_balances(rpc_client, &[source_liquidity, user_collateral]);
+    let collateral_before = obligation_key
+        .map(|obligation_key| get_deposited_collateral(rpc_client, &obligation_key, &reserve_key));
+    let recent_hash = rpc_client.get_latest_blockhash().unwrap();
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let signature = send_txn(rpc_client, &txn, true);
+    println!("{}", signature);
+    let balances_after =
+        get_token_account_balances(rpc_client, &[source_liquidity, user_collateral]);
+    let deposited = balances_before[0].saturating_sub(balances_after[0]);
+    // cTokens deposited as collateral only pass through the wallet, so they are measured in the
+    // obligation
+    let collateral_amount = match (obligation_key, collateral_before) {
+        (Some(obligation_key), Some(collateral_before)) => {
+            get_deposited_collateral(rpc_client, &obligation_key, &reserve_key)
+                .saturating_sub(collateral_before)
+        }
+        _ => balances_after[1].saturating_sub(balances_before[1]),
+    };
+    match obligation_key {
+        Some(obligation_key) => println!(
+            "deposited {} of mint:{} as {} ctokens of collateral in obligation:{}",
+            deposited, mint, collateral_amount, obligation_key
+        ),
+        None => println!(
+            "deposited {} of mint:{}, received {} ctokens of mint:{}",
+            deposited, mint, collateral_amount, reserve.collateral_mint
+        ),
+    }
+
+    let mut entry = JournalEntry::new(rpc_client, &signature, Operation::Deposit);
+    entry.pool = pool.map(|pool| pool.to_string());
+    entry.reserve = Some(reserve_key.to_string());
+    entry.obligation = obligation_key.map(|key| key.to_string());
+    entry.collateral_amount = Some(collateral_amount);
+    entry.push_movement(
+        mint,
+        MovementKind::Deposit,
+        deposited,
+        reserve.liquidity_mint_decimals,
+    );
+    append_journal_entry(data_dir, &entry);
+    deposited
+}
@@ -0,0 +1,389 @@
+//! Builders for the Save lending instructions, encoded as the program's tag byte followed by the
+//! little endian amount.
+
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_instruction, sysvar,
+};
+
+use super::state::{OBLIGATION_LEN, Obligation, Reserve};
+
+const REFRESH_RESERVE: u8 = 3;
+const DEPOSIT_RESERVE_LIQUIDITY: u8 = 4;
+const REDEEM_RESERVE_COLLATERAL: u8 = 5;
+const INIT_OBLIGATION: u8 = 6;
+const REFRESH_OBLIGATION: u8 = 7;
+const DEPOSIT_OBLIGATION_COLLATERAL: u8 = 8;
+const WITHDRAW_OBLIGATION_COLLATERAL: u8 = 9;
+const BORROW_OBLIGATION_LIQUIDITY: u8 = 10;
+const REPAY_OBLIGATION_LIQUIDITY: u8 = 11;
+const DEPOSIT_RESERVE_LIQUIDITY_AND_OBLIGATION_COLLATERAL: u8 = 14;
+const WITHDRAW_OBLIGATION_COLLATERAL_AND_REDEEM_RESERVE_COLLATERAL: u8 = 15;
+
+/// Signs for the lending market's token accounts and mints.
+pub fn lending_market_authority(save_program: &Pubkey, lending_market: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[lending_market.as_ref()], save_program).0
+}
+
+fn amount_data(tag: u8, amount: u64) -> Vec<u8> {
+    let mut data = vec![tag];
+    data.extend_from_slice(&amount.to_le_bytes());
+    data
+}
+
+pub fn refresh_reserve_instr(
+    save_program: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*reserve_key, false),
+        AccountMeta::new_readonly(reserve.pyth_oracle, false),
+        AccountMeta::new_readonly(reserve.switchboard_oracle, false),
+    ];
+    if reserve.config.extra_oracle != Pubkey::default() {
+        accounts.push(AccountMeta::new_readonly(
+            reserve.config.extra_oracle,
+            false,
+        ));
+    }
+    Instruction {
+        program_id: *save_program,
+        accounts,
+        data: vec![REFRESH_RESERVE],
+    }
+}
+
+/// Refreshes every reserve the obligation uses plus `extra_reserves`, then the obligation, as the
+/// program requires before anything that checks its health.
+pub fn refresh_obligation_instrs(
+    save_program: &Pubkey,
+    obligation_key: &Pubkey,
+    obligation: &Obligation,
+    reserves: &[(Pubkey, Reserve)],
+    extra_reserves: &[Pubkey],
+) -> Vec<Instruction> {
+    let deposit_reserves: Vec<Pubkey> = obligation
+        .deposits
+        .iter()
+        .map(|deposit| deposit.deposit_reserve)
+        .collect();
+    let borrow_reserves: Vec<Pubkey> = obligation
+        .borrows
+        .iter()
+        .map(|borrow| borrow.borrow_reserve)
+        .collect();
+    let mut refreshed: Vec<Pubkey> = Vec::new();
+    let mut instructions = Vec::new();
+    for reserve_key in deposit_reserves
+        .iter()
+        .chain(&borrow_reserves)
+        .chain(extra_reserves)
+    {
+        if refreshed.contains(reserve_key) {
+            continue;
+        }
+        let (_, reserve) = reserves
+            .iter()
+            .find(|(key, _)| key == reserve_key)
+            .expect("reserve not in the lending market");
+        instructions.push(refresh_reserve_instr(save_program, reserve_key, reserve));
+        refreshed.push(*reserve_key);
+    }
+    let mut accounts = vec![AccountMeta::new(*obligation_key, false)];
+    accounts.extend(
+        deposit_reserves
+            .iter()
+            .chain(&borrow_reserves)
+            .map(|reserve_key| AccountMeta::new_readonly(*reserve_key, false)),
+    );
+    instructions.push(Instruction {
+        program_id: *save_program,
+        accounts,
+        data: vec![REFRESH_OBLIGATION],
+    });
+    instructions
+}
+
+pub fn borrow_obligation_liquidity_instr(
+    save_program: &Pubkey,
+    liquidity_amount: u64,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    destination_liquidity: &Pubkey,
+    obligation_key: &Pubkey,
+    obligation_owner: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(reserve.liquidity_supply, false),
+            AccountMeta::new(*destination_liquidity, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(reserve.config.fee_receiver, false),
+            AccountMeta::new(*obligation_key, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(
+                lending_market_authority(save_program, &reserve.lending_market),
+                false,
+            ),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(BORROW_OBLIGATION_LIQUIDITY, liquidity_amount),
+    }
+}
+
+/// Repays up to `liquidity_amount`, `u64::MAX` repaying the whole debt.
+pub fn repay_obligation_liquidity_instr(
+    save_program: &Pubkey,
+    liquidity_amount: u64,
+    source_liquidity: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    obligation_key: &Pubkey,
+    transfer_authority: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(*source_liquidity, false),
+            AccountMeta::new(reserve.liquidity_supply, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(*obligation_key, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(*transfer_authority, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(REPAY_OBLIGATION_LIQUIDITY, liquidity_amount),
+    }
+}
+
+/// Creates the obligation account at the seeded address the official clients use, funded with
+/// `lamports` for rent, and initializes it.
+pub fn init_obligation_instrs(
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+    lamports: u64,
+) -> Vec<Instruction> {
+    let seed = &lending_market.to_string()[..32];
+    let obligation_key = Pubkey::create_with_seed(owner, seed, save_program).unwrap();
+    vec![
+        system_instruction::create_account_with_seed(
+            owner,
+            &obligation_key,
+            owner,
+            seed,
+            lamports,
+            OBLIGATION_LEN as u64,
+            save_program,
+        ),
+        Instruction {
+            program_id: *save_program,
+            accounts: vec![
+                AccountMeta::new(obligation_key, false),
+                AccountMeta::new_readonly(*lending_market, false),
+                AccountMeta::new_readonly(*owner, true),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+            data: vec![INIT_OBLIGATION],
+        },
+    ]
+}
+
+/// Supplies liquidity to the reserve, minting cTokens into `destination_collateral`.
+pub fn deposit_reserve_liquidity_instr(
+    save_program: &Pubkey,
+    liquidity_amount: u64,
+    source_liquidity: &Pubkey,
+    destination_collateral: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    transfer_authority: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(*source_liquidity, false),
+            AccountMeta::new(*destination_collateral, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(reserve.liquidity_supply, false),
+            AccountMeta::new(reserve.collateral_mint, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(
+                lending_market_authority(save_program, &reserve.lending_market),
+                false,
+            ),
+            AccountMeta::new_readonly(*transfer_authority, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(DEPOSIT_RESERVE_LIQUIDITY, liquidity_amount),
+    }
+}
+
+/// Supplies liquidity to the reserve and deposits the minted cTokens into the obligation, passing
+/// through `user_collateral`.
+pub fn deposit_reserve_liquidity_and_obligation_collateral_instr(
+    save_program: &Pubkey,
+    liquidity_amount: u64,
+    source_liquidity: &Pubkey,
+    user_collateral: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    obligation_key: &Pubkey,
+    obligation_owner: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(*source_liquidity, false),
+            AccountMeta::new(*user_collateral, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(reserve.liquidity_supply, false),
+            AccountMeta::new(reserve.collateral_mint, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(
+                lending_market_authority(save_program, &reserve.lending_market),
+                false,
+            ),
+            AccountMeta::new(reserve.collateral_supply, false),
+            AccountMeta::new(*obligation_key, false),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(reserve.pyth_oracle, false),
+            AccountMeta::new_readonly(reserve.switchboard_oracle, false),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(
+            DEPOSIT_RESERVE_LIQUIDITY_AND_OBLIGATION_COLLATERAL,
+            liquidity_amount,
+        ),
+    }
+}
+
+/// Moves cTokens from the wallet into the obligation as collateral.
+pub fn deposit_obligation_collateral_instr(
+    save_program: &Pubkey,
+    collateral_amount: u64,
+    source_collateral: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    obligation_key: &Pubkey,
+    obligation_owner: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(*source_collateral, false),
+            AccountMeta::new(reserve.collateral_supply, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(*obligation_key, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(DEPOSIT_OBLIGATION_COLLATERAL, collateral_amount),
+    }
+}
+
+/// Moves cTokens out of the obligation into `destination_collateral`, `u64::MAX` withdrawing as
+/// much as the obligation's health allows. The obligation must be refreshed first.
+pub fn withdraw_obligation_collateral_instr(
+    save_program: &Pubkey,
+    collateral_amount: u64,
+    destination_collateral: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    obligation_key: &Pubkey,
+    obligation_owner: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(reserve.collateral_supply, false),
+            AccountMeta::new(*destination_collateral, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(*obligation_key, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(
+                lending_market_authority(save_program, &reserve.lending_market),
+                false,
+            ),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(WITHDRAW_OBLIGATION_COLLATERAL, collateral_amount),
+    }
+}
+
+/// Burns cTokens from `source_collateral` for the liquidity they are worth.
+pub fn redeem_reserve_collateral_instr(
+    save_program: &Pubkey,
+    collateral_amount: u64,
+    source_collateral: &Pubkey,
+    destination_liquidity: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    transfer_authority: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(*source_collateral, false),
+            AccountMeta::new(*destination_liquidity, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(reserve.collateral_mint, false),
+            AccountMeta::new(reserve.liquidity_supply, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(
+                lending_market_authority(save_program, &reserve.lending_market),
+                false,
+            ),
+            AccountMeta::new_readonly(*transfer_authority, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(REDEEM_RESERVE_COLLATERAL, collateral_amount),
+    }
+}
+
+/// Takes cTokens out of the obligation and redeems them, passing through `user_collateral`. The
+/// obligation must be refreshed first.
+pub fn withdraw_obligation_collateral_and_redeem_reserve_collateral_instr(
+    save_program: &Pubkey,
+    collateral_amount: u64,
+    user_collateral: &Pubkey,
+    destination_liquidity: &Pubkey,
+    reserve_key: &Pubkey,
+    reserve: &Reserve,
+    obligation_key: &Pubkey,
+    obligation_owner: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *save_program,
+        accounts: vec![
+            AccountMeta::new(reserve.collateral_supply, false),
+            AccountMeta::new(*user_collateral, false),
+            AccountMeta::new(*reserve_key, false),
+            AccountMeta::new(*obligation_key, false),
+            AccountMeta::new_readonly(reserve.lending_market, false),
+            AccountMeta::new_readonly(
+                lending_market_authority(save_program, &reserve.lending_market),
+                false,
+            ),
+            AccountMeta::new(*destination_liquidity, false),
+            AccountMeta::new(reserve.collateral_mint, false),
+            AccountMeta::new(reserve.liquidity_supply, false),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(*obligation_owner, true),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+        data: amount_data(
+            WITHDRAW_OBLIGATION_COLLATERAL_AND_REDEEM_RESERVE_COLLATERAL,
+            collateral_amount,
+        ),
+    }
+}
@@ -1,21 +1,152 @@
 use std::rc::Rc;
 
 use anchor_client::Client;
-use raydium_amm_v3::states::POOL_TICK_ARRAY_BITMAP_SEED;
 use solana_client::rpc_client::RpcClient;
-use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+use solana_sdk::{signature::Keypair, signer::Signer};
 
 use crate::cli::{self, Cli};
 
+pub mod borrow;
+pub mod collateral;
 pub mod deposit;
+pub mod instructions;
+pub mod monitor;
+pub mod obligation;
+pub mod oracle;
+pub mod park;
+pub mod reserves;
+pub mod state;
 pub mod withdraw;
 
 pub fn run(args: &Cli, rpc_client: RpcClient, anchor_client: Client<Rc<Keypair>>, payer: Keypair) {
-    let program = anchor_client.program(args.save_program).unwrap();
+    let lending_market = args.lending_market();
+    let data_dir = crate::expand_home_dir(&args.data_dir);
 
     match args.command {
-        cli::CommandsName::Deposit { input_amount } => {}
-        cli::CommandsName::Withdraw => {}
+        cli::CommandsName::Reserves => {
+            reserves::print_reserves(&rpc_client, &args.save_program, &lending_market)
+        }
+        cli::CommandsName::Obligations => obligation::print_obligations(
+            &rpc_client,
+            &args.save_program,
+            &lending_market,
+            &payer.pubkey(),
+        ),
+        cli::CommandsName::Borrow { mint, amount } => {
+            borrow::borrow(
+                &rpc_client,
+                &payer,
+                &args.save_program,
+                &lending_market,
+                &mint,
+                amount,
+                args.max_ltv / 100.0,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::Repay { mint, amount, .. } => {
+            borrow::repay(
+                &rpc_client,
+                &payer,
+                &args.save_program,
+                &lending_market,
+                &mint,
+                amount,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::Deposit {
+            mint,
+            input_amount,
+            collateral,
+        } => {
+            deposit::deposit(
+                &rpc_client,
+                &payer,
+                &args.save_program,
+                &lending_market,
+                &mint,
+                input_amount,
+                collateral,
+                None,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::DepositCollateral {
+            mint,
+            collateral_amount,
+            ..
+        } => collateral::deposit_collateral(
+            &rpc_client,
+            &payer,
+            &args.save_program,
+            &lending_market,
+            &mint,
+            collateral_amount,
+            &data_dir,
+        ),
+        cli::CommandsName::WithdrawCollateral {
+            mint,
+            collateral_amount,
+            ..
+        } => collateral::withdraw_collateral(
+            &rpc_client,
+            &payer,
+            &args.save_program,
+            &lending_market,
+            &mint,
+            collateral_amount,
+            args.max_ltv / 100.0,
+            &data_dir,
+        ),
+        cli::CommandsName::Withdraw {
+            mint,
+            amount,
+            ctokens,
+            all,
+            collateral,
+        } => {
+            let withdraw_amount = match (amount, ctokens) {
+                _ if all => withdraw::WithdrawAmount::All,
+                (Some(amount), _) => withdraw::WithdrawAmount::Liquidity(amount),
+                (None, Some(ctokens)) => withdraw::WithdrawAmount::Collateral(ctokens),
+                (None, None) => unreachable!(),
+            };
+            withdraw::withdraw(
+                &rpc_client,
+                &payer,
+                &args.save_program,
+                &lending_market,
+                &mint,
+                withdraw_amount,
+                collateral,
+                args.max_ltv / 100.0,
+                None,
+                &data_dir,
+            );
+        }
+        cli::CommandsName::WatchObligation {
+            interval_slots,
+            warn_ltv,
+            auto_repay,
+            withdraw_lp,
+        } => monitor::watch_obligation(
+            Rc::new(anchor_client),
+            &rpc_client,
+            &payer,
+            &args.raydium_v3_program,
+            &args.save_program,
+            &lending_market,
+            &monitor::WatchConfig {
+                interval_slots,
+                warn_ltv: warn_ltv / 100.0,
+                target_ltv: args.max_ltv / 100.0,
+                auto_repay,
+                withdraw_lp,
+            },
+            args.slippage,
+            &data_dir,
+        ),
         _ => panic!("unhandled"),
     }
 }
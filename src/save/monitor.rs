@@ -0,0 +1,337 @@
+//! Watches the payer's obligation and pays borrows down before it can be liquidated.
+
+use std::{collections::HashMap, path::Path, rc::Rc, time::Duration};
+
+use anchor_client::Client;
+use raydium_amm_v3::{
+    libraries::{big_num::U256, liquidity_math},
+    states::PoolState,
+};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::{
+    journal::{JournalEntry, Operation, append_journal_entry},
+    raydium::{
+        close_all::close_positions, position::get_all_personal_positions_by_owner,
+        utils::try_get_token_account_balances,
+    },
+};
+
+use super::{
+    borrow::try_repay,
+    obligation::{BorrowValue, compute_health, try_find_obligation},
+    oracle::refresh_market_prices,
+    state::{Reserve, try_get_reserves},
+};
+
+/// Approximate duration of a slot, to wait out the interval between checks.
+const SLOT_DURATION: Duration = Duration::from_millis(400);
+
+/// When the watch acts on the obligation.
+pub struct WatchConfig {
+    /// Slots between health checks
+    pub interval_slots: u64,
+    /// LTV at which to warn and, with `auto_repay`, repay
+    pub warn_ltv: f64,
+    /// LTV the repayments bring the obligation back to
+    pub target_ltv: f64,
+    /// Repay borrows from wallet balances past `warn_ltv`
+    pub auto_repay: bool,
+    /// Close positions holding a borrowed mint when the wallet lacks it
+    pub withdraw_lp: bool,
+}
+
+/// Withdraws about `shortfall` of `mint` from the payer's positions in pools holding it, closing
+/// out of range positions first, then the smallest, and only decreasing the last one by what is
+/// still missing. Returns whether any liquidity was withdrawn.
+fn close_positions_holding(
+    client: &Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    raydium_v3_program: &Pubkey,
+    mint: &Pubkey,
+    shortfall: u64,
+    slippage: f64,
+    data_dir: &Path,
+) -> bool {
+    let program = client.program(*raydium_v3_program).unwrap();
+    let mut pools: HashMap<Pubkey, PoolState> = HashMap::new();
+    // (out of range, amount of the mint held, nft, position)
+    let mut holdings = Vec::new();
+    for (nft_info, position) in
+        get_all_personal_positions_by_owner(rpc_client, &payer.pubkey(), raydium_v3_program)
+    {
+        if position.liquidity == 0 {
+            continue;
+        }
+        let pool = *pools
+            .entry(position.pool_id)
+            .or_insert_with(|| program.account(position.pool_id).unwrap());
+        if pool.token_mint_0 != *mint && pool.token_mint_1 != *mint {
+            continue;
+        }
+        let (amount_0, amount_1) = liquidity_math::get_delta_amounts_signed(
+            pool.tick_current,
+            pool.sqrt_price_x64,
+            position.tick_lower_index,
+            position.tick_upper_index,
+            -(position.liquidity as i128),
+        )
+        .unwrap();
+        let amount = if pool.token_mint_0 == *mint {
+            amount_0
+        } else {
+            amount_1
+        };
+        if amount == 0 {
+            continue;
+        }
+        let out_of_range = pool.tick_current < position.tick_lower_index
+            || pool.tick_current >= position.tick_upper_index;
+        holdings.push((out_of_range, amount, nft_info, position));
+    }
+    holdings.sort_by_key(|(out_of_range, amount, _, _)| (!out_of_range, *amount));
+
+    let mut remaining = shortfall;
+    let mut selected = Vec::new();
+    for (_, amount, nft_info, position) in holdings {
+        if remaining == 0 {
+            break;
+        }
+        let liquidity = if amount <= remaining {
+            position.liquidity
+        } else {
+            // the mint scales with the liquidity, rounded up so the shortfall is covered
+            ((U256::from(position.liquidity) * U256::from(remaining) + U256::from(amount - 1))
+                / U256::from(amount))
+            .as_u128()
+        };
+        println!(
+            "withdrawing liquidity:{} of {} from position nft_mint:{} to free mint:{}",
+            liquidity, position.liquidity, position.nft_mint, mint
+        );
+        remaining = remaining.saturating_sub(amount);
+        selected.push((nft_info, position, liquidity));
+    }
+    if selected.is_empty() {
+        return false;
+    }
+    close_positions(
+        client.clone(),
+        rpc_client,
+        payer,
+        *raydium_v3_program,
+        program,
+        selected,
+        slippage,
+        None,
+        data_dir,
+    );
+    true
+}
+
+/// Repays `borrow` with as much of `excess_value`, in weighted borrow value, as the wallet covers,
+/// closing positions holding the mint first when allowed and needed. Returns the weighted value
+/// repaid, or why the repayment did not go through, which is journaled.
+fn repay_excess(
+    client: &Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    raydium_v3_program: &Pubkey,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    obligation_key: &Pubkey,
+    borrow: &BorrowValue,
+    excess_value: f64,
+    withdraw_lp: bool,
+    slippage: f64,
+    slot: u64,
+    data_dir: &Path,
+) -> Result<f64, String> {
+    let wanted = (excess_value / borrow.weighted_value * borrow.amount)
+        .min(borrow.amount)
+        .ceil() as u64;
+    let token_account = get_associated_token_address(&payer.pubkey(), &borrow.mint);
+    let read_balance = || {
+        try_get_token_account_balances(rpc_client, &[token_account])
+            .map(|balances| balances[0])
+            .map_err(|err| format!("cannot read the balance of mint:{}: {}", borrow.mint, err))
+    };
+    let mut held = read_balance()?;
+    if held < wanted
+        && withdraw_lp
+        && close_positions_holding(
+            client,
+            rpc_client,
+            payer,
+            raydium_v3_program,
+            &borrow.mint,
+            wanted - held,
+            slippage,
+            data_dir,
+        )
+    {
+        held = read_balance()?;
+    }
+    let amount = wanted.min(held);
+    if amount == 0 {
+        println!(
+            "cannot repay mint:{}, the wallet holds none of the {} wanted",
+            borrow.mint, wanted
+        );
+        return Ok(0.0);
+    }
+    match try_repay(
+        rpc_client,
+        payer,
+        save_program,
+        lending_market,
+        &borrow.mint,
+        Some(amount),
+        data_dir,
+    ) {
+        Ok(repaid) => Ok(repaid as f64 / borrow.amount * borrow.weighted_value),
+        Err(err) => {
+            let err = format!(
+                "repaying {} of mint:{} failed: {}",
+                amount, borrow.mint, err
+            );
+            let mut entry = JournalEntry::failed(slot, Operation::Repay, err.clone());
+            entry.reserve = Some(borrow.reserve.to_string());
+            entry.obligation = Some(obligation_key.to_string());
+            append_journal_entry(data_dir, &entry);
+            Err(err)
+        }
+    }
+}
+
+/// Checks the payer's obligation at `slot`, printing its health. Past `warn_ltv` it warns and,
+/// with `auto_repay`, repays the largest borrows first until the LTV is back at `target_ltv`.
+/// Fails when a read or a repayment does not go through.
+fn check_obligation(
+    client: &Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    raydium_v3_program: &Pubkey,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    config: &WatchConfig,
+    slippage: f64,
+    slot: u64,
+    data_dir: &Path,
+) -> Result<(), String> {
+    let Some((obligation_key, obligation)) =
+        try_find_obligation(rpc_client, save_program, lending_market, &payer.pubkey())
+            .map_err(|err| format!("cannot read the obligation: {}", err))?
+    else {
+        println!(
+            "slot:{}, no obligation in lending market:{}",
+            slot, lending_market
+        );
+        return Ok(());
+    };
+    let mut reserves = try_get_reserves(rpc_client, save_program, lending_market)
+        .map_err(|err| format!("cannot read the reserves: {}", err))?;
+    if let Err(err) = refresh_market_prices(rpc_client, &mut reserves, &obligation.reserve_keys()) {
+        // the program cannot refresh the obligation either, so nothing can be repaid
+        println!("slot:{}, cannot value the obligation: {}", slot, err);
+        return Ok(());
+    }
+    let reserves: HashMap<Pubkey, Reserve> = reserves.into_iter().collect();
+    let health = compute_health(&obligation, &reserves, slot);
+    let ltv = health.loan_to_value();
+    println!(
+        "slot:{}, obligation:{}, ltv:{:.2}%, liquidation ltv:{:.2}%, collateral drop to liquidation:{:.2}%",
+        slot,
+        obligation_key,
+        ltv * 100.0,
+        health.liquidation_loan_to_value() * 100.0,
+        health.distance_to_liquidation() * 100.0
+    );
+    if ltv < config.warn_ltv {
+        return Ok(());
+    }
+    println!(
+        "warning: ltv {:.2}% crossed {:.2}%",
+        ltv * 100.0,
+        config.warn_ltv * 100.0
+    );
+    if !config.auto_repay {
+        return Ok(());
+    }
+    let mut excess_value =
+        health.weighted_borrowed_value - config.target_ltv * health.deposited_value;
+    let mut borrows: Vec<&BorrowValue> = health.borrows.iter().collect();
+    borrows.sort_by(|a, b| b.weighted_value.total_cmp(&a.weighted_value));
+    for borrow in borrows {
+        if excess_value <= 0.0 {
+            break;
+        }
+        excess_value -= repay_excess(
+            client,
+            rpc_client,
+            payer,
+            raydium_v3_program,
+            save_program,
+            lending_market,
+            &obligation_key,
+            borrow,
+            excess_value,
+            config.withdraw_lp,
+            slippage,
+            slot,
+            data_dir,
+        )?;
+    }
+    if excess_value > 0.0 {
+        println!(
+            "warning: {:.6} of borrows could not be repaid, ltv stays above {:.2}%",
+            excess_value,
+            config.target_ltv * 100.0
+        );
+    }
+    Ok(())
+}
+
+/// Checks the payer's obligation every `interval_slots` until interrupted, as `check_obligation`
+/// does. A check that fails is reported and the next one runs at the following interval.
+pub fn watch_obligation(
+    client: Rc<Client<Rc<Keypair>>>,
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    raydium_v3_program: &Pubkey,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    config: &WatchConfig,
+    slippage: f64,
+    data_dir: &Path,
+) {
+    assert!(
+        config.warn_ltv > config.target_ltv,
+        "the warning ltv must be above the ltv repayments bring the obligation back to"
+    );
+    loop {
+        match rpc_client.get_slot() {
+            Err(err) => println!("cannot read the slot: {}, retrying next interval", err),
+            Ok(slot) => {
+                if let Err(err) = check_obligation(
+                    &client,
+                    rpc_client,
+                    payer,
+                    raydium_v3_program,
+                    save_program,
+                    lending_market,
+                    config,
+                    slippage,
+                    slot,
+                    data_dir,
+                ) {
+                    println!("slot:{}, {}, retrying next interval", slot, err);
+                }
+            }
+        }
+        std::thread::sleep(SLOT_DURATION * config.interval_slots as u32);
+    }
+}
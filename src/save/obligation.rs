@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::pubkey::Pubkey;
+
+use super::{
+    oracle::refresh_market_prices,
+    state::{
+        Obligation, Reserve, WAD, get_obligations, get_reserves, obligation_address,
+        try_get_obligations,
+    },
+};
+
+/// cTokens deposited as collateral and what they are worth.
+pub struct CollateralValue {
+    pub reserve: Pubkey,
+    pub mint: Pubkey,
+    pub decimals: u8,
+    pub collateral_amount: u64,
+    /// Liquidity the cTokens redeem for
+    pub liquidity_amount: u64,
+    pub value: f64,
+    pub loan_to_value_ratio: f64,
+    pub liquidation_threshold: f64,
+}
+
+/// Liquidity owed to a reserve, interest included, and what it is worth.
+pub struct BorrowValue {
+    pub reserve: Pubkey,
+    pub mint: Pubkey,
+    pub decimals: u8,
+    /// Owed amount in raw units, with the fraction interest has accrued
+    pub amount: f64,
+    pub value: f64,
+    /// Value scaled by the reserve's borrow weight, as counted against the limits
+    pub weighted_value: f64,
+}
+
+/// An obligation valued from current reserve state rather than its last refresh. Values are in
+/// the lending market's quote currency.
+#[derive(Default)]
+pub struct ObligationHealth {
+    pub collaterals: Vec<CollateralValue>,
+    pub borrows: Vec<BorrowValue>,
+    pub deposited_value: f64,
+    pub borrowed_value: f64,
+    pub weighted_borrowed_value: f64,
+    /// Weighted borrows valued at the higher of the market and smoothed prices, as the borrow
+    /// limit counts them
+    pub weighted_borrowed_value_upper_bound: f64,
+    /// Weighted borrows allowed by the collaterals' LTVs, the collaterals valued at the lower of
+    /// the market and smoothed prices
+    pub allowed_borrow_value: f64,
+    /// Weighted borrows past which the obligation can be liquidated
+    pub unhealthy_borrow_value: f64,
+}
+
+impl ObligationHealth {
+    /// Weighted borrows over deposits.
+    pub fn loan_to_value(&self) -> f64 {
+        if self.deposited_value == 0.0 {
+            return 0.0;
+        }
+        self.weighted_borrowed_value / self.deposited_value
+    }
+
+    /// Loan to value at which the obligation can be liquidated, averaged over the collaterals.
+    pub fn liquidation_loan_to_value(&self) -> f64 {
+        if self.deposited_value == 0.0 {
+            return 0.0;
+        }
+        self.unhealthy_borrow_value / self.deposited_value
+    }
+
+    /// Adds `collateral_amount` cTokens of `reserve` to the deposits, valued at its exchange rate
+    /// and market price.
+    pub fn push_collateral(
+        &mut self,
+        reserve_key: Pubkey,
+        reserve: &Reserve,
+        collateral_amount: u64,
+    ) {
+        let liquidity_amount = reserve.collateral_to_liquidity(collateral_amount);
+        let whole_amount =
+            liquidity_amount as f64 / 10f64.powi(reserve.liquidity_mint_decimals as i32);
+        let value = whole_amount * reserve.market_price();
+        let loan_to_value_ratio = reserve.config.loan_to_value_ratio as f64 / 100.0;
+        let liquidation_threshold = reserve.config.liquidation_threshold as f64 / 100.0;
+        self.deposited_value += value;
+        self.allowed_borrow_value +=
+            whole_amount * reserve.market_price_lower_bound() * loan_to_value_ratio;
+        self.unhealthy_borrow_value += value * liquidation_threshold;
+        self.collaterals.push(CollateralValue {
+            reserve: reserve_key,
+            mint: reserve.liquidity_mint,
+            decimals: reserve.liquidity_mint_decimals,
+            collateral_amount,
+            liquidity_amount,
+            value,
+            loan_to_value_ratio,
+            liquidation_threshold,
+        });
+    }
+
+    /// Share the collateral value can drop, with borrows unchanged, before liquidation.
+    pub fn distance_to_liquidation(&self) -> f64 {
+        if self.unhealthy_borrow_value == 0.0 {
+            return 0.0;
+        }
+        1.0 - self.weighted_borrowed_value / self.unhealthy_borrow_value
+    }
+}
+
+/// The obligation of `owner` in the lending market, preferring the one at the address the
+/// official clients use when there are several.
+pub fn find_obligation(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+) -> Option<(Pubkey, Obligation)> {
+    try_find_obligation(rpc_client, save_program, lending_market, owner).unwrap()
+}
+
+/// Like `find_obligation`, but hands a failed read back to the caller.
+pub fn try_find_obligation(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+) -> Result<Option<(Pubkey, Obligation)>, ClientError> {
+    let mut obligations = try_get_obligations(rpc_client, save_program, lending_market, owner)?;
+    let default_address = obligation_address(save_program, lending_market, owner);
+    Ok(
+        match obligations
+            .iter()
+            .position(|(key, _)| *key == default_address)
+        {
+            Some(index) => Some(obligations.swap_remove(index)),
+            None => obligations.into_iter().next(),
+        },
+    )
+}
+
+/// cTokens of `reserve_key` deposited in the obligation at `obligation_key`, none when the
+/// obligation does not exist yet.
+pub fn get_deposited_collateral(
+    rpc_client: &RpcClient,
+    obligation_key: &Pubkey,
+    reserve_key: &Pubkey,
+) -> u64 {
+    rpc_client
+        .get_account_with_commitment(obligation_key, rpc_client.commitment())
+        .unwrap()
+        .value
+        .map_or(0, |account| {
+            Obligation::unpack(&account.data)
+                .deposits
+                .iter()
+                .find(|deposit| deposit.deposit_reserve == *reserve_key)
+                .map_or(0, |deposit| deposit.deposited_amount)
+        })
+}
+
+/// Values the obligation's deposits and borrows at the reserves' market prices and exchange
+/// rates, accruing borrow interest up to `slot`. The market prices are those of the last refresh
+/// unless `refresh_market_prices` has set them from the oracles.
+pub fn compute_health(
+    obligation: &Obligation,
+    reserves: &HashMap<Pubkey, Reserve>,
+    slot: u64,
+) -> ObligationHealth {
+    let mut health = ObligationHealth::default();
+    for deposit in &obligation.deposits {
+        let reserve = reserves
+            .get(&deposit.deposit_reserve)
+            .expect("deposit reserve not in the lending market");
+        health.push_collateral(deposit.deposit_reserve, reserve, deposit.deposited_amount);
+    }
+    for borrow in &obligation.borrows {
+        let reserve = reserves
+            .get(&borrow.borrow_reserve)
+            .expect("borrow reserve not in the lending market");
+        // the debt grows with the reserve's cumulative rate since the borrow was last accrued
+        let rate_growth = reserve.accrued_cumulative_borrow_rate(slot)
+            / (borrow.cumulative_borrow_rate_wads as f64 / WAD as f64);
+        let amount = borrow.borrowed_amount_wads as f64 / WAD as f64 * rate_growth;
+        let whole_amount = amount / 10f64.powi(reserve.liquidity_mint_decimals as i32);
+        let borrow_weight = 1.0 + reserve.config.added_borrow_weight_bps as f64 / 10_000.0;
+        let value = whole_amount * reserve.market_price();
+        let weighted_value = value * borrow_weight;
+        health.borrowed_value += value;
+        health.weighted_borrowed_value += weighted_value;
+        health.weighted_borrowed_value_upper_bound +=
+            whole_amount * reserve.market_price_upper_bound() * borrow_weight;
+        health.borrows.push(BorrowValue {
+            reserve: borrow.borrow_reserve,
+            mint: reserve.liquidity_mint,
+            decimals: reserve.liquidity_mint_decimals,
+            amount,
+            value,
+            weighted_value,
+        });
+    }
+    health
+}
+
+/// Prints every obligation of `owner` in the lending market with its collaterals, borrows, LTV,
+/// liquidation threshold and distance to liquidation, valued at the reserves' oracle prices.
+pub fn print_obligations(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+) {
+    let obligations = get_obligations(rpc_client, save_program, lending_market, owner);
+    if obligations.is_empty() {
+        println!("no obligations in lending market:{}", lending_market);
+        return;
+    }
+    let mut reserves = get_reserves(rpc_client, save_program, lending_market);
+    let required: Vec<Pubkey> = obligations
+        .iter()
+        .flat_map(|(_, obligation)| obligation.reserve_keys())
+        .collect();
+    if let Err(err) = refresh_market_prices(rpc_client, &mut reserves, &required) {
+        println!("cannot value the obligations: {}", err);
+        return;
+    }
+    let reserves: HashMap<Pubkey, Reserve> = reserves.into_iter().collect();
+    let slot = rpc_client.get_slot().unwrap();
+    for (obligation_key, obligation) in &obligations {
+        let health = compute_health(obligation, &reserves, slot);
+        println!("obligation:{}", obligation_key);
+        for collateral in &health.collaterals {
+            println!(
+                "  collateral mint:{}, ctokens:{}, amount:{}, value:{:.6}, ltv:{}%, liquidation threshold:{}%",
+                collateral.mint,
+                collateral.collateral_amount,
+                collateral.liquidity_amount as f64 / 10f64.powi(collateral.decimals as i32),
+                collateral.value,
+                collateral.loan_to_value_ratio * 100.0,
+                collateral.liquidation_threshold * 100.0
+            );
+        }
+        for borrow in &health.borrows {
+            println!(
+                "  borrow mint:{}, amount:{}, value:{:.6}, weighted value:{:.6}",
+                borrow.mint,
+                borrow.amount / 10f64.powi(borrow.decimals as i32),
+                borrow.value,
+                borrow.weighted_value
+            );
+        }
+        println!(
+            "  deposited:{:.6}, borrowed:{:.6}, weighted borrowed:{:.6}, against borrow limit:{:.6}, borrow limit:{:.6}, liquidation at:{:.6}",
+            health.deposited_value,
+            health.borrowed_value,
+            health.weighted_borrowed_value,
+            health.weighted_borrowed_value_upper_bound,
+            health.allowed_borrow_value,
+            health.unhealthy_borrow_value
+        );
+        println!(
+            "  ltv:{:.2}%, liquidation ltv:{:.2}%, collateral drop to liquidation:{:.2}%",
+            health.loan_to_value() * 100.0,
+            health.liquidation_loan_to_value() * 100.0,
+            health.distance_to_liquidation() * 100.0
+        );
+    }
+}
@@ -0,0 +1,451 @@
+//! Pyth and Switchboard price accounts referenced by reserves, decoded by hand like the reserves
+//! themselves according to the program owning them, and held to RefreshReserve's price checks.
+
+use std::{collections::HashMap, str::FromStr};
+
+use arrayref::array_ref;
+use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use solana_sdk::{account::Account, pubkey::Pubkey};
+
+use super::state::{Reserve, WAD};
+use crate::raydium::utils::try_get_chain_timestamp;
+
+/// Slots after which the program rejects an oracle price.
+pub const STALE_AFTER_SLOTS: u64 = 240;
+/// Seconds after which the program rejects a Pyth pull price, which carries no publish slot.
+pub const STALE_AFTER_SECONDS: i64 = 120;
+/// A Pyth confidence interval must stay within this fraction of the price.
+const CONFIDENCE_RATIO: f64 = 10.0;
+/// Placeholder the program stores for a missing oracle.
+const NULL_ORACLE: &str = "nu11111111111111111111111111111111111111111";
+const PYTH_PROGRAM: &str = "FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH";
+const PYTH_RECEIVER_PROGRAM: &str = "rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ";
+const SWITCHBOARD_V2_PROGRAM: &str = "SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f";
+const SWITCHBOARD_ON_DEMAND_PROGRAM: &str = "SBondMDrcV3K4kxZR1HNVT7osZxAHVHgYXL5Ze1oMUv";
+const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+const PYTH_PRICE_ACCOUNT_TYPE: u32 = 3;
+const PYTH_STATUS_TRADING: u32 = 1;
+const PYTH_PRICE_LEN: usize = 240;
+/// Anchor discriminator of Pyth receiver `PriceUpdateV2` accounts.
+const PYTH_PRICE_UPDATE_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+/// `VerificationLevel::Full` of a price update, whose variant carries no data.
+const PYTH_VERIFICATION_FULL: u8 = 1;
+/// Anchor discriminator of Switchboard v2 aggregator accounts.
+const SWITCHBOARD_AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+const SWITCHBOARD_AGGREGATOR_LEN: usize = 406;
+/// Anchor discriminator of Switchboard on-demand `PullFeedAccountData` accounts.
+const SWITCHBOARD_PULL_FEED_DISCRIMINATOR: [u8; 8] = [196, 27, 108, 196, 10, 215, 219, 40];
+/// Offset of the feed's current result, after the discriminator, 32 submissions and the feed's
+/// settings.
+const SWITCHBOARD_PULL_FEED_RESULT_OFFSET: usize = 2264;
+const SWITCHBOARD_PULL_FEED_LEN: usize = SWITCHBOARD_PULL_FEED_RESULT_OFFSET + 128;
+/// Decimals of the fixed point values of on-demand feeds.
+const SWITCHBOARD_PULL_FEED_DECIMALS: i32 = 18;
+
+/// Which kind of oracle a price comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleKind {
+    Pyth,
+    PythPull,
+    Switchboard,
+    SwitchboardOnDemand,
+}
+
+/// A price read from an oracle account, in the quote currency per whole token.
+#[derive(Clone, Copy, Debug)]
+pub struct OraclePrice {
+    pub kind: OracleKind,
+    pub oracle: Pubkey,
+    pub price: f64,
+    /// Pyth EMA price, which the program keeps as the reserve's smoothed price
+    pub smoothed_price: Option<f64>,
+    /// Pyth confidence interval, or standard deviation of the Switchboard responses
+    pub confidence: f64,
+    /// Slot the price was published or posted at, or its Switchboard round opened at
+    pub slot: u64,
+    /// Unix time a Pyth pull price was published at
+    pub publish_time: Option<i64>,
+}
+
+impl OraclePrice {
+    /// Checks that the price is positive and not stale at `current_slot`, or for a Pyth pull
+    /// price at `current_timestamp`, and that a Pyth price is known within a tenth of itself.
+    /// Switchboard's standard deviation is not checked, as the program does not check it.
+    pub fn validate(&self, current_slot: u64, current_timestamp: i64) -> Result<(), String> {
+        if self.price <= 0.0 {
+            return Err(format!(
+                "{:?} oracle:{} price {} is not positive",
+                self.kind, self.oracle, self.price
+            ));
+        }
+        match self.publish_time {
+            Some(publish_time) => {
+                let age = current_timestamp.saturating_sub(publish_time);
+                if age > STALE_AFTER_SECONDS {
+                    return Err(format!(
+                        "{:?} oracle:{} is stale, last published {}s ago, limit {}s",
+                        self.kind, self.oracle, age, STALE_AFTER_SECONDS
+                    ));
+                }
+            }
+            None => {
+                let age = current_slot.saturating_sub(self.slot);
+                if age > STALE_AFTER_SLOTS {
+                    return Err(format!(
+                        "{:?} oracle:{} is stale, last updated {} slots ago at slot {}, limit {}",
+                        self.kind, self.oracle, age, self.slot, STALE_AFTER_SLOTS
+                    ));
+                }
+            }
+        }
+        if matches!(self.kind, OracleKind::Pyth | OracleKind::PythPull)
+            && self.confidence * CONFIDENCE_RATIO > self.price
+        {
+            return Err(format!(
+                "{:?} oracle:{} confidence {} is too wide for price {}",
+                self.kind, self.oracle, self.confidence, self.price
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn is_null_oracle(oracle: &Pubkey) -> bool {
+    *oracle == Pubkey::default() || *oracle == Pubkey::from_str(NULL_ORACLE).unwrap()
+}
+
+/// Decodes the aggregate price of a Pyth v2 price account.
+pub fn decode_pyth(oracle: Pubkey, data: &[u8]) -> Result<OraclePrice, String> {
+    if data.len() < PYTH_PRICE_LEN
+        || u32::from_le_bytes(*array_ref![data, 0, 4]) != PYTH_MAGIC
+        || u32::from_le_bytes(*array_ref![data, 8, 4]) != PYTH_PRICE_ACCOUNT_TYPE
+    {
+        return Err(format!("oracle:{} is not a pyth price account", oracle));
+    }
+    let expo = i32::from_le_bytes(*array_ref![data, 20, 4]);
+    let ema_price = i64::from_le_bytes(*array_ref![data, 48, 8]);
+    let price = i64::from_le_bytes(*array_ref![data, 208, 8]);
+    let conf = u64::from_le_bytes(*array_ref![data, 216, 8]);
+    let status = u32::from_le_bytes(*array_ref![data, 224, 4]);
+    let pub_slot = u64::from_le_bytes(*array_ref![data, 232, 8]);
+    if status != PYTH_STATUS_TRADING {
+        return Err(format!("pyth oracle:{} is not trading", oracle));
+    }
+    let scale = 10f64.powi(expo);
+    Ok(OraclePrice {
+        kind: OracleKind::Pyth,
+        oracle,
+        price: price as f64 * scale,
+        smoothed_price: Some(ema_price as f64 * scale),
+        confidence: conf as f64 * scale,
+        slot: pub_slot,
+        publish_time: None,
+    })
+}
+
+/// Decodes the price message of a Pyth receiver `PriceUpdateV2` account, which must be fully
+/// verified.
+pub fn decode_pyth_pull(oracle: Pubkey, data: &[u8]) -> Result<OraclePrice, String> {
+    if data.len() < 41 || *array_ref![data, 0, 8] != PYTH_PRICE_UPDATE_DISCRIMINATOR {
+        return Err(format!("oracle:{} is not a pyth price update", oracle));
+    }
+    // the verification level follows the write authority, `Partial` with its signature count
+    if data[40] != PYTH_VERIFICATION_FULL {
+        return Err(format!("pyth oracle:{} is only partially verified", oracle));
+    }
+    // the price message follows, after its 32 byte feed id, then the posted slot
+    let message = 41 + 32;
+    if data.len() < message + 60 {
+        return Err(format!("oracle:{} is not a pyth price update", oracle));
+    }
+    let price = i64::from_le_bytes(*array_ref![data, message, 8]);
+    let conf = u64::from_le_bytes(*array_ref![data, message + 8, 8]);
+    let expo = i32::from_le_bytes(*array_ref![data, message + 16, 4]);
+    let publish_time = i64::from_le_bytes(*array_ref![data, message + 20, 8]);
+    let ema_price = i64::from_le_bytes(*array_ref![data, message + 36, 8]);
+    let posted_slot = u64::from_le_bytes(*array_ref![data, message + 52, 8]);
+    let scale = 10f64.powi(expo);
+    Ok(OraclePrice {
+        kind: OracleKind::PythPull,
+        oracle,
+        price: price as f64 * scale,
+        smoothed_price: Some(ema_price as f64 * scale),
+        confidence: conf as f64 * scale,
+        slot: posted_slot,
+        publish_time: Some(publish_time),
+    })
+}
+
+/// Decodes the latest confirmed round of a Switchboard v2 aggregator.
+pub fn decode_switchboard(oracle: Pubkey, data: &[u8]) -> Result<OraclePrice, String> {
+    if data.len() < SWITCHBOARD_AGGREGATOR_LEN
+        || *array_ref![data, 0, 8] != SWITCHBOARD_AGGREGATOR_DISCRIMINATOR
+    {
+        return Err(format!("oracle:{} is not a switchboard aggregator", oracle));
+    }
+    // SwitchboardDecimal is an i128 mantissa over 10^scale
+    let decimal = |offset: usize| {
+        let mantissa = i128::from_le_bytes(*array_ref![data, offset, 16]);
+        let scale = u32::from_le_bytes(*array_ref![data, offset + 16, 4]);
+        mantissa as f64 / 10f64.powi(scale as i32)
+    };
+    let round_open_slot = u64::from_le_bytes(*array_ref![data, 350, 8]);
+    Ok(OraclePrice {
+        kind: OracleKind::Switchboard,
+        oracle,
+        price: decimal(366),
+        smoothed_price: None,
+        confidence: decimal(386),
+        slot: round_open_slot,
+        publish_time: None,
+    })
+}
+
+/// Decodes the current result of a Switchboard on-demand pull feed.
+pub fn decode_switchboard_on_demand(oracle: Pubkey, data: &[u8]) -> Result<OraclePrice, String> {
+    if data.len() < SWITCHBOARD_PULL_FEED_LEN
+        || *array_ref![data, 0, 8] != SWITCHBOARD_PULL_FEED_DISCRIMINATOR
+    {
+        return Err(format!("oracle:{} is not a switchboard pull feed", oracle));
+    }
+    let result = SWITCHBOARD_PULL_FEED_RESULT_OFFSET;
+    let decimal = |offset: usize| {
+        i128::from_le_bytes(*array_ref![data, offset, 16]) as f64
+            / 10f64.powi(SWITCHBOARD_PULL_FEED_DECIMALS)
+    };
+    let slot = u64::from_le_bytes(*array_ref![data, result + 104, 8]);
+    Ok(OraclePrice {
+        kind: OracleKind::SwitchboardOnDemand,
+        oracle,
+        price: decimal(result),
+        smoothed_price: None,
+        confidence: decimal(result + 16),
+        slot,
+        publish_time: None,
+    })
+}
+
+/// Decodes an oracle account with the decoder of the program owning it.
+pub fn decode_oracle(oracle: Pubkey, account: &Account) -> Result<OraclePrice, String> {
+    let owner = account.owner.to_string();
+    match owner.as_str() {
+        PYTH_PROGRAM => decode_pyth(oracle, &account.data),
+        PYTH_RECEIVER_PROGRAM => decode_pyth_pull(oracle, &account.data),
+        SWITCHBOARD_V2_PROGRAM => decode_switchboard(oracle, &account.data),
+        SWITCHBOARD_ON_DEMAND_PROGRAM => decode_switchboard_on_demand(oracle, &account.data),
+        _ => Err(format!(
+            "oracle:{} is owned by {}, not a known oracle program",
+            oracle, owner
+        )),
+    }
+}
+
+/// The reserve's validated price: the price of its Pyth oracle field's account, or of its
+/// Switchboard field's when the first is missing or unusable, as RefreshReserve picks them.
+/// `accounts` holds the oracle accounts.
+pub fn reserve_oracle_price(
+    reserve: &Reserve,
+    accounts: &HashMap<Pubkey, Account>,
+    current_slot: u64,
+    current_timestamp: i64,
+) -> Result<OraclePrice, String> {
+    let price_of = |oracle: &Pubkey| {
+        (!is_null_oracle(oracle)).then(|| {
+            accounts
+                .get(oracle)
+                .ok_or_else(|| format!("oracle:{} not found", oracle))
+                .and_then(|account| decode_oracle(*oracle, account))
+                .and_then(|price| {
+                    price
+                        .validate(current_slot, current_timestamp)
+                        .map(|_| price)
+                })
+        })
+    };
+    let pyth = price_of(&reserve.pyth_oracle);
+    if let Some(Ok(price)) = pyth {
+        return Ok(price);
+    }
+    let switchboard = price_of(&reserve.switchboard_oracle);
+    match (pyth, switchboard) {
+        (_, Some(Ok(price))) => Ok(price),
+        (Some(Err(pyth_err)), Some(Err(switchboard_err))) => {
+            Err(format!("{}; {}", pyth_err, switchboard_err))
+        }
+        (Some(Err(err)), None) | (None, Some(Err(err))) => Err(err),
+        _ => Err(format!(
+            "reserve of mint:{} has no oracle",
+            reserve.liquidity_mint
+        )),
+    }
+}
+
+/// Loads the oracles of `reserves` and returns each reserve's validated price, or why it cannot
+/// be used, in the same order.
+pub fn get_oracle_prices(
+    rpc_client: &RpcClient,
+    reserves: &[(Pubkey, Reserve)],
+) -> Vec<Result<OraclePrice, String>> {
+    try_get_oracle_prices(rpc_client, reserves).unwrap()
+}
+
+/// Like `get_oracle_prices`, but hands a failed read back to the caller.
+pub fn try_get_oracle_prices(
+    rpc_client: &RpcClient,
+    reserves: &[(Pubkey, Reserve)],
+) -> Result<Vec<Result<OraclePrice, String>>, ClientError> {
+    let mut oracles: Vec<Pubkey> = reserves
+        .iter()
+        .flat_map(|(_, reserve)| [reserve.pyth_oracle, reserve.switchboard_oracle])
+        .filter(|oracle| !is_null_oracle(oracle))
+        .collect();
+    oracles.sort();
+    oracles.dedup();
+    let mut accounts = HashMap::new();
+    for chunk in oracles.chunks(100) {
+        let rsps = rpc_client.get_multiple_accounts(chunk)?;
+        for (oracle, rsp) in chunk.iter().zip(rsps) {
+            if let Some(account) = rsp {
+                accounts.insert(*oracle, account);
+            }
+        }
+    }
+    let current_slot = rpc_client.get_slot()?;
+    let current_timestamp = try_get_chain_timestamp(rpc_client)? as i64;
+    Ok(reserves
+        .iter()
+        .map(|(_, reserve)| {
+            reserve_oracle_price(reserve, &accounts, current_slot, current_timestamp)
+        })
+        .collect())
+}
+
+/// Sets the market price of every reserve with usable oracles to its validated oracle price, and
+/// the smoothed price to the Pyth EMA price, as refreshing the reserves would, so valuations do
+/// not depend on when each reserve was last refreshed. Fails when the oracles of a reserve in
+/// `required` are stale or unusable, or cannot be read.
+pub fn refresh_market_prices(
+    rpc_client: &RpcClient,
+    reserves: &mut [(Pubkey, Reserve)],
+    required: &[Pubkey],
+) -> Result<(), String> {
+    let prices = try_get_oracle_prices(rpc_client, reserves)
+        .map_err(|err| format!("cannot read the oracles: {}", err))?;
+    for ((reserve_key, reserve), price) in reserves.iter_mut().zip(prices) {
+        match price {
+            Ok(price) => {
+                reserve.market_price_wads = (price.price * WAD as f64) as u128;
+                if let Some(smoothed_price) = price.smoothed_price {
+                    reserve.smoothed_market_price_wads = (smoothed_price * WAD as f64) as u128;
+                }
+            }
+            Err(err) if required.contains(reserve_key) => {
+                return Err(format!("reserve:{}: {}", reserve_key, err));
+            }
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::save::state::tests::{account_data, assert_close};
+
+    fn account(owner: &str, data: Vec<u8>) -> Account {
+        Account {
+            lamports: 0,
+            data,
+            owner: Pubkey::from_str(owner).unwrap(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    /// A fully verified price update of 1.2345 with an EMA of 1.23, both at exponent -4.
+    fn pyth_price_update(verification_level: u8) -> Vec<u8> {
+        account_data(
+            133,
+            &[
+                (0, &PYTH_PRICE_UPDATE_DISCRIMINATOR[..]),
+                (40, &[verification_level][..]),
+                (73, &12_345i64.to_le_bytes()[..]),
+                (81, &5u64.to_le_bytes()[..]),
+                (89, &(-4i32).to_le_bytes()[..]),
+                (93, &1_700_000_000i64.to_le_bytes()[..]),
+                (109, &12_300i64.to_le_bytes()[..]),
+                (125, &250_000_000u64.to_le_bytes()[..]),
+            ],
+        )
+    }
+
+    #[test]
+    fn pyth_pull_price_update_layout() {
+        let oracle = Pubkey::new_unique();
+        let price = decode_oracle(
+            oracle,
+            &account(
+                PYTH_RECEIVER_PROGRAM,
+                pyth_price_update(PYTH_VERIFICATION_FULL),
+            ),
+        )
+        .unwrap();
+        assert_eq!(price.kind, OracleKind::PythPull);
+        assert_close(price.price, 1.2345);
+        assert_close(price.smoothed_price.unwrap(), 1.23);
+        assert_close(price.confidence, 0.0005);
+        assert_eq!(price.publish_time, Some(1_700_000_000));
+        assert_eq!(price.slot, 250_000_000);
+        // staleness is measured in seconds, whatever the slot
+        assert!(
+            price
+                .validate(u64::MAX, 1_700_000_000 + STALE_AFTER_SECONDS)
+                .is_ok()
+        );
+        assert!(
+            price
+                .validate(0, 1_700_000_001 + STALE_AFTER_SECONDS)
+                .is_err()
+        );
+        // a partially verified update carries its signature count
+        let mut data = pyth_price_update(0);
+        data.insert(41, 3);
+        assert!(decode_pyth_pull(oracle, &data).is_err());
+    }
+
+    #[test]
+    fn switchboard_on_demand_result_layout() {
+        let result = SWITCHBOARD_PULL_FEED_RESULT_OFFSET;
+        let data = account_data(
+            SWITCHBOARD_PULL_FEED_LEN,
+            &[
+                (0, &SWITCHBOARD_PULL_FEED_DISCRIMINATOR[..]),
+                (result, &(25 * 10i128.pow(17)).to_le_bytes()[..]),
+                // far wider than a tenth of the price, which only Pyth is held to
+                (result + 16, &10i128.pow(18).to_le_bytes()[..]),
+                (result + 104, &1_000u64.to_le_bytes()[..]),
+            ],
+        );
+        let price = decode_oracle(
+            Pubkey::new_unique(),
+            &account(SWITCHBOARD_ON_DEMAND_PROGRAM, data),
+        )
+        .unwrap();
+        assert_eq!(price.kind, OracleKind::SwitchboardOnDemand);
+        assert_close(price.price, 2.5);
+        assert_close(price.confidence, 1.0);
+        assert_eq!(price.slot, 1_000);
+        assert!(price.validate(1_000 + STALE_AFTER_SLOTS, 0).is_ok());
+        assert!(price.validate(1_001 + STALE_AFTER_SLOTS, 0).is_err());
+    }
+
+    #[test]
+    fn unknown_owner_is_rejected() {
+        let data = pyth_price_update(PYTH_VERIFICATION_FULL);
+        assert!(decode_oracle(Pubkey::new_unique(), &account(PYTH_PROGRAM, data)).is_err());
+        let owner = Pubkey::new_unique().to_string();
+        let data = pyth_price_update(PYTH_VERIFICATION_FULL);
+        assert!(decode_oracle(Pubkey::new_unique(), &account(&owner, data)).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Keeps idle wallet tokens earning in Save: depositing what closing positions leaves behind and
+//! withdrawing just enough back when opening one needs more than the wallet holds.
+
+use std::{path::Path, str::FromStr};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::raydium::utils::get_token_account_balances;
+
+use super::{
+    deposit::deposit,
+    state::get_reserves,
+    withdraw::{WithdrawAmount, withdraw},
+};
+
+/// Wallet balance of a mint kept out of Save, parsed from `MINT:AMOUNT`.
+#[derive(Clone, Copy, Debug)]
+pub struct WorkingReserve {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl FromStr for WorkingReserve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mint, amount) = s
+            .split_once(':')
+            .ok_or_else(|| "expected MINT:AMOUNT".to_string())?;
+        Ok(WorkingReserve {
+            mint: mint
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid mint: {}", mint))?,
+            amount: amount
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid amount: {}", amount))?,
+        })
+    }
+}
+
+/// Where tokens left in the wallet are deposited, and how much of them.
+#[derive(Clone, Debug)]
+pub struct ParkConfig {
+    pub save_program: Pubkey,
+    pub lending_market: Pubkey,
+    /// Mints whose whole balance above the amount is deposited; of the other mints only what was
+    /// received is deposited
+    pub working_reserves: Vec<WorkingReserve>,
+    /// Pool the parked tokens came out of, recorded on the deposits
+    pub pool: Option<Pubkey>,
+}
+
+/// Deposits the idle wallet tokens of `mints` into their reserves, keeping the cTokens in the
+/// wallet: the growth of each balance since `balances_before`, or everything above its working
+/// reserve. Mints without a reserve in the lending market stay in the wallet.
+pub fn park_idle_tokens(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    park: &ParkConfig,
+    mints: &[Pubkey],
+    balances_before: &[u64],
+    data_dir: &Path,
+) {
+    let reserves = get_reserves(rpc_client, &park.save_program, &park.lending_market);
+    let token_accounts: Vec<Pubkey> = mints
+        .iter()
+        .map(|mint| get_associated_token_address(&payer.pubkey(), mint))
+        .collect();
+    let balances = get_token_account_balances(rpc_client, &token_accounts);
+    for ((mint, balance_before), balance) in mints.iter().zip(balances_before).zip(balances) {
+        let amount = match park
+            .working_reserves
+            .iter()
+            .find(|working_reserve| working_reserve.mint == *mint)
+        {
+            Some(working_reserve) => balance.saturating_sub(working_reserve.amount),
+            None => balance.saturating_sub(*balance_before),
+        };
+        if amount == 0 {
+            continue;
+        }
+        if !reserves
+            .iter()
+            .any(|(_, reserve)| reserve.liquidity_mint == *mint)
+        {
+            println!(
+                "no reserve for mint:{}, keeping {} in the wallet",
+                mint, amount
+            );
+            continue;
+        }
+        println!("parking {} of mint:{} in save", amount, mint);
+        deposit(
+            rpc_client,
+            payer,
+            &park.save_program,
+            &park.lending_market,
+            mint,
+            amount,
+            false,
+            park.pool.as_ref(),
+            data_dir,
+        );
+    }
+}
+
+/// Redeems the payer's cTokens of `mint`'s reserve for whatever `token_account` lacks of
+/// `needed`. Returns the liquidity withdrawn.
+pub fn withdraw_shortfall(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    token_account: &Pubkey,
+    needed: u64,
+    data_dir: &Path,
+) -> u64 {
+    let balance = get_token_account_balances(rpc_client, &[*token_account])[0];
+    if balance >= needed {
+        return 0;
+    }
+    if !get_reserves(rpc_client, save_program, lending_market)
+        .iter()
+        .any(|(_, reserve)| reserve.liquidity_mint == *mint)
+    {
+        println!(
+            "wallet lacks {} of mint:{} and it has no reserve to withdraw from",
+            needed - balance,
+            mint
+        );
+        return 0;
+    }
+    println!(
+        "wallet lacks {} of mint:{}, withdrawing it from save",
+        needed - balance,
+        mint
+    );
+    // wallet cTokens are not collateral, so there is no LTV to respect
+    withdraw(
+        rpc_client,
+        payer,
+        save_program,
+        lending_market,
+        mint,
+        WithdrawAmount::Liquidity(needed - balance),
+        false,
+        0.0,
+        None,
+        data_dir,
+    )
+}
@@ -0,0 +1,85 @@
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use super::{
+    oracle::get_oracle_prices,
+    state::{WAD, get_reserves, rate_to_apy},
+};
+
+/// Prints every reserve of the lending market with its liquidity, rates, utilization, collateral
+/// exchange rate, oracle price and config.
+pub fn print_reserves(rpc_client: &RpcClient, save_program: &Pubkey, lending_market: &Pubkey) {
+    let mut reserves = get_reserves(rpc_client, save_program, lending_market);
+    if reserves.is_empty() {
+        println!("no reserves in lending market:{}", lending_market);
+        return;
+    }
+    reserves.sort_by_key(|(_, reserve)| reserve.liquidity_mint);
+    let current_slot = rpc_client.get_slot().unwrap();
+    let oracle_prices = get_oracle_prices(rpc_client, &reserves);
+    for ((reserve_key, reserve), oracle_price) in reserves.iter().zip(oracle_prices) {
+        let config = &reserve.config;
+        let scale = 10f64.powi(reserve.liquidity_mint_decimals as i32);
+        println!("reserve:{}", reserve_key);
+        println!(
+            "  liquidity mint:{}, decimals:{}, ctoken mint:{}",
+            reserve.liquidity_mint, reserve.liquidity_mint_decimals, reserve.collateral_mint
+        );
+        println!(
+            "  available:{}, borrowed:{}, market price:{}, last update slot:{} ({} slots ago{})",
+            reserve.available_amount as f64 / scale,
+            reserve.borrowed_amount_wads as f64 / WAD as f64 / scale,
+            reserve.market_price(),
+            reserve.last_update_slot,
+            current_slot.saturating_sub(reserve.last_update_slot),
+            if reserve.stale { ", stale" } else { "" }
+        );
+        match oracle_price {
+            Ok(oracle_price) => println!(
+                "  oracle price:{}, confidence:{}, from {:?} oracle:{} at slot {} ({} slots ago)",
+                oracle_price.price,
+                oracle_price.confidence,
+                oracle_price.kind,
+                oracle_price.oracle,
+                oracle_price.slot,
+                current_slot.saturating_sub(oracle_price.slot)
+            ),
+            Err(err) => println!("  oracle price unavailable: {}", err),
+        }
+        println!(
+            "  utilization:{:.2}%, supply apy:{:.4}%, borrow apy:{:.4}%",
+            reserve.utilization_rate() * 100.0,
+            rate_to_apy(reserve.supply_rate()) * 100.0,
+            rate_to_apy(reserve.borrow_rate()) * 100.0
+        );
+        println!(
+            "  cumulative borrow rate:{}, ctokens per token:{}, ctoken supply:{}",
+            reserve.cumulative_borrow_rate_wads as f64 / WAD as f64,
+            reserve.collateral_exchange_rate(),
+            reserve.collateral_mint_total_supply as f64 / scale
+        );
+        println!(
+            "  ltv:{}%, liquidation threshold:{}%, liquidation bonus:{}%, borrow weight bps:{}",
+            config.loan_to_value_ratio,
+            config.liquidation_threshold,
+            config.liquidation_bonus,
+            10_000 + config.added_borrow_weight_bps
+        );
+        println!(
+            "  borrow rate curve: {}% .. {}% at {}% .. {}% at {}% .. {}%",
+            config.min_borrow_rate,
+            config.optimal_borrow_rate,
+            config.optimal_utilization_rate,
+            config.max_borrow_rate,
+            config.max_utilization_rate,
+            config.super_max_borrow_rate
+        );
+        println!(
+            "  protocol take rate:{}%, borrow fee:{}%, deposit limit:{}, borrow limit:{}",
+            config.protocol_take_rate,
+            config.borrow_fee_wad as f64 / WAD as f64 * 100.0,
+            config.deposit_limit as f64 / scale,
+            config.borrow_limit as f64 / scale
+        );
+    }
+}
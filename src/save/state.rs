@@ -0,0 +1,755 @@
+//! Account layouts of the Save (formerly Solend) lending program, decoded by hand as the program
+//! crate is not a dependency.
+
+use arrayref::{array_ref, array_refs};
+use raydium_amm_v3::libraries::big_num::U256;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    client_error::ClientError,
+    rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::pubkey::Pubkey;
+
+/// Scale of the program's fixed point decimals.
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+/// Slots per year the program uses to accrue interest.
+pub const SLOTS_PER_YEAR: u64 = 63_072_000;
+/// Size of a reserve account.
+pub const RESERVE_LEN: usize = 619;
+/// Size of an obligation account.
+pub const OBLIGATION_LEN: usize = 1300;
+/// Most deposits and borrows an obligation holds together.
+pub const MAX_OBLIGATION_RESERVES: usize = 10;
+const OBLIGATION_COLLATERAL_LEN: usize = 88;
+const OBLIGATION_LIQUIDITY_LEN: usize = 112;
+/// Offset of the lending market in reserve and obligation accounts.
+const LENDING_MARKET_OFFSET: usize = 10;
+/// Offset of the owner in obligation accounts.
+const OBLIGATION_OWNER_OFFSET: usize = 42;
+
+/// Risk and fee parameters of a reserve. Rates are in percent unless named otherwise.
+#[derive(Clone, Copy, Debug)]
+pub struct ReserveConfig {
+    pub optimal_utilization_rate: u8,
+    pub max_utilization_rate: u8,
+    pub loan_to_value_ratio: u8,
+    pub liquidation_bonus: u8,
+    pub max_liquidation_bonus: u8,
+    pub liquidation_threshold: u8,
+    pub max_liquidation_threshold: u8,
+    pub min_borrow_rate: u8,
+    pub optimal_borrow_rate: u8,
+    pub max_borrow_rate: u8,
+    pub super_max_borrow_rate: u64,
+    pub borrow_fee_wad: u64,
+    pub flash_loan_fee_wad: u64,
+    pub host_fee_percentage: u8,
+    pub deposit_limit: u64,
+    pub borrow_limit: u64,
+    pub fee_receiver: Pubkey,
+    pub protocol_liquidation_fee: u8,
+    pub protocol_take_rate: u8,
+    pub added_borrow_weight_bps: u64,
+    pub extra_oracle: Pubkey,
+}
+
+/// A lending reserve: the liquidity supplied for one mint and the cTokens minted against it.
+#[derive(Clone, Copy, Debug)]
+pub struct Reserve {
+    pub version: u8,
+    pub last_update_slot: u64,
+    pub stale: bool,
+    pub lending_market: Pubkey,
+    pub liquidity_mint: Pubkey,
+    pub liquidity_mint_decimals: u8,
+    pub liquidity_supply: Pubkey,
+    pub pyth_oracle: Pubkey,
+    pub switchboard_oracle: Pubkey,
+    pub available_amount: u64,
+    pub borrowed_amount_wads: u128,
+    pub cumulative_borrow_rate_wads: u128,
+    pub accumulated_protocol_fees_wads: u128,
+    /// Price of one whole liquidity token in the market's quote currency, scaled by `WAD`
+    pub market_price_wads: u128,
+    pub smoothed_market_price_wads: u128,
+    pub collateral_mint: Pubkey,
+    pub collateral_mint_total_supply: u64,
+    pub collateral_supply: Pubkey,
+    pub config: ReserveConfig,
+}
+
+impl Reserve {
+    pub fn unpack(data: &[u8]) -> Reserve {
+        let input = array_ref![data, 0, RESERVE_LEN];
+        #[rustfmt::skip]
+        let (
+            version, last_update_slot, last_update_stale, lending_market,
+            liquidity_mint, liquidity_mint_decimals, liquidity_supply,
+            pyth_oracle, switchboard_oracle,
+            available_amount, borrowed_amount_wads, cumulative_borrow_rate_wads, market_price_wads,
+            collateral_mint, collateral_mint_total_supply, collateral_supply,
+            optimal_utilization_rate, loan_to_value_ratio, liquidation_bonus,
+            liquidation_threshold, min_borrow_rate, optimal_borrow_rate, max_borrow_rate,
+            borrow_fee_wad, flash_loan_fee_wad, host_fee_percentage,
+            deposit_limit, borrow_limit, fee_receiver,
+            protocol_liquidation_fee, protocol_take_rate, accumulated_protocol_fees_wads,
+            _rate_limiter, added_borrow_weight_bps, smoothed_market_price_wads,
+            _asset_type, max_utilization_rate, super_max_borrow_rate,
+            max_liquidation_bonus, max_liquidation_threshold, _scaled_price_offset_bps,
+            extra_oracle, _padding,
+        ) = array_refs![
+            input, 1, 8, 1, 32, 32, 1, 32, 32, 32, 8, 16, 16, 16, 32, 8, 32,
+            1, 1, 1, 1, 1, 1, 1, 8, 8, 1, 8, 8, 32, 1, 1, 16,
+            56, 8, 16, 1, 1, 8, 1, 1, 8, 32, 98
+        ];
+        Reserve {
+            version: version[0],
+            last_update_slot: u64::from_le_bytes(*last_update_slot),
+            stale: last_update_stale[0] != 0,
+            lending_market: Pubkey::new_from_array(*lending_market),
+            liquidity_mint: Pubkey::new_from_array(*liquidity_mint),
+            liquidity_mint_decimals: liquidity_mint_decimals[0],
+            liquidity_supply: Pubkey::new_from_array(*liquidity_supply),
+            pyth_oracle: Pubkey::new_from_array(*pyth_oracle),
+            switchboard_oracle: Pubkey::new_from_array(*switchboard_oracle),
+            available_amount: u64::from_le_bytes(*available_amount),
+            borrowed_amount_wads: u128::from_le_bytes(*borrowed_amount_wads),
+            cumulative_borrow_rate_wads: u128::from_le_bytes(*cumulative_borrow_rate_wads),
+            accumulated_protocol_fees_wads: u128::from_le_bytes(*accumulated_protocol_fees_wads),
+            market_price_wads: u128::from_le_bytes(*market_price_wads),
+            smoothed_market_price_wads: u128::from_le_bytes(*smoothed_market_price_wads),
+            collateral_mint: Pubkey::new_from_array(*collateral_mint),
+            collateral_mint_total_supply: u64::from_le_bytes(*collateral_mint_total_supply),
+            collateral_supply: Pubkey::new_from_array(*collateral_supply),
+            config: ReserveConfig {
+                optimal_utilization_rate: optimal_utilization_rate[0],
+                max_utilization_rate: max_utilization_rate[0],
+                loan_to_value_ratio: loan_to_value_ratio[0],
+                liquidation_bonus: liquidation_bonus[0],
+                max_liquidation_bonus: max_liquidation_bonus[0],
+                liquidation_threshold: liquidation_threshold[0],
+                max_liquidation_threshold: max_liquidation_threshold[0],
+                min_borrow_rate: min_borrow_rate[0],
+                optimal_borrow_rate: optimal_borrow_rate[0],
+                max_borrow_rate: max_borrow_rate[0],
+                super_max_borrow_rate: u64::from_le_bytes(*super_max_borrow_rate),
+                borrow_fee_wad: u64::from_le_bytes(*borrow_fee_wad),
+                flash_loan_fee_wad: u64::from_le_bytes(*flash_loan_fee_wad),
+                host_fee_percentage: host_fee_percentage[0],
+                deposit_limit: u64::from_le_bytes(*deposit_limit),
+                borrow_limit: u64::from_le_bytes(*borrow_limit),
+                fee_receiver: Pubkey::new_from_array(*fee_receiver),
+                protocol_liquidation_fee: protocol_liquidation_fee[0],
+                protocol_take_rate: protocol_take_rate[0],
+                added_borrow_weight_bps: u64::from_le_bytes(*added_borrow_weight_bps),
+                extra_oracle: Pubkey::new_from_array(*extra_oracle),
+            },
+        }
+    }
+
+    /// Liquidity owed to the cToken holders: available plus borrowed, less the protocol's fees.
+    pub fn total_liquidity_wads(&self) -> u128 {
+        (self.available_amount as u128 * WAD + self.borrowed_amount_wads)
+            .saturating_sub(self.accumulated_protocol_fees_wads)
+    }
+
+    /// Share of the liquidity that is borrowed.
+    pub fn utilization_rate(&self) -> f64 {
+        let total = self.available_amount as u128 * WAD + self.borrowed_amount_wads;
+        if total == 0 {
+            return 0.0;
+        }
+        self.borrowed_amount_wads as f64 / total as f64
+    }
+
+    /// cTokens minted per liquidity token scaled by `WAD`, truncated as the program does and 1
+    /// for an empty reserve.
+    pub fn collateral_exchange_rate_wads(&self) -> u128 {
+        let total_liquidity_wads = self.total_liquidity_wads();
+        if self.collateral_mint_total_supply == 0 || total_liquidity_wads == 0 {
+            return WAD;
+        }
+        (U256::from(self.collateral_mint_total_supply) * U256::from(WAD) * U256::from(WAD)
+            / U256::from(total_liquidity_wads))
+        .as_u128()
+    }
+
+    /// cTokens minted per liquidity token.
+    pub fn collateral_exchange_rate(&self) -> f64 {
+        self.collateral_exchange_rate_wads() as f64 / WAD as f64
+    }
+
+    /// cTokens minted for `liquidity_amount`, rounded down as the program does.
+    pub fn liquidity_to_collateral(&self, liquidity_amount: u64) -> u64 {
+        (U256::from(liquidity_amount) * U256::from(self.collateral_exchange_rate_wads())
+            / U256::from(WAD))
+        .as_u64()
+    }
+
+    /// cTokens that redeem for at least `liquidity_amount`, rounded up.
+    pub fn liquidity_to_collateral_ceil(&self, liquidity_amount: u64) -> u64 {
+        let wad = U256::from(WAD);
+        let collateral_wads =
+            U256::from(liquidity_amount) * U256::from(self.collateral_exchange_rate_wads());
+        ((collateral_wads + wad - 1) / wad).as_u64()
+    }
+
+    /// Liquidity redeemed for `collateral_amount` cTokens, rounded down as the program does.
+    pub fn collateral_to_liquidity(&self, collateral_amount: u64) -> u64 {
+        (U256::from(collateral_amount) * U256::from(WAD)
+            / U256::from(self.collateral_exchange_rate_wads()))
+        .as_u64()
+    }
+
+    /// Yearly borrow rate on the reserve's kinked curve: min to optimal rate up to the optimal
+    /// utilization, optimal to max rate up to the max utilization, then up to the super max rate.
+    pub fn borrow_rate(&self) -> f64 {
+        let config = &self.config;
+        let utilization = self.utilization_rate();
+        let optimal_utilization = config.optimal_utilization_rate as f64 / 100.0;
+        // reserves configured before the max utilization existed treat it as 100%
+        let max_utilization = match config.max_utilization_rate {
+            0 => 1.0,
+            rate => rate as f64 / 100.0,
+        };
+        let min_rate = config.min_borrow_rate as f64 / 100.0;
+        let optimal_rate = config.optimal_borrow_rate as f64 / 100.0;
+        let max_rate = config.max_borrow_rate as f64 / 100.0;
+        let super_max_rate = (config.super_max_borrow_rate as f64 / 100.0).max(max_rate);
+        if optimal_utilization >= 1.0 || utilization <= optimal_utilization {
+            let progress = if optimal_utilization == 0.0 {
+                0.0
+            } else {
+                (utilization / optimal_utilization).min(1.0)
+            };
+            min_rate + (optimal_rate - min_rate) * progress
+        } else if utilization <= max_utilization {
+            let progress =
+                (utilization - optimal_utilization) / (max_utilization - optimal_utilization);
+            optimal_rate + (max_rate - optimal_rate) * progress
+        } else {
+            let progress = (utilization - max_utilization) / (1.0 - max_utilization);
+            max_rate + (super_max_rate - max_rate) * progress
+        }
+    }
+
+    /// Yearly rate earned by suppliers: the borrow interest spread over all liquidity, less the
+    /// protocol's take.
+    pub fn supply_rate(&self) -> f64 {
+        self.borrow_rate()
+            * self.utilization_rate()
+            * (1.0 - self.config.protocol_take_rate as f64 / 100.0)
+    }
+
+    /// Price of one whole liquidity token in the market's quote currency.
+    pub fn market_price(&self) -> f64 {
+        self.market_price_wads as f64 / WAD as f64
+    }
+
+    /// Lower of the market and smoothed prices, at which the program counts deposits towards the
+    /// borrow limit. Reserves without a smoothed price use the market price.
+    pub fn market_price_lower_bound(&self) -> f64 {
+        match self.smoothed_market_price_wads {
+            0 => self.market_price(),
+            smoothed => self.market_price_wads.min(smoothed) as f64 / WAD as f64,
+        }
+    }
+
+    /// Higher of the market and smoothed prices, at which the program counts borrows against the
+    /// borrow limit.
+    pub fn market_price_upper_bound(&self) -> f64 {
+        match self.smoothed_market_price_wads {
+            0 => self.market_price(),
+            smoothed => self.market_price_wads.max(smoothed) as f64 / WAD as f64,
+        }
+    }
+
+    /// Cumulative borrow rate as of `slot`, compounding the current borrow rate over the slots
+    /// since the reserve was last refreshed.
+    pub fn accrued_cumulative_borrow_rate(&self, slot: u64) -> f64 {
+        let elapsed = slot.saturating_sub(self.last_update_slot);
+        self.cumulative_borrow_rate_wads as f64 / WAD as f64
+            * (1.0 + self.borrow_rate() / SLOTS_PER_YEAR as f64).powf(elapsed as f64)
+    }
+}
+
+/// cTokens of one reserve deposited as collateral.
+#[derive(Clone, Copy, Debug)]
+pub struct ObligationCollateral {
+    pub deposit_reserve: Pubkey,
+    pub deposited_amount: u64,
+    pub market_value_wads: u128,
+}
+
+/// Liquidity borrowed from one reserve, with the cumulative borrow rate it was last accrued at.
+#[derive(Clone, Copy, Debug)]
+pub struct ObligationLiquidity {
+    pub borrow_reserve: Pubkey,
+    pub cumulative_borrow_rate_wads: u128,
+    pub borrowed_amount_wads: u128,
+    pub market_value_wads: u128,
+}
+
+/// A borrower's position in a lending market. Values are in the market's quote currency as of
+/// the last refresh, scaled by `WAD`.
+#[derive(Clone, Debug)]
+pub struct Obligation {
+    pub last_update_slot: u64,
+    pub stale: bool,
+    pub lending_market: Pubkey,
+    pub owner: Pubkey,
+    pub deposited_value_wads: u128,
+    pub borrowed_value_wads: u128,
+    pub allowed_borrow_value_wads: u128,
+    pub unhealthy_borrow_value_wads: u128,
+    pub deposits: Vec<ObligationCollateral>,
+    pub borrows: Vec<ObligationLiquidity>,
+}
+
+impl Obligation {
+    pub fn unpack(data: &[u8]) -> Obligation {
+        let input = array_ref![data, 0, OBLIGATION_LEN];
+        #[rustfmt::skip]
+        let (
+            _version, last_update_slot, last_update_stale, lending_market, owner,
+            deposited_value, borrowed_value, allowed_borrow_value, unhealthy_borrow_value,
+            _borrowed_value_upper_bound, _borrowing_isolated_asset, _super_unhealthy_borrow_value,
+            _unweighted_borrowed_value, _closeable, _padding,
+            deposits_len, borrows_len, data_flat,
+        ) = array_refs![
+            input, 1, 8, 1, 32, 32, 16, 16, 16, 16, 16, 1, 16, 16, 1, 14, 1, 1,
+            OBLIGATION_COLLATERAL_LEN + OBLIGATION_LIQUIDITY_LEN * (MAX_OBLIGATION_RESERVES - 1)
+        ];
+        let deposits_len = deposits_len[0] as usize;
+        let borrows_len = borrows_len[0] as usize;
+        let mut offset = 0;
+        let mut deposits = Vec::with_capacity(deposits_len);
+        for _ in 0..deposits_len {
+            let collateral = array_ref![data_flat, offset, OBLIGATION_COLLATERAL_LEN];
+            let (deposit_reserve, deposited_amount, market_value, _) =
+                array_refs![collateral, 32, 8, 16, 32];
+            deposits.push(ObligationCollateral {
+                deposit_reserve: Pubkey::new_from_array(*deposit_reserve),
+                deposited_amount: u64::from_le_bytes(*deposited_amount),
+                market_value_wads: u128::from_le_bytes(*market_value),
+            });
+            offset += OBLIGATION_COLLATERAL_LEN;
+        }
+        let mut borrows = Vec::with_capacity(borrows_len);
+        for _ in 0..borrows_len {
+            let liquidity = array_ref![data_flat, offset, OBLIGATION_LIQUIDITY_LEN];
+            let (borrow_reserve, cumulative_borrow_rate, borrowed_amount, market_value, _) =
+                array_refs![liquidity, 32, 16, 16, 16, 32];
+            borrows.push(ObligationLiquidity {
+                borrow_reserve: Pubkey::new_from_array(*borrow_reserve),
+                cumulative_borrow_rate_wads: u128::from_le_bytes(*cumulative_borrow_rate),
+                borrowed_amount_wads: u128::from_le_bytes(*borrowed_amount),
+                market_value_wads: u128::from_le_bytes(*market_value),
+            });
+            offset += OBLIGATION_LIQUIDITY_LEN;
+        }
+        Obligation {
+            last_update_slot: u64::from_le_bytes(*last_update_slot),
+            stale: last_update_stale[0] != 0,
+            lending_market: Pubkey::new_from_array(*lending_market),
+            owner: Pubkey::new_from_array(*owner),
+            deposited_value_wads: u128::from_le_bytes(*deposited_value),
+            borrowed_value_wads: u128::from_le_bytes(*borrowed_value),
+            allowed_borrow_value_wads: u128::from_le_bytes(*allowed_borrow_value),
+            unhealthy_borrow_value_wads: u128::from_le_bytes(*unhealthy_borrow_value),
+            deposits,
+            borrows,
+        }
+    }
+    /// Reserves the obligation has deposits in, then those it borrows from.
+    pub fn reserve_keys(&self) -> Vec<Pubkey> {
+        self.deposits
+            .iter()
+            .map(|deposit| deposit.deposit_reserve)
+            .chain(self.borrows.iter().map(|borrow| borrow.borrow_reserve))
+            .collect()
+    }
+}
+
+/// Address of the obligation the official clients create for `owner`, seeded with the first 32
+/// characters of the lending market address.
+pub fn obligation_address(
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    Pubkey::create_with_seed(owner, &lending_market.to_string()[..32], save_program).unwrap()
+}
+
+/// A yearly rate compounded every slot, as interest accrues on the program.
+pub fn rate_to_apy(rate: f64) -> f64 {
+    (1.0 + rate / SLOTS_PER_YEAR as f64).powf(SLOTS_PER_YEAR as f64) - 1.0
+}
+
+/// Every reserve of `lending_market`, keyed by address.
+pub fn get_reserves(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+) -> Vec<(Pubkey, Reserve)> {
+    try_get_reserves(rpc_client, save_program, lending_market).unwrap()
+}
+
+/// Like `get_reserves`, but hands a failed read back to the caller.
+pub fn try_get_reserves(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+) -> Result<Vec<(Pubkey, Reserve)>, ClientError> {
+    let accounts = rpc_client.get_program_accounts_with_config(
+        save_program,
+        RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(RESERVE_LEN as u64),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    LENDING_MARKET_OFFSET,
+                    lending_market.as_ref(),
+                )),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        },
+    )?;
+    Ok(accounts
+        .into_iter()
+        .map(|(key, account)| (key, Reserve::unpack(&account.data)))
+        .collect())
+}
+
+/// The reserve of `lending_market` lending `liquidity_mint`.
+pub fn get_reserve_by_mint(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    liquidity_mint: &Pubkey,
+) -> (Pubkey, Reserve) {
+    get_reserves(rpc_client, save_program, lending_market)
+        .into_iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *liquidity_mint)
+        .expect("no reserve for the mint in the lending market")
+}
+
+/// Every obligation `owner` holds in `lending_market`, keyed by address.
+pub fn get_obligations(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+) -> Vec<(Pubkey, Obligation)> {
+    try_get_obligations(rpc_client, save_program, lending_market, owner).unwrap()
+}
+
+/// Like `get_obligations`, but hands a failed read back to the caller.
+pub fn try_get_obligations(
+    rpc_client: &RpcClient,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    owner: &Pubkey,
+) -> Result<Vec<(Pubkey, Obligation)>, ClientError> {
+    let accounts = rpc_client.get_program_accounts_with_config(
+        save_program,
+        RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(OBLIGATION_LEN as u64),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    LENDING_MARKET_OFFSET,
+                    lending_market.as_ref(),
+                )),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    OBLIGATION_OWNER_OFFSET,
+                    owner.as_ref(),
+                )),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        },
+    )?;
+    Ok(accounts
+        .into_iter()
+        .map(|(key, account)| (key, Obligation::unpack(&account.data)))
+        .collect())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A zeroed account of `len` bytes with each `(offset, bytes)` written at its offset.
+    pub(crate) fn account_data(len: usize, fields: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        for (offset, bytes) in fields {
+            data[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+        data
+    }
+
+    /// A reserve holding `available_amount` liquidity and `borrowed_amount` borrowed, with
+    /// `collateral_supply` cTokens minted against it.
+    fn reserve(available_amount: u64, borrowed_amount: u64, collateral_supply: u64) -> Reserve {
+        Reserve::unpack(&account_data(
+            RESERVE_LEN,
+            &[
+                (171, &available_amount.to_le_bytes()[..]),
+                (179, &(borrowed_amount as u128 * WAD).to_le_bytes()[..]),
+                (259, &collateral_supply.to_le_bytes()[..]),
+            ],
+        ))
+    }
+
+    /// A reserve at `utilization` percent on a curve kinked at 80% and 90% utilization.
+    fn kinked_reserve(utilization: u64, max_utilization_rate: u8) -> Reserve {
+        let mut reserve = reserve(100 - utilization, utilization, 0);
+        reserve.config.optimal_utilization_rate = 80;
+        reserve.config.max_utilization_rate = max_utilization_rate;
+        reserve.config.min_borrow_rate = 0;
+        reserve.config.optimal_borrow_rate = 8;
+        reserve.config.max_borrow_rate = 50;
+        reserve.config.super_max_borrow_rate = 300;
+        reserve
+    }
+
+    pub(crate) fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn reserve_layout_offsets() {
+        let lending_market = Pubkey::new_unique();
+        let liquidity_mint = Pubkey::new_unique();
+        let liquidity_supply = Pubkey::new_unique();
+        let pyth_oracle = Pubkey::new_unique();
+        let switchboard_oracle = Pubkey::new_unique();
+        let collateral_mint = Pubkey::new_unique();
+        let collateral_supply = Pubkey::new_unique();
+        let fee_receiver = Pubkey::new_unique();
+        let extra_oracle = Pubkey::new_unique();
+        // offsets of the program's `Reserve::pack`
+        let data = account_data(
+            RESERVE_LEN,
+            &[
+                (0, &[1][..]),
+                (1, &250_000_000u64.to_le_bytes()[..]),
+                (9, &[1][..]),
+                (10, lending_market.as_ref()),
+                (42, liquidity_mint.as_ref()),
+                (74, &[6][..]),
+                (75, liquidity_supply.as_ref()),
+                (107, pyth_oracle.as_ref()),
+                (139, switchboard_oracle.as_ref()),
+                (171, &1_000_000u64.to_le_bytes()[..]),
+                (179, &(3 * WAD).to_le_bytes()[..]),
+                (195, &(WAD + 7).to_le_bytes()[..]),
+                (211, &(2 * WAD).to_le_bytes()[..]),
+                (227, collateral_mint.as_ref()),
+                (259, &900_000u64.to_le_bytes()[..]),
+                (267, collateral_supply.as_ref()),
+                (299, &[80][..]),
+                (300, &[75][..]),
+                (301, &[5][..]),
+                (302, &[85][..]),
+                (303, &[1][..]),
+                (304, &[8][..]),
+                (305, &[50][..]),
+                (306, &11u64.to_le_bytes()[..]),
+                (314, &12u64.to_le_bytes()[..]),
+                (322, &[20][..]),
+                (323, &13u64.to_le_bytes()[..]),
+                (331, &14u64.to_le_bytes()[..]),
+                (339, fee_receiver.as_ref()),
+                (371, &[30][..]),
+                (372, &[10][..]),
+                (373, &(WAD / 2).to_le_bytes()[..]),
+                (445, &10_000u64.to_le_bytes()[..]),
+                (453, &(WAD * 3 / 2).to_le_bytes()[..]),
+                (470, &[95][..]),
+                (471, &300u64.to_le_bytes()[..]),
+                (479, &[7][..]),
+                (480, &[90][..]),
+                (489, extra_oracle.as_ref()),
+            ],
+        );
+        let reserve = Reserve::unpack(&data);
+        assert_eq!(reserve.version, 1);
+        assert_eq!(reserve.last_update_slot, 250_000_000);
+        assert!(reserve.stale);
+        assert_eq!(reserve.lending_market, lending_market);
+        assert_eq!(reserve.liquidity_mint, liquidity_mint);
+        assert_eq!(reserve.liquidity_mint_decimals, 6);
+        assert_eq!(reserve.liquidity_supply, liquidity_supply);
+        assert_eq!(reserve.pyth_oracle, pyth_oracle);
+        assert_eq!(reserve.switchboard_oracle, switchboard_oracle);
+        assert_eq!(reserve.available_amount, 1_000_000);
+        assert_eq!(reserve.borrowed_amount_wads, 3 * WAD);
+        assert_eq!(reserve.cumulative_borrow_rate_wads, WAD + 7);
+        assert_eq!(reserve.market_price_wads, 2 * WAD);
+        assert_eq!(reserve.collateral_mint, collateral_mint);
+        assert_eq!(reserve.collateral_mint_total_supply, 900_000);
+        assert_eq!(reserve.collateral_supply, collateral_supply);
+        assert_eq!(reserve.accumulated_protocol_fees_wads, WAD / 2);
+        assert_eq!(reserve.smoothed_market_price_wads, WAD * 3 / 2);
+        let config = reserve.config;
+        assert_eq!(config.optimal_utilization_rate, 80);
+        assert_eq!(config.loan_to_value_ratio, 75);
+        assert_eq!(config.liquidation_bonus, 5);
+        assert_eq!(config.liquidation_threshold, 85);
+        assert_eq!(config.min_borrow_rate, 1);
+        assert_eq!(config.optimal_borrow_rate, 8);
+        assert_eq!(config.max_borrow_rate, 50);
+        assert_eq!(config.borrow_fee_wad, 11);
+        assert_eq!(config.flash_loan_fee_wad, 12);
+        assert_eq!(config.host_fee_percentage, 20);
+        assert_eq!(config.deposit_limit, 13);
+        assert_eq!(config.borrow_limit, 14);
+        assert_eq!(config.fee_receiver, fee_receiver);
+        assert_eq!(config.protocol_liquidation_fee, 30);
+        assert_eq!(config.protocol_take_rate, 10);
+        assert_eq!(config.added_borrow_weight_bps, 10_000);
+        assert_eq!(config.max_utilization_rate, 95);
+        assert_eq!(config.super_max_borrow_rate, 300);
+        assert_eq!(config.max_liquidation_bonus, 7);
+        assert_eq!(config.max_liquidation_threshold, 90);
+        assert_eq!(config.extra_oracle, extra_oracle);
+        // the lending market filter of `get_reserves` reads the same bytes
+        assert_eq!(
+            &data[LENDING_MARKET_OFFSET..LENDING_MARKET_OFFSET + 32],
+            lending_market.as_ref()
+        );
+    }
+
+    #[test]
+    fn obligation_layout_offsets() {
+        let lending_market = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let deposit_reserve = Pubkey::new_unique();
+        let borrow_reserve = Pubkey::new_unique();
+        // offsets of the program's `Obligation::pack`, the borrows follow the deposits
+        let borrow_offset = 204 + OBLIGATION_COLLATERAL_LEN;
+        let data = account_data(
+            OBLIGATION_LEN,
+            &[
+                (0, &[1][..]),
+                (1, &250_000_000u64.to_le_bytes()[..]),
+                (9, &[1][..]),
+                (10, lending_market.as_ref()),
+                (42, owner.as_ref()),
+                (74, &(100 * WAD).to_le_bytes()[..]),
+                (90, &(40 * WAD).to_le_bytes()[..]),
+                (106, &(75 * WAD).to_le_bytes()[..]),
+                (122, &(85 * WAD).to_le_bytes()[..]),
+                (202, &[1][..]),
+                (203, &[1][..]),
+                (204, deposit_reserve.as_ref()),
+                (236, &5_000u64.to_le_bytes()[..]),
+                (244, &(100 * WAD).to_le_bytes()[..]),
+                (borrow_offset, borrow_reserve.as_ref()),
+                (borrow_offset + 32, &(WAD + 3).to_le_bytes()[..]),
+                (borrow_offset + 48, &(2_000 * WAD).to_le_bytes()[..]),
+                (borrow_offset + 64, &(40 * WAD).to_le_bytes()[..]),
+            ],
+        );
+        let obligation = Obligation::unpack(&data);
+        assert_eq!(obligation.last_update_slot, 250_000_000);
+        assert!(obligation.stale);
+        assert_eq!(obligation.lending_market, lending_market);
+        assert_eq!(obligation.owner, owner);
+        assert_eq!(obligation.deposited_value_wads, 100 * WAD);
+        assert_eq!(obligation.borrowed_value_wads, 40 * WAD);
+        assert_eq!(obligation.allowed_borrow_value_wads, 75 * WAD);
+        assert_eq!(obligation.unhealthy_borrow_value_wads, 85 * WAD);
+        assert_eq!(obligation.deposits.len(), 1);
+        assert_eq!(obligation.deposits[0].deposit_reserve, deposit_reserve);
+        assert_eq!(obligation.deposits[0].deposited_amount, 5_000);
+        assert_eq!(obligation.deposits[0].market_value_wads, 100 * WAD);
+        assert_eq!(obligation.borrows.len(), 1);
+        assert_eq!(obligation.borrows[0].borrow_reserve, borrow_reserve);
+        assert_eq!(obligation.borrows[0].cumulative_borrow_rate_wads, WAD + 3);
+        assert_eq!(obligation.borrows[0].borrowed_amount_wads, 2_000 * WAD);
+        assert_eq!(obligation.borrows[0].market_value_wads, 40 * WAD);
+        assert_eq!(
+            obligation.reserve_keys(),
+            vec![deposit_reserve, borrow_reserve]
+        );
+        // the owner filter of `get_obligations` reads the same bytes
+        assert_eq!(
+            &data[OBLIGATION_OWNER_OFFSET..OBLIGATION_OWNER_OFFSET + 32],
+            owner.as_ref()
+        );
+    }
+
+    #[test]
+    fn empty_reserve_exchange_rate_is_one() {
+        let reserve = reserve(0, 0, 0);
+        assert_eq!(reserve.collateral_exchange_rate_wads(), WAD);
+        assert_eq!(reserve.liquidity_to_collateral(123), 123);
+        assert_eq!(reserve.collateral_to_liquidity(123), 123);
+    }
+
+    #[test]
+    fn exchange_rate_rounding_directions() {
+        // 2 cTokens for 3 liquidity tokens, a rate that is not exact in wads
+        let reserve = reserve(3, 0, 2);
+        assert_eq!(reserve.collateral_exchange_rate_wads(), 2 * WAD / 3);
+        assert_eq!(reserve.liquidity_to_collateral(10), 6);
+        assert_eq!(reserve.liquidity_to_collateral_ceil(10), 7);
+        assert_eq!(reserve.collateral_to_liquidity(6), 9);
+        for liquidity_amount in [1, 2, 10, 999, 1_000_000_007] {
+            // minting never gives more cTokens than the liquidity is worth
+            assert!(
+                reserve.collateral_to_liquidity(reserve.liquidity_to_collateral(liquidity_amount))
+                    <= liquidity_amount
+            );
+            // the rounded up cTokens always redeem for at least the liquidity asked for
+            assert!(
+                reserve.collateral_to_liquidity(
+                    reserve.liquidity_to_collateral_ceil(liquidity_amount)
+                ) >= liquidity_amount
+            );
+        }
+    }
+
+    #[test]
+    fn price_bounds_fall_back_to_the_market_price() {
+        let mut reserve = reserve(0, 0, 0);
+        reserve.market_price_wads = 2 * WAD;
+        assert_close(reserve.market_price_lower_bound(), 2.0);
+        assert_close(reserve.market_price_upper_bound(), 2.0);
+        reserve.smoothed_market_price_wads = 3 * WAD;
+        assert_close(reserve.market_price_lower_bound(), 2.0);
+        assert_close(reserve.market_price_upper_bound(), 3.0);
+        reserve.smoothed_market_price_wads = WAD;
+        assert_close(reserve.market_price_lower_bound(), 1.0);
+        assert_close(reserve.market_price_upper_bound(), 2.0);
+    }
+
+    #[test]
+    fn borrow_rate_kinks() {
+        assert_close(kinked_reserve(0, 90).borrow_rate(), 0.0);
+        assert_close(kinked_reserve(40, 90).borrow_rate(), 0.04);
+        assert_close(kinked_reserve(80, 90).borrow_rate(), 0.08);
+        assert_close(kinked_reserve(85, 90).borrow_rate(), 0.29);
+        assert_close(kinked_reserve(90, 90).borrow_rate(), 0.5);
+        assert_close(kinked_reserve(95, 90).borrow_rate(), 1.75);
+        assert_close(kinked_reserve(100, 90).borrow_rate(), 3.0);
+        // without a max utilization the second segment runs to 100%
+        assert_close(kinked_reserve(90, 0).borrow_rate(), 0.29);
+        assert_close(kinked_reserve(100, 0).borrow_rate(), 0.5);
+    }
+}
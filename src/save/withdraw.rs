@@ -0,0 +1,200 @@
+use std::{collections::HashMap, path::Path};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
+};
+
+use crate::{
+    journal::{JournalEntry, MovementKind, Operation, append_journal_entry},
+    raydium::utils::get_token_account_balances,
+    send_txn,
+};
+
+use super::{
+    collateral::check_collateral_withdrawal,
+    instructions::{
+        redeem_reserve_collateral_instr, refresh_obligation_instrs, refresh_reserve_instr,
+        withdraw_obligation_collateral_and_redeem_reserve_collateral_instr,
+    },
+    obligation::{compute_health, find_obligation},
+    oracle::refresh_market_prices,
+    state::{Reserve, get_reserves},
+};
+
+/// How much to take out of a reserve.
+#[derive(Clone, Copy, Debug)]
+pub enum WithdrawAmount {
+    /// Underlying liquidity, converted to the cTokens redeeming for at least that much
+    Liquidity(u64),
+    /// Exact cToken amount
+    Collateral(u64),
+    /// Every cToken held
+    All,
+}
+
+/// Redeems cTokens of `mint`'s reserve from the wallet, or from the payer's obligation with
+/// `from_collateral`. When the reserve lacks available liquidity, redeems what it can and reports
+/// the shortfall. `pool` is the pool the tokens go to, recorded in the journal. Returns the
+/// liquidity received.
+pub fn withdraw(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    save_program: &Pubkey,
+    lending_market: &Pubkey,
+    mint: &Pubkey,
+    withdraw_amount: WithdrawAmount,
+    from_collateral: bool,
+    max_ltv: f64,
+    pool: Option<&Pubkey>,
+    data_dir: &Path,
+) -> u64 {
+    let mut reserves = get_reserves(rpc_client, save_program, lending_market);
+    let (reserve_key, reserve) = *reserves
+        .iter()
+        .find(|(_, reserve)| reserve.liquidity_mint == *mint)
+        .expect("no reserve for the mint in the lending market");
+    let user_collateral = get_associated_token_address(&payer.pubkey(), &reserve.collateral_mint);
+    let destination_liquidity = get_associated_token_address(&payer.pubkey(), mint);
+
+    let obligation = from_collateral.then(|| {
+        find_obligation(rpc_client, save_program, lending_market, &payer.pubkey())
+            .expect("no obligation in the lending market")
+    });
+    let held = match &obligation {
+        Some((_, obligation)) => obligation
+            .deposits
+            .iter()
+            .find(|deposit| deposit.deposit_reserve == reserve_key)
+            .map_or(0, |deposit| deposit.deposited_amount),
+        None => get_token_account_balances(rpc_client, &[user_collateral])[0],
+    };
+    let (requested_liquidity, requested_collateral) = match withdraw_amount {
+        WithdrawAmount::Liquidity(amount) => (amount, reserve.liquidity_to_collateral_ceil(amount)),
+        WithdrawAmount::Collateral(amount) => (reserve.collateral_to_liquidity(amount), amount),
+        WithdrawAmount::All => (reserve.collateral_to_liquidity(held), held),
+    };
+    if requested_collateral > held {
+        println!(
+            "{} ctokens needed but only {} held, withdrawing those",
+            requested_collateral, held
+        );
+    }
+    // the reserve can only pay out the liquidity that is not lent
+    let redeemable = reserve.liquidity_to_collateral(reserve.available_amount);
+    let collateral_amount = requested_collateral.min(held).min(redeemable);
+    if collateral_amount == 0 {
+        println!(
+            "nothing to withdraw: {} ctokens held, {} available in reserve:{}",
+            held, reserve.available_amount, reserve_key
+        );
+        return 0;
+    }
+
+    let mut instructions = vec![create_associated_token_account_idempotent(
+        &payer.pubkey(),
+        &payer.pubkey(),
+        mint,
+        &spl_token::id(),
+    )];
+    match &obligation {
+        Some((obligation_key, obligation)) => {
+            if let Err(err) =
+                refresh_market_prices(rpc_client, &mut reserves, &obligation.reserve_keys())
+            {
+                println!("refusing to withdraw: {}", err);
+                return 0;
+            }
+            let slot = rpc_client.get_slot().unwrap();
+            let reserve_map: HashMap<Pubkey, Reserve> = reserves.iter().cloned().collect();
+            let health = compute_health(obligation, &reserve_map, slot);
+            if let Err(err) = check_collateral_withdrawal(
+                &health,
+                &reserve_map[&reserve_key],
+                collateral_amount,
+                max_ltv,
+            ) {
+                println!("refusing to withdraw: {}", err);
+                return 0;
+            }
+            instructions.push(create_associated_token_account_idempotent(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &reserve.collateral_mint,
+                &spl_token::id(),
+            ));
+            instructions.extend(refresh_obligation_instrs(
+                save_program,
+                obligation_key,
+                obligation,
+                &reserves,
+                &[],
+            ));
+            instructions.push(
+                withdraw_obligation_collateral_and_redeem_reserve_collateral_instr(
+                    save_program,
+                    collateral_amount,
+                    &user_collateral,
+                    &destination_liquidity,
+                    &reserve_key,
+                    &reserve,
+                    obligation_key,
+                    &payer.pubkey(),
+                ),
+            );
+        }
+        None => {
+            instructions.push(refresh_reserve_instr(save_program, &reserve_key, &reserve));
+            instructions.push(redeem_reserve_collateral_instr(
+                save_program,
+                collateral_amount,
+                &user_collateral,
+                &destination_liquidity,
+                &reserve_key,
+                &reserve,
+                &payer.pubkey(),
+            ));
+        }
+    }
+
+    let balance_before = get_token_account_balances(rpc_client, &[destination_liquidity])[0];
+    let recent_hash = rpc_client.get_latest_blockhash().unwrap();
+    let txn = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_hash,
+    );
+    let signature = send_txn(rpc_client, &txn, true);
+    println!("{}", signature);
+    let withdrawn = get_token_account_balances(rpc_client, &[destination_liquidity])[0]
+        .saturating_sub(balance_before);
+    println!(
+        "withdrew {} of mint:{} for {} ctokens",
+        withdrawn, mint, collateral_amount
+    );
+    if collateral_amount < requested_collateral {
+        println!(
+            "shortfall:{} of the {} requested, {} ctokens held, {} liquidity was available",
+            requested_liquidity.saturating_sub(withdrawn),
+            requested_liquidity,
+            held,
+            reserve.available_amount
+        );
+    }
+
+    let mut entry = JournalEntry::new(rpc_client, &signature, Operation::Withdraw);
+    entry.pool = pool.map(|pool| pool.to_string());
+    entry.reserve = Some(reserve_key.to_string());
+    entry.obligation = obligation.map(|(obligation_key, _)| obligation_key.to_string());
+    entry.collateral_amount = Some(collateral_amount);
+    entry.push_movement(
+        mint,
+        MovementKind::Withdraw,
+        withdrawn,
+        reserve.liquidity_mint_decimals,
+    );
+    append_journal_entry(data_dir, &entry);
+    withdrawn
+}